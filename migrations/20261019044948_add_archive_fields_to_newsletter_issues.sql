@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues ADD COLUMN slug TEXT NULL UNIQUE;
+ALTER TABLE newsletter_issues ADD COLUMN subscribers_only BOOLEAN NOT NULL DEFAULT FALSE;
\ No newline at end of file
@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE newsletter_delivery_log (
+  newsletter_issue_id uuid NOT NULL
+    REFERENCES newsletter_issues (newsletter_issue_id),
+  subscriber_email TEXT NOT NULL,
+  outcome TEXT NOT NULL,
+  recorded_at timestamptz NOT NULL,
+  PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues ADD COLUMN author_id uuid NULL
+  REFERENCES users (user_id);
\ No newline at end of file
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues
+  ADD COLUMN delivery_status TEXT NOT NULL DEFAULT 'sending';
\ No newline at end of file
@@ -0,0 +1,6 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues ADD COLUMN track_opens BOOLEAN NOT NULL DEFAULT TRUE;
+
+ALTER TABLE newsletter_delivery_log ADD COLUMN tracking_token TEXT NULL UNIQUE;
+ALTER TABLE newsletter_delivery_log ADD COLUMN first_opened_at timestamptz NULL;
+ALTER TABLE newsletter_delivery_log ADD COLUMN open_count INTEGER NOT NULL DEFAULT 0;
\ No newline at end of file
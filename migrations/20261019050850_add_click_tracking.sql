@@ -0,0 +1,10 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues ADD COLUMN track_clicks BOOLEAN NOT NULL DEFAULT TRUE;
+
+CREATE TABLE newsletter_link_clicks (
+  newsletter_issue_id uuid NOT NULL
+    REFERENCES newsletter_issues (newsletter_issue_id),
+  subscriber_email TEXT NOT NULL,
+  url TEXT NOT NULL,
+  clicked_at timestamptz NOT NULL
+);
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE newsletter_delivery_log ADD COLUMN unsubscribed_at timestamptz NULL;
@@ -0,0 +1,15 @@
+-- Add migration script here
+CREATE TABLE newsletter_subject_variants (
+  newsletter_issue_id uuid NOT NULL
+    REFERENCES newsletter_issues (newsletter_issue_id),
+  subject_variant SMALLINT NOT NULL,
+  subject TEXT NOT NULL,
+  PRIMARY KEY (newsletter_issue_id, subject_variant)
+);
+
+ALTER TABLE newsletter_issues ADD COLUMN ab_test_ends_at timestamptz NULL;
+ALTER TABLE newsletter_issues ADD COLUMN ab_test_metric TEXT NULL;
+ALTER TABLE newsletter_issues ADD COLUMN winning_subject_variant SMALLINT NULL;
+
+ALTER TABLE newsletter_delivery_queue ADD COLUMN subject_variant SMALLINT NULL;
+ALTER TABLE newsletter_delivery_log ADD COLUMN subject_variant SMALLINT NULL;
@@ -0,0 +1,21 @@
+-- Add migration script here
+CREATE TABLE newsletter_assets (
+  asset_id uuid PRIMARY KEY,
+  file_name TEXT NOT NULL,
+  content_type TEXT NOT NULL,
+  size_bytes BIGINT NOT NULL,
+  storage_key TEXT NOT NULL,
+  uploaded_by uuid NOT NULL
+    REFERENCES users (user_id),
+  uploaded_at timestamptz NOT NULL
+);
+
+CREATE TABLE newsletter_issue_assets (
+  newsletter_issue_id uuid NOT NULL
+    REFERENCES newsletter_issues (newsletter_issue_id),
+  asset_id uuid NOT NULL
+    REFERENCES newsletter_assets (asset_id),
+  PRIMARY KEY (newsletter_issue_id, asset_id)
+);
+
+ALTER TABLE newsletter_issues ADD COLUMN asset_delivery TEXT NOT NULL DEFAULT 'hosted';
@@ -0,0 +1,28 @@
+-- Add migration script here
+CREATE TABLE newsletter_drafts (
+  draft_id uuid PRIMARY KEY,
+  title TEXT NOT NULL,
+  preheader TEXT NOT NULL,
+  text_content TEXT NOT NULL,
+  html_content TEXT NOT NULL,
+  created_by uuid NOT NULL
+    REFERENCES users (user_id),
+  created_at timestamptz NOT NULL,
+  updated_at timestamptz NOT NULL,
+  published_issue_id uuid NULL
+    REFERENCES newsletter_issues (newsletter_issue_id)
+);
+
+CREATE TABLE newsletter_draft_revisions (
+  draft_id uuid NOT NULL
+    REFERENCES newsletter_drafts (draft_id),
+  revision INTEGER NOT NULL,
+  title TEXT NOT NULL,
+  preheader TEXT NOT NULL,
+  text_content TEXT NOT NULL,
+  html_content TEXT NOT NULL,
+  edited_by uuid NOT NULL
+    REFERENCES users (user_id),
+  edited_at timestamptz NOT NULL,
+  PRIMARY KEY (draft_id, revision)
+);
@@ -0,0 +1,22 @@
+-- Add migration script here
+ALTER TABLE newsletter_drafts
+  ADD COLUMN review_status TEXT NOT NULL DEFAULT 'draft'
+    CHECK (review_status IN ('draft', 'submitted', 'approved', 'sent')),
+  ADD COLUMN submitted_by uuid NULL
+    REFERENCES users (user_id),
+  ADD COLUMN submitted_at timestamptz NULL,
+  ADD COLUMN approved_by uuid NULL
+    REFERENCES users (user_id),
+  ADD COLUMN approved_at timestamptz NULL;
+
+UPDATE newsletter_drafts
+SET review_status = 'sent'
+WHERE published_issue_id IS NOT NULL;
+
+ALTER TABLE newsletter_issues
+  ADD COLUMN submitted_by uuid NULL
+    REFERENCES users (user_id),
+  ADD COLUMN submitted_at timestamptz NULL,
+  ADD COLUMN approved_by uuid NULL
+    REFERENCES users (user_id),
+  ADD COLUMN approved_at timestamptz NULL;
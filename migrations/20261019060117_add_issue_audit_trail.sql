@@ -0,0 +1,33 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues
+  ADD COLUMN published_by uuid NULL
+    REFERENCES users (user_id),
+  ADD COLUMN idempotency_key TEXT NULL;
+
+-- Until now the author column held whoever published the issue.
+UPDATE newsletter_issues SET published_by = author_id;
+
+CREATE TABLE newsletter_issue_events (
+  newsletter_issue_id uuid NOT NULL
+    REFERENCES newsletter_issues (newsletter_issue_id),
+  action TEXT NOT NULL,
+  user_id uuid NULL
+    REFERENCES users (user_id),
+  details TEXT NOT NULL DEFAULT '',
+  occurred_at timestamptz NOT NULL
+);
+
+CREATE INDEX newsletter_issue_events_issue_idx
+  ON newsletter_issue_events (newsletter_issue_id, occurred_at);
+
+INSERT INTO newsletter_issue_events (newsletter_issue_id, action, user_id, occurred_at)
+SELECT newsletter_issue_id, 'submitted', submitted_by, submitted_at
+FROM newsletter_issues
+WHERE submitted_at IS NOT NULL
+UNION ALL
+SELECT newsletter_issue_id, 'approved', approved_by, approved_at
+FROM newsletter_issues
+WHERE approved_at IS NOT NULL
+UNION ALL
+SELECT newsletter_issue_id, 'published', published_by, published_at
+FROM newsletter_issues;
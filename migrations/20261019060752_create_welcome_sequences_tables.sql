@@ -0,0 +1,46 @@
+-- Add migration script here
+CREATE TABLE welcome_sequences (
+  sequence_id uuid PRIMARY KEY,
+  name TEXT NOT NULL,
+  active BOOLEAN NOT NULL DEFAULT TRUE,
+  created_at timestamptz NOT NULL
+);
+
+CREATE TABLE welcome_sequence_steps (
+  step_id uuid PRIMARY KEY,
+  sequence_id uuid NOT NULL
+    REFERENCES welcome_sequences (sequence_id),
+  delay_days INTEGER NOT NULL CHECK (delay_days >= 0),
+  subject TEXT NOT NULL,
+  text_content TEXT NOT NULL,
+  html_content TEXT NOT NULL,
+  created_at timestamptz NOT NULL
+);
+
+-- Enrollments and pending sends go away with the subscription, which is
+-- what stops a sequence when someone unsubscribes.
+CREATE TABLE welcome_sequence_enrollments (
+  sequence_id uuid NOT NULL
+    REFERENCES welcome_sequences (sequence_id),
+  subscriber_id uuid NOT NULL
+    REFERENCES subscriptions (id) ON DELETE CASCADE,
+  enrolled_at timestamptz NOT NULL,
+  PRIMARY KEY (sequence_id, subscriber_id)
+);
+
+CREATE TABLE welcome_sequence_queue (
+  step_id uuid NOT NULL
+    REFERENCES welcome_sequence_steps (step_id),
+  subscriber_id uuid NOT NULL
+    REFERENCES subscriptions (id) ON DELETE CASCADE,
+  PRIMARY KEY (step_id, subscriber_id)
+);
+
+CREATE TABLE welcome_sequence_log (
+  step_id uuid NOT NULL
+    REFERENCES welcome_sequence_steps (step_id),
+  subscriber_id uuid NOT NULL,
+  outcome TEXT NOT NULL,
+  recorded_at timestamptz NOT NULL,
+  PRIMARY KEY (step_id, subscriber_id)
+);
@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Workers lease queue rows instead of holding a row lock while they send:
+-- a row is free when it has no lease or its lease has expired.
+ALTER TABLE newsletter_delivery_queue
+  ADD COLUMN locked_until timestamptz NULL,
+  ADD COLUMN locked_by TEXT NULL;
+
+ALTER TABLE welcome_sequence_queue
+  ADD COLUMN locked_until timestamptz NULL,
+  ADD COLUMN locked_by TEXT NULL;
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Emails held back by a rate limit are returned to the queue and only
+-- claimed again once their next attempt is due.
+ALTER TABLE newsletter_delivery_queue
+  ADD COLUMN next_attempt_at timestamptz NOT NULL DEFAULT now();
+
+ALTER TABLE welcome_sequence_queue
+  ADD COLUMN next_attempt_at timestamptz NOT NULL DEFAULT now();
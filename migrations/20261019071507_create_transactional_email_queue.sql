@@ -0,0 +1,16 @@
+-- Add migration script here
+-- One-off emails, like subscription confirmations, written in the same
+-- transaction as the change that triggers them and sent by the workers.
+CREATE TABLE transactional_email_queue (
+  email_id uuid PRIMARY KEY,
+  recipient TEXT NOT NULL,
+  subject TEXT NOT NULL,
+  text_body TEXT NOT NULL,
+  html_body TEXT NOT NULL,
+  enqueued_at timestamptz NOT NULL DEFAULT now(),
+  n_attempts INTEGER NOT NULL DEFAULT 0,
+  last_error TEXT NULL,
+  next_attempt_at timestamptz NOT NULL DEFAULT now(),
+  locked_until timestamptz NULL,
+  locked_by TEXT NULL
+);
\ No newline at end of file
@@ -0,0 +1,15 @@
+-- Add migration script here
+-- A delivery is claimed with its lease, then marked as sending right
+-- before the call to the email provider, and leaves the queue for the log
+-- once sent. A row found sending after its lease expired may have gone out
+-- already: its delivery id, passed to the provider as metadata, tells.
+ALTER TABLE newsletter_delivery_queue
+  ADD COLUMN delivery_id uuid NOT NULL DEFAULT gen_random_uuid(),
+  ADD COLUMN status TEXT NOT NULL DEFAULT 'queued'
+    CHECK (status IN ('queued', 'claimed', 'sending')),
+  ADD COLUMN tracking_token TEXT NULL;
+
+ALTER TABLE newsletter_delivery_queue
+  ADD CONSTRAINT newsletter_delivery_queue_delivery_id_key UNIQUE (delivery_id);
+
+ALTER TABLE newsletter_delivery_log ADD COLUMN delivery_id uuid NULL;
\ No newline at end of file
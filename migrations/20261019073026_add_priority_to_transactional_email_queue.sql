@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Higher priority emails are claimed first within the transactional lane,
+-- which workers serve ahead of bulk issue deliveries.
+ALTER TABLE transactional_email_queue
+  ADD COLUMN priority SMALLINT NOT NULL DEFAULT 0;
+
+CREATE INDEX transactional_email_queue_claim_order
+  ON transactional_email_queue (priority DESC, enqueued_at);
\ No newline at end of file
@@ -0,0 +1,22 @@
+-- Add migration script here
+-- The settings an issue is published with are part of its draft, so that
+-- they are reviewed and approved along with the content.
+ALTER TABLE newsletter_drafts
+  ADD COLUMN subscribers_only BOOLEAN NOT NULL DEFAULT false,
+  ADD COLUMN track_opens BOOLEAN NOT NULL DEFAULT true,
+  ADD COLUMN track_clicks BOOLEAN NOT NULL DEFAULT true,
+  ADD COLUMN subject_variants TEXT NOT NULL DEFAULT '',
+  ADD COLUMN ab_test_percentage INTEGER NOT NULL DEFAULT 20,
+  ADD COLUMN ab_test_window_minutes INTEGER NOT NULL DEFAULT 240,
+  ADD COLUMN ab_test_metric TEXT NOT NULL DEFAULT 'opens',
+  ADD COLUMN asset_delivery TEXT NOT NULL DEFAULT 'hosted';
+
+ALTER TABLE newsletter_draft_revisions
+  ADD COLUMN subscribers_only BOOLEAN NOT NULL DEFAULT false,
+  ADD COLUMN track_opens BOOLEAN NOT NULL DEFAULT true,
+  ADD COLUMN track_clicks BOOLEAN NOT NULL DEFAULT true,
+  ADD COLUMN subject_variants TEXT NOT NULL DEFAULT '',
+  ADD COLUMN ab_test_percentage INTEGER NOT NULL DEFAULT 20,
+  ADD COLUMN ab_test_window_minutes INTEGER NOT NULL DEFAULT 240,
+  ADD COLUMN ab_test_metric TEXT NOT NULL DEFAULT 'opens',
+  ADD COLUMN asset_delivery TEXT NOT NULL DEFAULT 'hosted';
\ No newline at end of file
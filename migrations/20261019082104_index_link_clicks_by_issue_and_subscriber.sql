@@ -0,0 +1,5 @@
+-- Add migration script here
+-- Analytics, subject line tests and unsubscribe attribution look clicks up
+-- by issue and subscriber.
+CREATE INDEX newsletter_link_clicks_issue_subscriber
+  ON newsletter_link_clicks (newsletter_issue_id, subscriber_email);
\ No newline at end of file
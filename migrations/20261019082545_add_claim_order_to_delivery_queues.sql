@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Workers claim the rows whose next attempt is due, so that claiming does
+-- not scan the whole queue.
+CREATE INDEX newsletter_delivery_queue_claim_order
+  ON newsletter_delivery_queue (next_attempt_at);
+
+CREATE INDEX welcome_sequence_queue_claim_order
+  ON welcome_sequence_queue (next_attempt_at);
\ No newline at end of file
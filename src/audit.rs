@@ -0,0 +1,63 @@
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// Something an admin did to a newsletter issue, as recorded in its audit
+/// trail.
+#[derive(Clone, Copy, Debug)]
+pub enum IssueAction {
+  Submitted,
+  Approved,
+  Published,
+  Paused,
+  Resumed,
+  Cancelled,
+  Duplicated,
+}
+
+impl IssueAction {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      IssueAction::Submitted => "submitted",
+      IssueAction::Approved => "approved",
+      IssueAction::Published => "published",
+      IssueAction::Paused => "paused",
+      IssueAction::Resumed => "resumed",
+      IssueAction::Cancelled => "cancelled",
+      IssueAction::Duplicated => "duplicated",
+    }
+  }
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn record_issue_event<'e, E>(
+  executor: E,
+  issue_id: Uuid,
+  action: IssueAction,
+  user_id: Uuid,
+  details: &str,
+  occurred_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error>
+where
+  E: sqlx::PgExecutor<'e>,
+{
+  sqlx::query!(
+    r#"
+    INSERT INTO newsletter_issue_events (
+      newsletter_issue_id,
+      action,
+      user_id,
+      details,
+      occurred_at
+    )
+    VALUES ($1, $2, $3, $4, $5)
+    "#,
+    issue_id,
+    action.as_str(),
+    user_id,
+    details,
+    occurred_at,
+  )
+  .execute(executor)
+  .await?;
+  Ok(())
+}
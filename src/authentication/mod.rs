@@ -2,7 +2,7 @@ mod password;
 mod middlewear;
 
 pub use password::{
-  change_password, validate_credentials,
+  change_password, create_user, validate_credentials,
   AuthError, Credentials
 };
 pub use middlewear::{reject_anonymous_users, UserId};
\ No newline at end of file
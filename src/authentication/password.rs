@@ -87,6 +87,36 @@ pub async fn change_password(
   Ok(())
 }
 
+/// Adds an admin who can log in with `password`.
+#[tracing::instrument(skip(password, pool))]
+pub async fn create_user(
+  username: &str,
+  email: Option<&str>,
+  password: Secret<String>,
+  pool: &PgPool
+) -> Result<uuid::Uuid, anyhow::Error> {
+  let password_hash = tracing::info_span!("Hash password")
+    .in_scope(|| compute_password_hash(password))
+    .context("Failed to hash password")?;
+  let user_id = uuid::Uuid::new_v4();
+
+  sqlx::query!(
+    r#"
+    INSERT INTO users (user_id, username, password_hash, email)
+    VALUES ($1, $2, $3, $4)
+    "#,
+    user_id,
+    username,
+    password_hash.expose_secret(),
+    email,
+  )
+  .execute(pool)
+  .await
+  .context("Failed to store the new user in the database.")?;
+
+  Ok(user_id)
+}
+
 fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
   let salt = SaltString::generate(&mut rand::thread_rng());
   let password_hash = Argon2::new(
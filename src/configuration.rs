@@ -1,4 +1,7 @@
-use crate::{domain::SubscriberEmail, email_client::EmailClient};
+use crate::{
+  asset_storage::{AssetStorage, S3Bucket}, domain::SubscriberEmail, email_client::EmailClient,
+  rate_limiter::RateLimiter,
+};
 use secrecy::{Secret, ExposeSecret};
 use serde_aux::prelude::*;
 use sqlx::{postgres::{PgConnectOptions, PgSslMode}, ConnectOptions};
@@ -9,6 +12,8 @@ pub struct Settings {
   pub database: DatabaseSettings,
   pub application: ApplicationSettings,
   pub email_client: EmailClientSettings,
+  pub asset_storage: AssetStorageSettings,
+  pub delivery: DeliverySettings,
   pub redis_uri: Secret<String>,
 }
 
@@ -19,6 +24,10 @@ pub struct ApplicationSettings {
   pub port: u16,
   pub base_url: String,
   pub hmac_secret: Secret<String>,
+  pub require_issue_approval: bool,
+  /// How long in-flight requests and deliveries get to finish on shutdown.
+  #[serde(deserialize_with = "deserialize_number_from_string")]
+  pub shutdown_timeout_secs: u64,
 }
 
 #[derive(serde::Deserialize)]
@@ -30,6 +39,91 @@ pub struct DatabaseSettings {
   pub host: String,
   pub name: String,
   pub require_ssl: bool,
+  #[serde(deserialize_with = "deserialize_number_from_string")]
+  pub max_connections: u32,
+}
+
+#[derive(serde::Deserialize)]
+#[derive(Clone)]
+pub struct DeliverySettings {
+  /// How many delivery workers run side by side.
+  #[serde(deserialize_with = "deserialize_number_from_string")]
+  pub workers: usize,
+  /// How many emails each worker claims and sends in one go, as a single
+  /// call to the email provider up to its batch limit.
+  #[serde(deserialize_with = "deserialize_number_from_string")]
+  pub sends_per_worker: usize,
+  /// The most emails sent per second by all the workers of one instance
+  /// together, unlimited when missing. The budget is not shared between
+  /// processes, so each `scoop` or `scoop worker` process sends at up to
+  /// this rate.
+  #[serde(default)]
+  pub instance_messages_per_second: Option<f64>,
+  /// Lower rates for the mailbox providers that throttle bursts, per
+  /// instance as well.
+  #[serde(default)]
+  pub domain_limits: Vec<DomainLimit>,
+  /// The share of each worker's rounds that serve bulk deliveries ahead of
+  /// transactional email, between 0 and 1.
+  #[serde(deserialize_with = "deserialize_number_from_string")]
+  pub bulk_min_share: f64,
+}
+
+#[derive(serde::Deserialize)]
+#[derive(Clone)]
+pub struct DomainLimit {
+  pub domain: String,
+  pub instance_messages_per_second: f64,
+}
+
+impl DeliverySettings {
+  /// Catches the values the workers cannot run with while the configuration
+  /// is read, rather than once they start.
+  pub fn validate(&self) -> Result<(), String> {
+    for (name, value) in [
+      ("delivery.workers", self.workers),
+      ("delivery.sends_per_worker", self.sends_per_worker),
+    ] {
+      if value == 0 {
+        return Err(format!("{} must be at least 1.", name));
+      }
+    }
+    let rates = self
+      .instance_messages_per_second
+      .iter()
+      .map(|rate| ("delivery.instance_messages_per_second".to_owned(), *rate))
+      .chain(self.domain_limits.iter().map(|limit| {
+        (format!("delivery.domain_limits for {}", limit.domain), limit.instance_messages_per_second)
+      }));
+    for (name, rate) in rates {
+      if !(rate.is_finite() && rate > 0.0) {
+        return Err(format!("The send rate in {} must be a positive number, got {}.", name, rate));
+      }
+    }
+    if !(0.0..=1.0).contains(&self.bulk_min_share) {
+      return Err(format!(
+        "delivery.bulk_min_share must be between 0 and 1, got {}.",
+        self.bulk_min_share
+      ));
+    }
+    Ok(())
+  }
+
+  pub fn rate_limiter(&self) -> RateLimiter {
+    let domain_limits: Vec<_> = self
+      .domain_limits
+      .iter()
+      .map(|limit| (limit.domain.clone(), limit.instance_messages_per_second))
+      .collect();
+    RateLimiter::new(self.instance_messages_per_second, &domain_limits)
+  }
+
+  /// Each worker runs one query at a time, whatever the size of its batch,
+  /// and the listener that wakes them up and the scheduler hold a
+  /// connection of their own.
+  pub fn connection_pool_size(&self) -> u32 {
+    self.workers as u32 + 2
+  }
 }
 
 #[derive(serde::Deserialize)]
@@ -57,6 +151,33 @@ impl EmailClientSettings {
   }
 }
 
+#[derive(serde::Deserialize)]
+#[derive(Clone)]
+#[serde(tag = "backend", rename_all = "lowercase")]
+pub enum AssetStorageSettings {
+  Local {
+    directory: String,
+  },
+  S3 {
+    endpoint: String,
+    bucket: String,
+    region: String,
+    access_key_id: String,
+    secret_access_key: Secret<String>,
+  },
+}
+
+impl AssetStorageSettings {
+  pub fn storage(self) -> AssetStorage {
+    match self {
+      AssetStorageSettings::Local { directory } => AssetStorage::Local(directory.into()),
+      AssetStorageSettings::S3 { endpoint, bucket, region, access_key_id, secret_access_key } => {
+        AssetStorage::S3(S3Bucket::new(endpoint, bucket, region, access_key_id, secret_access_key))
+      }
+    }
+  }
+}
+
 impl DatabaseSettings {
   pub fn without_db(&self) -> PgConnectOptions {
     PgConnectOptions::new()
@@ -98,7 +219,9 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
         .separator("__"),
     )
     .build()?;
-  settings.try_deserialize::<Settings>()
+  let settings = settings.try_deserialize::<Settings>()?;
+  settings.delivery.validate().map_err(config::ConfigError::Message)?;
+  Ok(settings)
 }
 
 pub enum Environment {
@@ -127,4 +250,57 @@ impl TryFrom<String> for Environment {
                       )),
       }
   }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{DeliverySettings, DomainLimit};
+
+  fn delivery_settings() -> DeliverySettings {
+    DeliverySettings {
+      workers: 1,
+      sends_per_worker: 10,
+      instance_messages_per_second: Some(10.0),
+      domain_limits: vec![DomainLimit { domain: "gmail.com".into(), instance_messages_per_second: 1.0 }],
+      bulk_min_share: 0.2,
+    }
+  }
+
+  #[test]
+  fn valid_delivery_settings_are_accepted() {
+    assert!(delivery_settings().validate().is_ok());
+    let unlimited = DeliverySettings { instance_messages_per_second: None, ..delivery_settings() };
+    assert!(unlimited.validate().is_ok());
+  }
+
+  #[test]
+  fn there_must_be_workers_that_send_emails() {
+    let settings = DeliverySettings { workers: 0, ..delivery_settings() };
+    assert_eq!(settings.validate().unwrap_err(), "delivery.workers must be at least 1.");
+    let settings = DeliverySettings { sends_per_worker: 0, ..delivery_settings() };
+    assert_eq!(settings.validate().unwrap_err(), "delivery.sends_per_worker must be at least 1.");
+  }
+
+  #[test]
+  fn send_rates_must_be_positive() {
+    let settings = DeliverySettings { instance_messages_per_second: Some(0.0), ..delivery_settings() };
+    assert_eq!(
+      settings.validate().unwrap_err(),
+      "The send rate in delivery.instance_messages_per_second must be a positive number, got 0."
+    );
+
+    let mut settings = delivery_settings();
+    settings.domain_limits[0].instance_messages_per_second = -1.0;
+    assert!(settings.validate().unwrap_err().contains("gmail.com"));
+  }
+
+  #[test]
+  fn the_bulk_share_must_be_between_zero_and_one() {
+    for bulk_min_share in [0.0, 1.0] {
+      assert!(DeliverySettings { bulk_min_share, ..delivery_settings() }.validate().is_ok());
+    }
+    for bulk_min_share in [-0.1, 1.5, f64::NAN] {
+      assert!(DeliverySettings { bulk_min_share, ..delivery_settings() }.validate().is_err());
+    }
+  }
 }
\ No newline at end of file
@@ -0,0 +1,32 @@
+use serde::Deserialize;
+
+/// How the assets referenced by an issue reach subscribers: as links to the
+/// copies hosted by this application, or attached to every email.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetDelivery {
+  #[default]
+  Hosted,
+  Attached,
+}
+
+impl AssetDelivery {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      AssetDelivery::Hosted => "hosted",
+      AssetDelivery::Attached => "attached",
+    }
+  }
+}
+
+impl TryFrom<String> for AssetDelivery {
+  type Error = String;
+
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    match value.as_str() {
+      "hosted" => Ok(AssetDelivery::Hosted),
+      "attached" => Ok(AssetDelivery::Attached),
+      other => Err(format!("{} is not a way to deliver assets", other)),
+    }
+  }
+}
\ No newline at end of file
@@ -0,0 +1,59 @@
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub struct IssueSlug(String);
+
+impl IssueSlug {
+  /// Builds a URL friendly slug out of an issue title. The start of the issue
+  /// id is appended so that issues sharing a title get distinct slugs.
+  pub fn new(title: &str, issue_id: Uuid) -> IssueSlug {
+    let mut slug = String::new();
+    for c in title.chars() {
+      if c.is_alphanumeric() {
+        slug.extend(c.to_lowercase());
+      } else if !slug.is_empty() && !slug.ends_with('-') {
+        slug.push('-');
+      }
+    }
+    let id = issue_id.simple().to_string();
+    if !slug.is_empty() && !slug.ends_with('-') {
+      slug.push('-');
+    }
+    slug.push_str(&id[..8]);
+    Self(slug)
+  }
+}
+
+impl AsRef<str> for IssueSlug {
+  fn as_ref(&self) -> &str {
+    &self.0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::IssueSlug;
+  use uuid::Uuid;
+
+  fn issue_id() -> Uuid {
+    Uuid::parse_str("1a2b3c4d-0000-0000-0000-000000000000").unwrap()
+  }
+
+  #[test]
+  fn title_is_lowercased_and_hyphenated() {
+    let slug = IssueSlug::new("Hello, World!  Issue #3", issue_id());
+    assert_eq!(slug.as_ref(), "hello-world-issue-3-1a2b3c4d");
+  }
+
+  #[test]
+  fn a_title_without_alphanumeric_characters_falls_back_to_the_id() {
+    let slug = IssueSlug::new("!!!", issue_id());
+    assert_eq!(slug.as_ref(), "1a2b3c4d");
+  }
+
+  #[test]
+  fn non_ascii_letters_are_kept() {
+    let slug = IssueSlug::new("Crème brûlée", issue_id());
+    assert_eq!(slug.as_ref(), "crème-brûlée-1a2b3c4d");
+  }
+}
@@ -1,7 +1,13 @@
 mod subscriber_name;
 mod subscriber_email;
 mod new_subscriber;
+mod issue_slug;
+mod subject_test;
+mod asset_delivery;
 
 pub use subscriber_name::SubscriberName;
 pub use subscriber_email::SubscriberEmail;
-pub use new_subscriber::NewSubscriber;
\ No newline at end of file
+pub use new_subscriber::NewSubscriber;
+pub use issue_slug::IssueSlug;
+pub use subject_test::{SubjectTest, SubjectTestMetric};
+pub use asset_delivery::AssetDelivery;
\ No newline at end of file
@@ -0,0 +1,126 @@
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubjectTestMetric {
+  #[default]
+  Opens,
+  Clicks,
+}
+
+impl SubjectTestMetric {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      SubjectTestMetric::Opens => "opens",
+      SubjectTestMetric::Clicks => "clicks",
+    }
+  }
+}
+
+impl TryFrom<String> for SubjectTestMetric {
+  type Error = String;
+
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    match value.as_str() {
+      "opens" => Ok(SubjectTestMetric::Opens),
+      "clicks" => Ok(SubjectTestMetric::Clicks),
+      other => Err(format!("{} is not a subject test metric", other)),
+    }
+  }
+}
+
+/// An A/B test between subject lines. The issue title is always the first
+/// subject; a test percentage of the audience is split evenly between the
+/// subjects and everyone else receives the winner once the window closes.
+#[derive(Debug)]
+pub struct SubjectTest {
+  subjects: Vec<String>,
+  test_percentage: i32,
+  window_minutes: i32,
+  metric: SubjectTestMetric,
+}
+
+impl SubjectTest {
+  /// Returns `None` when `variants` holds no subject other than the title,
+  /// as there is nothing to test.
+  pub fn parse(
+    title: &str,
+    variants: &str,
+    test_percentage: i32,
+    window_minutes: i32,
+    metric: SubjectTestMetric,
+  ) -> Result<Option<SubjectTest>, String> {
+    let mut subjects = vec![title.to_owned()];
+    for variant in variants.lines().map(str::trim) {
+      if !variant.is_empty() && !subjects.iter().any(|s| s == variant) {
+        subjects.push(variant.to_owned());
+      }
+    }
+    if subjects.len() < 2 {
+      return Ok(None);
+    }
+    if !(1..=100).contains(&test_percentage) {
+      return Err(format!("{} is not a valid test percentage", test_percentage));
+    }
+    if !(1..=7 * 24 * 60).contains(&window_minutes) {
+      return Err(format!("{} is not a valid test window in minutes", window_minutes));
+    }
+    Ok(Some(Self { subjects, test_percentage, window_minutes, metric }))
+  }
+
+  pub fn subjects(&self) -> &[String] {
+    &self.subjects
+  }
+
+  pub fn test_percentage(&self) -> i32 {
+    self.test_percentage
+  }
+
+  pub fn window_minutes(&self) -> i32 {
+    self.window_minutes
+  }
+
+  pub fn metric(&self) -> SubjectTestMetric {
+    self.metric
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{SubjectTest, SubjectTestMetric};
+  use claims::{assert_err, assert_none};
+
+  fn parse(variants: &str, test_percentage: i32, window_minutes: i32) -> Result<Option<SubjectTest>, String> {
+    SubjectTest::parse("Title", variants, test_percentage, window_minutes, SubjectTestMetric::Opens)
+  }
+
+  #[test]
+  fn variants_follow_the_title() {
+    let test = parse("  Another subject \n\nA third one\n", 20, 60).unwrap().unwrap();
+    assert_eq!(test.subjects(), ["Title", "Another subject", "A third one"]);
+  }
+
+  #[test]
+  fn no_test_is_run_without_distinct_variants() {
+    assert_none!(parse("", 20, 60).unwrap());
+    assert_none!(parse("Title\n  \nTitle", 20, 60).unwrap());
+  }
+
+  #[test]
+  fn duplicate_variants_are_ignored() {
+    let test = parse("Other\nOther\nTitle", 20, 60).unwrap().unwrap();
+    assert_eq!(test.subjects(), ["Title", "Other"]);
+  }
+
+  #[test]
+  fn test_percentage_must_be_between_1_and_100() {
+    assert_err!(parse("Other", 0, 60));
+    assert_err!(parse("Other", 101, 60));
+  }
+
+  #[test]
+  fn test_window_must_be_at_most_a_week() {
+    assert_err!(parse("Other", 20, 0));
+    assert_err!(parse("Other", 20, 7 * 24 * 60 + 1));
+  }
+}
@@ -1,8 +1,20 @@
-use reqwest::Client;
+use base64::Engine;
+use reqwest::{Client, StatusCode};
 use secrecy::{Secret, ExposeSecret};
 
 use crate::domain::SubscriberEmail;
 
+/// Postmark accepts at most this many messages in one batch call.
+pub const MAX_BATCH_SIZE: usize = 500;
+
+/// Postmark refuses messages over 10 MB, attachments included.
+pub const MAX_MESSAGE_BYTES: usize = 10_000_000;
+
+/// Postmark refuses batch calls over 50 MB. Every message of a batch carries
+/// its own copy of the issue's attachments, so large attachments make for
+/// smaller batches.
+pub const MAX_BATCH_BYTES: usize = 50_000_000;
+
 pub struct EmailClient {
   sender: SubscriberEmail,
   client: Client,
@@ -36,25 +48,227 @@ impl EmailClient {
     text_body: &str,
     html_body: &str,
   ) -> Result<(), reqwest::Error> {
-    let url = format!("{}/email", self.base_url);
+    self.send_email_with_attachments(receiver, subject, text_body, html_body, &[]).await
+  }
+
+  pub async fn send_email_with_attachments(
+    &self,
+    receiver: &SubscriberEmail,
+    subject: &str,
+    text_body: &str,
+    html_body: &str,
+    attachments: &[EmailAttachment],
+  ) -> Result<(), reqwest::Error> {
     let request_body = SendEmailRequest {
       from: self.sender.as_ref(),
       to: receiver.as_ref(),
       subject,
       text_body,
       html_body,
+      attachments,
+      metadata: None,
     };
+    self.post_email(&request_body).await
+  }
 
+  async fn post_email(&self, request_body: &SendEmailRequest<'_>) -> Result<(), reqwest::Error> {
+    let url = format!("{}/email", self.base_url);
     self
       .client
       .post(&url)
       .header("X-Postmark-Server-Token", self.auth_token.expose_secret().clone())
-      .json(&request_body)
+      .json(request_body)
       .send()
       .await?
       .error_for_status()?;
     Ok(())
   }
+
+  /// Sends any number of messages, in batches of up to `MAX_BATCH_SIZE`
+  /// messages and `MAX_BATCH_BYTES`, and returns the outcome of each in
+  /// order. A failed call fails every message of its batch: as unavailable
+  /// when Postmark was down or throttling us, as rejected when it answered
+  /// with any other error, and as unknown when it did not answer, since it
+  /// may have taken the batch before the call failed.
+  pub async fn send_emails(&self, messages: &[EmailMessage<'_>]) -> Vec<Result<(), SendError>> {
+    let mut results = Vec::with_capacity(messages.len());
+    for batch in self.batches(messages) {
+      match self.send_email_batch(batch).await {
+        Ok(batch_results) => {
+          results.extend(batch_results.into_iter().map(|result| result.map_err(SendError::Rejected)))
+        }
+        Err(e) => {
+          tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to send a batch of {} emails.",
+            batch.len(),
+          );
+          let status = e.downcast_ref::<reqwest::Error>().and_then(reqwest::Error::status);
+          let error = match status {
+            Some(status) if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS => {
+              SendError::Unavailable(e.to_string())
+            }
+            Some(_) => SendError::Rejected(e.to_string()),
+            None => SendError::Unknown(e.to_string()),
+          };
+          results.extend(batch.iter().map(|_| Err(error.clone())));
+        }
+      }
+    }
+    results
+  }
+
+  /// Splits the messages into consecutive batches Postmark accepts. A
+  /// message too large for a batch of its own is sent alone, for Postmark
+  /// to turn it down.
+  fn batches<'m, 'a>(&self, messages: &'m [EmailMessage<'a>]) -> Vec<&'m [EmailMessage<'a>]> {
+    let mut batches = Vec::new();
+    // The brackets around a batch and the commas between its messages.
+    let (mut start, mut batch_bytes) = (0, 2);
+    for (i, message) in messages.iter().enumerate() {
+      let message_bytes = self.request_for(message).encoded_size() + 1;
+      if i > start && (i - start == MAX_BATCH_SIZE || batch_bytes + message_bytes > MAX_BATCH_BYTES) {
+        batches.push(&messages[start..i]);
+        (start, batch_bytes) = (i, 2);
+      }
+      batch_bytes += message_bytes;
+    }
+    if start < messages.len() {
+      batches.push(&messages[start..]);
+    }
+    batches
+  }
+
+  /// Sends up to `MAX_BATCH_SIZE` messages in one call. A lone message goes
+  /// through the plain `/email` endpoint. The outcome of each message is
+  /// returned in order, with Postmark's reason when it rejected one; an
+  /// error means the call itself failed and nothing was sent.
+  pub async fn send_email_batch(
+    &self,
+    messages: &[EmailMessage<'_>],
+  ) -> Result<Vec<Result<(), String>>, anyhow::Error> {
+    anyhow::ensure!(
+      messages.len() <= MAX_BATCH_SIZE,
+      "A batch holds at most {} messages.",
+      MAX_BATCH_SIZE
+    );
+    if let [message] = messages {
+      self.post_email(&self.request_for(message)).await?;
+      return Ok(vec![Ok(())]);
+    }
+    if messages.is_empty() {
+      return Ok(Vec::new());
+    }
+
+    let url = format!("{}/email/batch", self.base_url);
+    let request_body: Vec<_> = messages.iter().map(|message| self.request_for(message)).collect();
+    let results: Vec<BatchMessageResult> = self
+      .client
+      .post(&url)
+      .header("X-Postmark-Server-Token", self.auth_token.expose_secret().clone())
+      .json(&request_body)
+      .send()
+      .await?
+      .error_for_status()?
+      .json()
+      .await?;
+    anyhow::ensure!(
+      results.len() == messages.len(),
+      "Postmark returned {} results for a batch of {} messages.",
+      results.len(),
+      messages.len()
+    );
+
+    Ok(results
+      .into_iter()
+      .map(|result| match result.error_code {
+        0 => Ok(()),
+        code => Err(format!("{} (error code {})", result.message, code)),
+      })
+      .collect())
+  }
+
+  fn request_for<'a>(&'a self, message: &'a EmailMessage<'_>) -> SendEmailRequest<'a> {
+    SendEmailRequest {
+      from: self.sender.as_ref(),
+      to: message.to.as_ref(),
+      subject: message.subject,
+      text_body: message.text_body,
+      html_body: message.html_body,
+      attachments: message.attachments,
+      metadata: message.reference.map(|delivery_reference| Metadata { delivery_reference }),
+    }
+  }
+
+  /// Whether a message sent with `reference` has reached Postmark, looked
+  /// up through the metadata it was sent with.
+  pub async fn was_sent(&self, reference: &str) -> Result<bool, reqwest::Error> {
+    let url = format!("{}/messages/outbound", self.base_url);
+    let found: OutboundMessageSearch = self
+      .client
+      .get(&url)
+      .header("X-Postmark-Server-Token", self.auth_token.expose_secret().clone())
+      .header("Accept", "application/json")
+      .query(&[
+        ("count", "1"),
+        ("offset", "0"),
+        ("metadata_delivery_reference", reference),
+      ])
+      .send()
+      .await?
+      .error_for_status()?
+      .json()
+      .await?;
+    Ok(found.total_count > 0)
+  }
+}
+
+/// Why a message was not sent, as far as the client can tell.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SendError {
+  /// Postmark turned the message down, it was not sent.
+  Rejected(String),
+  /// Postmark failed or was throttling us, the message was not sent and
+  /// can be tried again later.
+  Unavailable(String),
+  /// The call failed without an answer from Postmark, which may or may not
+  /// have sent the message.
+  Unknown(String),
+}
+
+impl std::fmt::Display for SendError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      SendError::Rejected(reason) | SendError::Unavailable(reason) | SendError::Unknown(reason) => {
+        f.write_str(reason)
+      }
+    }
+  }
+}
+
+/// One email of a batch. A reference is sent along as metadata, for the
+/// message to be found again with `EmailClient::was_sent`.
+pub struct EmailMessage<'a> {
+  pub to: &'a SubscriberEmail,
+  pub subject: &'a str,
+  pub text_body: &'a str,
+  pub html_body: &'a str,
+  pub attachments: &'a [EmailAttachment],
+  pub reference: Option<&'a str>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct OutboundMessageSearch {
+  total_count: u64,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchMessageResult {
+  error_code: i64,
+  message: String,
 }
 
 #[derive(serde::Serialize)]
@@ -65,15 +279,84 @@ struct SendEmailRequest<'a> {
   subject: &'a str,
   text_body: &'a str,
   html_body: &'a str,
+  #[serde(skip_serializing_if = "<[_]>::is_empty")]
+  attachments: &'a [EmailAttachment],
+  #[serde(skip_serializing_if = "Option::is_none")]
+  metadata: Option<Metadata<'a>>,
+}
+
+impl SendEmailRequest<'_> {
+  /// The size of the request as JSON. Attachments are measured once, when
+  /// they are encoded, rather than with every message they go out with.
+  fn encoded_size(&self) -> usize {
+    let without_attachments = SendEmailRequest { attachments: &[], metadata: self.metadata.clone(), ..*self };
+    let mut counter = ByteCounter(0);
+    serde_json::to_writer(&mut counter, &without_attachments).expect("Requests always serialize.");
+    if self.attachments.is_empty() {
+      return counter.0;
+    }
+    // `,"Attachments":[]` and the commas between attachments.
+    let attachments_bytes: usize = self.attachments.iter().map(|a| a.encoded_size + 1).sum();
+    counter.0 + r#","Attachments":[]"#.len() + attachments_bytes - 1
+  }
+}
+
+struct ByteCounter(usize);
+
+impl std::io::Write for ByteCounter {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    self.0 += buf.len();
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+#[derive(serde::Serialize, Clone)]
+struct Metadata<'a> {
+  delivery_reference: &'a str,
+}
+
+/// A file sent along with an email. Attachments with a content id are shown
+/// inline wherever the HTML body references `cid:<content id>`.
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EmailAttachment {
+  name: String,
+  content: String,
+  content_type: String,
+  #[serde(rename = "ContentID", skip_serializing_if = "Option::is_none")]
+  content_id: Option<String>,
+  /// The size of the attachment as JSON.
+  #[serde(skip)]
+  encoded_size: usize,
+}
+
+impl EmailAttachment {
+  pub fn new(name: String, content_type: String, content: &[u8], content_id: Option<String>) -> Self {
+    let mut attachment = Self {
+      name,
+      content: base64::engine::general_purpose::STANDARD.encode(content),
+      content_type,
+      content_id,
+      encoded_size: 0,
+    };
+    let mut counter = ByteCounter(0);
+    serde_json::to_writer(&mut counter, &attachment).expect("Attachments always serialize.");
+    attachment.encoded_size = counter.0;
+    attachment
+  }
 }
 
 #[cfg(test)]
 mod tests {
   use claims::{assert_ok, assert_err};
   use secrecy::Secret;
-  use wiremock::{MockServer, Mock, matchers::{header_exists, header, path, method, any}, ResponseTemplate};
+  use wiremock::{MockServer, Mock, matchers::{header_exists, header, path, method, any, body_partial_json, query_param}, ResponseTemplate};
   use fake::{faker::{internet::en::SafeEmail, lorem::en::{Sentence, Paragraph}}, Fake, Faker};
-  use crate::{domain::SubscriberEmail, email_client::EmailClient};
+  use crate::{domain::SubscriberEmail, email_client::{EmailAttachment, EmailClient, EmailMessage, SendError}};
 
   fn subject() -> String {
     Sentence(1 .. 2).fake()
@@ -159,5 +442,194 @@ mod tests {
 
     assert_err!(response);
   }
-      
+
+  #[tokio::test]
+  async fn attachments_are_sent_base64_encoded() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+
+    Mock::given(body_partial_json(serde_json::json!({
+      "Attachments": [{
+        "Name": "logo.png",
+        "Content": "aGVsbG8=",
+        "ContentType": "image/png",
+        "ContentID": "cid:logo",
+      }]
+    })))
+      .respond_with(ResponseTemplate::new(200))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let attachments = [EmailAttachment::new(
+      "logo.png".into(),
+      "image/png".into(),
+      b"hello",
+      Some("cid:logo".into()),
+    )];
+    let response = email_client
+      .send_email_with_attachments(&email(), &subject(), &body(), &body(), &attachments)
+      .await;
+
+    assert_ok!(response);
+  }
+
+  #[tokio::test]
+  async fn batches_go_to_the_batch_endpoint_with_one_result_per_message() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+
+    Mock::given(path("/email/batch"))
+      .and(method("POST"))
+      .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+        {"ErrorCode": 0, "Message": "OK"},
+        {"ErrorCode": 406, "Message": "Address is inactive."},
+      ])))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let receivers = [email(), email()];
+    let (subject, body) = (subject(), body());
+    let messages: Vec<_> = receivers
+      .iter()
+      .map(|to| EmailMessage { to, subject: &subject, text_body: &body, html_body: &body, attachments: &[], reference: None })
+      .collect();
+    let results = email_client.send_email_batch(&messages).await.unwrap();
+
+    assert_ok!(&results[0]);
+    assert_eq!(results[1], Err("Address is inactive. (error code 406)".to_owned()));
+  }
+
+  #[tokio::test]
+  async fn a_batch_fails_if_500_response() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+
+    Mock::given(any())
+      .respond_with(ResponseTemplate::new(500))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let receivers = [email(), email()];
+    let (subject, body) = (subject(), body());
+    let messages: Vec<_> = receivers
+      .iter()
+      .map(|to| EmailMessage { to, subject: &subject, text_body: &body, html_body: &body, attachments: &[], reference: None })
+      .collect();
+
+    assert_err!(email_client.send_email_batch(&messages).await);
+  }
+
+  #[tokio::test]
+  async fn sent_messages_are_found_by_their_reference() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+
+    Mock::given(path("/messages/outbound"))
+      .and(method("GET"))
+      .and(header_exists("X-Postmark-Server-Token"))
+      .and(query_param("metadata_delivery_reference", "a-reference"))
+      .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+        "TotalCount": 1,
+        "Messages": [{"MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817"}],
+      })))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    assert!(email_client.was_sent("a-reference").await.unwrap());
+  }
+
+  #[test]
+  fn the_encoded_size_of_a_request_is_its_length_as_json() {
+    let email_client = email_client("http://localhost".into());
+    let to = email();
+    let attachments = [
+      EmailAttachment::new("logo.png".into(), "image/png".into(), b"hello", Some("cid:logo".into())),
+      EmailAttachment::new("r\u{e9}sum\u{e9} \"1\".pdf".into(), "application/pdf".into(), b"%PDF", None),
+    ];
+    for attachments in [&attachments[..], &[]] {
+      let message = EmailMessage {
+        to: &to,
+        subject: "A \"quoted\" subject",
+        text_body: "Line one\nLine two",
+        html_body: "<p>Hi</p>",
+        attachments,
+        reference: Some("a-reference"),
+      };
+      let request = email_client.request_for(&message);
+      assert_eq!(request.encoded_size(), serde_json::to_vec(&request).unwrap().len());
+    }
+  }
+
+  #[test]
+  fn batches_are_split_to_stay_under_the_size_limit() {
+    let email_client = email_client("http://localhost".into());
+    let receivers: Vec<_> = (0..5).map(|_| email()).collect();
+    // 20 MB once encoded, so that two messages fit in a batch.
+    let attachments = [EmailAttachment::new("big.pdf".into(), "application/pdf".into(), &vec![0; 15_000_000], None)];
+    let messages: Vec<_> = receivers
+      .iter()
+      .map(|to| EmailMessage { to, subject: "Subject", text_body: "", html_body: "", attachments: &attachments, reference: None })
+      .collect();
+
+    let batch_sizes: Vec<_> = email_client.batches(&messages).iter().map(|batch| batch.len()).collect();
+
+    assert_eq!(batch_sizes, [2, 2, 1]);
+  }
+
+  #[tokio::test]
+  async fn messages_are_rejected_when_postmark_turns_their_batch_down() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+    Mock::given(any())
+      .respond_with(ResponseTemplate::new(422))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let to = email();
+    let message = EmailMessage { to: &to, subject: "Subject", text_body: "", html_body: "", attachments: &[], reference: None };
+    let results = email_client.send_emails(&[message]).await;
+
+    assert!(matches!(results[..], [Err(SendError::Rejected(_))]));
+  }
+
+  #[tokio::test]
+  async fn messages_can_be_retried_when_postmark_fails_or_throttles_their_batch() {
+    for status in [500, 503, 429] {
+      let mock_server = MockServer::start().await;
+      let email_client = email_client(mock_server.uri());
+      Mock::given(any())
+        .respond_with(ResponseTemplate::new(status))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+      let to = email();
+      let message = EmailMessage { to: &to, subject: "Subject", text_body: "", html_body: "", attachments: &[], reference: None };
+      let results = email_client.send_emails(&[message]).await;
+
+      assert!(matches!(results[..], [Err(SendError::Unavailable(_))]), "status {}", status);
+    }
+  }
+
+  #[tokio::test]
+  async fn the_outcome_of_messages_is_unknown_when_the_call_times_out() {
+    let mock_server = MockServer::start().await;
+    let email_client = email_client(mock_server.uri());
+    Mock::given(any())
+      .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180)))
+      .expect(1)
+      .mount(&mock_server)
+      .await;
+
+    let to = email();
+    let message = EmailMessage { to: &to, subject: "Subject", text_body: "", html_body: "", attachments: &[], reference: None };
+    let results = email_client.send_emails(&[message]).await;
+
+    assert!(matches!(results[..], [Err(SendError::Unknown(_))]));
+  }
 }
\ No newline at end of file
@@ -1,110 +1,622 @@
+use std::collections::{hash_map::Entry, HashMap, HashSet};
 use std::time::Duration;
 
-use sqlx::{PgPool, Transaction, Postgres};
-use tracing::{Span, field::display};
+use anyhow::Context;
+use futures_util::future::try_join_all;
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use sqlx::{postgres::PgListener, PgPool};
+use tokio::sync::watch;
 use uuid::Uuid;
 
-use crate::{email_client::EmailClient, domain::SubscriberEmail, configuration::Settings, startup::get_connection_pool};
+use crate::{
+  email_client::{EmailAttachment, EmailClient, EmailMessage, SendError}, domain::SubscriberEmail, configuration::Settings,
+  startup::get_connection_pool, tracking::TrackingLinks, asset_storage::AssetStorage,
+  rate_limiter::RateLimiter, shutdown::Shutdown,
+  welcome_sequences::{schedule_welcome_sequence_steps, try_execute_sequence_task},
+  transactional_emails::try_execute_transactional_task,
+  priority_lanes::{Lane, LaneScheduler},
+  issue_rendering::{
+    hosted_asset_url, render_issue, resolve_assets, rewrite_links, with_tracking_pixel,
+    IssueContent, MergeData, RenderedIssue,
+  },
+};
 
 pub enum ExecutionOutcome {
   TaskCompleted,
   EmptyQueue,
+  /// Nothing can be sent until the send rate allows it again.
+  RateLimited(Duration),
 }
 
-#[tracing::instrument(
-  skip_all,
-  fields(
-    newsletter_issue_id=tracing::field::Empty,
-    subscriber_email=tracing::field::Empty
-  ),
-  err
-)]
+/// A delivery worker: the id its leases are taken under, how many emails
+/// it claims at a time, the send rates it keeps to and the share of its
+/// rounds that serve bulk deliveries first.
+pub struct Worker<'a> {
+  pub id: String,
+  pub batch_size: usize,
+  pub rate_limiter: &'a RateLimiter,
+  pub bulk_min_share: f64,
+}
+
+/// The channel workers listen on to hear about new work.
+pub const DELIVERY_CHANNEL: &str = "delivery_queue";
+
+/// How often idle workers look for work when nothing has woken them up.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often the scheduler closes subject line tests and enqueues the
+/// welcome emails that have fallen due.
+const SCHEDULER_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a claim lasts. It has to outlast sending a whole batch, or
+/// other workers reclaim rows that are still being sent.
+pub const LEASE_DURATION: Duration = Duration::from_secs(300);
+
+/// How long until a worker asks the email provider again whether an
+/// interrupted delivery went out, when it could not tell the first time.
+const INTERRUPTED_DELIVERY_RETRY: Duration = Duration::from_secs(60);
+
+/// How long until a worker tries again the emails the email provider could
+/// not take, as it failed or was throttling us.
+pub const UNAVAILABLE_PROVIDER_RETRY: Duration = Duration::from_secs(60);
+
+/// Runs one round of a worker: sends a batch from `first_lane`, or from the
+/// other lane when it has nothing to send. The bulk lane holds issues, and
+/// welcome emails go out when there are none.
+pub async fn try_execute_round(
+  pool: &PgPool,
+  email_client: &EmailClient,
+  tracking_links: &TrackingLinks,
+  asset_storage: &AssetStorage,
+  base_url: &str,
+  worker: &Worker<'_>,
+  first_lane: Lane,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+  for lane in first_lane.with_fallback() {
+    let outcome = match lane {
+      Lane::Transactional => try_execute_transactional_task(pool, email_client, worker).await?,
+      Lane::Bulk => match try_execute_task(pool, email_client, tracking_links, asset_storage, base_url, worker).await? {
+        ExecutionOutcome::EmptyQueue => try_execute_sequence_task(pool, email_client, worker).await?,
+        outcome => outcome,
+      },
+    };
+    if !matches!(outcome, ExecutionOutcome::EmptyQueue) {
+      return Ok(outcome);
+    }
+  }
+  Ok(ExecutionOutcome::EmptyQueue)
+}
+
+/// Claims a batch of queue rows and sends them with as few calls to the
+/// email provider as possible. No transaction is held open while sending:
+/// rows are leased to the worker, and if the worker dies its lease expires
+/// and another worker picks them up. Rows of throttled domains are left in
+/// the queue, and rows the send rate cannot take yet are put back with a
+/// later next attempt.
+///
+/// Rows are marked as sending right before the call to the provider, and
+/// each message carries its delivery id as metadata. A row reclaimed while
+/// sending is only sent again if the provider has no message with its id,
+/// so a worker dying between sending and recording the outcome does not
+/// send the issue twice. Rows of a call that failed without an answer from
+/// the provider are left sending in the same way, to be checked later, and
+/// rows the provider could not take because it failed or throttled us go
+/// back to the queue to be tried again.
+#[tracing::instrument(skip_all, fields(worker_id=%worker.id), err)]
 pub async fn try_execute_task(
   pool: &PgPool,
   email_client: &EmailClient,
+  tracking_links: &TrackingLinks,
+  asset_storage: &AssetStorage,
+  base_url: &str,
+  worker: &Worker<'_>,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
-  let task = dequeue_task(pool).await?;
-  if task.is_none() {
+  let batch_size = match worker.rate_limiter.allowance(worker.batch_size) {
+    Ok(batch_size) => batch_size,
+    Err(wait) => return Ok(ExecutionOutcome::RateLimited(wait)),
+  };
+  let throttled_domains = worker.rate_limiter.throttled_domains();
+  let tasks = claim_tasks(pool, &worker.id, batch_size as i64, &throttled_domains).await?;
+  if tasks.is_empty() {
     return Ok(ExecutionOutcome::EmptyQueue);
   }
-  let (transaction, issue_id, email) = task.unwrap();
-  Span::current()
-  .record("newsletter_issue_id", &display(issue_id))
-  .record("subscriber_email", &display(&email));
-  match SubscriberEmail::parse(email.clone()) {
-    Ok(email) => {
-      let issue = get_issue(pool, issue_id).await?;
-      if let Err(e) = email_client.send_email(
-        &email, &issue.title, &issue.text_content, &issue.html_content
-      )
-      .await
-      {
-        tracing::error!(
-          error.cause_chain = ?e,
-          error.message = %e,
-          "Failed to deliver issue to a confirmed subscriber.\
-          Skipping.",
-        );
+
+  // Each issue in the batch is loaded once, however many deliveries it has.
+  let mut issues = HashMap::new();
+  let mut deliveries = Vec::with_capacity(tasks.len());
+  let mut deferred = Vec::new();
+  let mut already_sent = Vec::new();
+  for task in tasks {
+    if task.interrupted {
+      match email_client.was_sent(&task.delivery_id.to_string()).await {
+        Ok(true) => {
+          already_sent.push(task);
+          continue;
+        }
+        Ok(false) => {}
+        Err(e) => {
+          tracing::warn!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            delivery_id = %task.delivery_id,
+            "Failed to find out whether an interrupted delivery was sent. Retrying later.",
+          );
+          deferred.push((task, INTERRUPTED_DELIVERY_RETRY));
+          continue;
+        }
+      }
+    }
+    if let Err(wait) = worker.rate_limiter.try_acquire(&task.email) {
+      deferred.push((task, wait));
+      continue;
+    }
+    if let Entry::Vacant(entry) = issues.entry(task.issue_id) {
+      entry.insert(load_issue(pool, asset_storage, task.issue_id).await?);
+    }
+    deliveries.push(prepare_delivery(&issues[&task.issue_id], tracking_links, base_url, task)?);
+  }
+  defer_tasks(pool, &worker.id, deferred).await?;
+  for task in already_sent {
+    tracing::info!(delivery_id = %task.delivery_id, "An interrupted delivery was already sent.");
+    complete_task(pool, &worker.id, &task, DeliveryOutcome::Delivered, task.tracking_token.as_deref()).await?;
+  }
+
+  let deliveries = mark_sending(pool, &worker.id, deliveries).await?;
+  let attempts = send_deliveries(email_client, &issues, &deliveries).await;
+  let mut unknown = Vec::new();
+  let mut unsent = Vec::new();
+  for (delivery, attempt) in deliveries.into_iter().zip(attempts) {
+    match attempt {
+      SendAttempt::Completed(outcome) => {
+        complete_task(pool, &worker.id, &delivery.task, outcome, delivery.tracking_token.as_deref()).await?
       }
+      SendAttempt::Unsent => unsent.push(delivery.task),
+      // The row stays marked as sending, for the provider to be asked
+      // whether it went out once it is claimed again.
+      SendAttempt::Unknown => unknown.push((delivery.task, INTERRUPTED_DELIVERY_RETRY)),
     }
+  }
+  defer_tasks(pool, &worker.id, unknown).await?;
+  requeue_unsent_tasks(pool, &worker.id, unsent, UNAVAILABLE_PROVIDER_RETRY).await?;
+  Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// An issue rendered for one subscriber. There is no message when the
+/// stored address is invalid.
+struct Delivery {
+  task: Task,
+  message: Option<(SubscriberEmail, RenderedIssue)>,
+  tracking_token: Option<String>,
+}
+
+fn prepare_delivery(
+  issue: &LoadedIssue,
+  tracking_links: &TrackingLinks,
+  base_url: &str,
+  task: Task,
+) -> Result<Delivery, anyhow::Error> {
+  let recipient = match SubscriberEmail::parse(task.email.clone()) {
+    Ok(recipient) => recipient,
     Err(e) => {
       tracing::error!(
         error.cause_chain = ?e,
         error.message = %e,
+        newsletter_issue_id = %task.issue_id,
+        subscriber_email = %task.email,
         "Skipping a confirmed subscriber.\
         Their stored contact details are invalid."
       );
+      return Ok(Delivery { task, message: None, tracking_token: None });
+    }
+  };
+  let merge_data = MergeData {
+    name: task.name.clone().unwrap_or_default(),
+    email: task.email.clone(),
+  };
+  let mut rendered = issue.render(task.subject_variant, &merge_data, base_url)?;
+  let mut tracking_token = None;
+  if issue.issue.track_opens || issue.issue.track_clicks {
+    let token = generate_tracking_token();
+    if issue.issue.track_clicks {
+      rendered.html_content = rewrite_links(
+        &rendered.html_content,
+        |link| tracking_links.click_url(&token, link),
+      );
+    }
+    if issue.issue.track_opens {
+      rendered.html_content = with_tracking_pixel(
+        &rendered.html_content,
+        &tracking_links.open_pixel_url(&token),
+      );
     }
+    tracking_token = Some(token);
   }
-  delete_task(transaction, issue_id, &email).await?;
-  Ok(ExecutionOutcome::TaskCompleted)
+  Ok(Delivery { task, message: Some((recipient, rendered)), tracking_token })
 }
 
-type PgTransaction = Transaction<'static, Postgres>;
+/// Sends the deliveries in batches and returns how each attempt went, in
+/// order.
+async fn send_deliveries(
+  email_client: &EmailClient,
+  issues: &HashMap<Uuid, LoadedIssue>,
+  deliveries: &[Delivery],
+) -> Vec<SendAttempt> {
+  let references: Vec<_> = deliveries.iter().map(|delivery| delivery.task.delivery_id.to_string()).collect();
+  let messages: Vec<_> = deliveries
+    .iter()
+    .zip(&references)
+    .filter_map(|(delivery, reference)| {
+      let (recipient, rendered) = delivery.message.as_ref()?;
+      Some(EmailMessage {
+        to: recipient,
+        subject: &rendered.title,
+        text_body: &rendered.text_content,
+        html_body: &rendered.html_content,
+        attachments: &issues[&delivery.task.issue_id].attachments,
+        reference: Some(reference),
+      })
+    })
+    .collect();
 
-#[tracing::instrument(skip_all)]
-async fn dequeue_task(
-  pool: &PgPool
-) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
-  let mut transaction = pool.begin().await?;
-  let r = sqlx::query!(
+  let mut results = email_client.send_emails(&messages).await.into_iter();
+  deliveries
+    .iter()
+    .map(|delivery| {
+      if delivery.message.is_none() {
+        return SendAttempt::Completed(DeliveryOutcome::Failed);
+      }
+      match results.next().expect("Every message has a result.") {
+        Ok(()) => SendAttempt::Completed(DeliveryOutcome::Delivered),
+        Err(SendError::Rejected(reason)) => {
+          tracing::error!(
+            newsletter_issue_id = %delivery.task.issue_id,
+            subscriber_email = %delivery.task.email,
+            reason,
+            "Failed to deliver issue to a confirmed subscriber.\
+            Skipping.",
+          );
+          SendAttempt::Completed(DeliveryOutcome::Failed)
+        }
+        Err(SendError::Unavailable(reason)) => {
+          tracing::warn!(
+            delivery_id = %delivery.task.delivery_id,
+            reason,
+            "The email provider could not take an issue delivery. Retrying later.",
+          );
+          SendAttempt::Unsent
+        }
+        Err(SendError::Unknown(reason)) => {
+          tracing::warn!(
+            delivery_id = %delivery.task.delivery_id,
+            reason,
+            "Could not tell whether an issue was delivered. Checking again later.",
+          );
+          SendAttempt::Unknown
+        }
+      }
+    })
+    .collect()
+}
+
+/// How sending a delivery went, as far as the worker can tell.
+enum SendAttempt {
+  /// The delivery is over, with an outcome to log.
+  Completed(DeliveryOutcome),
+  /// The email provider could not take it, nothing was sent.
+  Unsent,
+  /// It may or may not have been sent.
+  Unknown,
+}
+
+enum DeliveryOutcome {
+  Delivered,
+  Failed,
+}
+
+impl DeliveryOutcome {
+  fn as_str(&self) -> &'static str {
+    match self {
+      DeliveryOutcome::Delivered => "delivered",
+      DeliveryOutcome::Failed => "failed",
+    }
+  }
+}
+
+struct Task {
+  issue_id: Uuid,
+  email: String,
+  name: Option<String>,
+  subject_variant: Option<i16>,
+  delivery_id: Uuid,
+  /// Whether the row was left sending by a worker that stopped mid-send.
+  interrupted: bool,
+  /// The tracking token the email was sent with, if it was.
+  tracking_token: Option<String>,
+}
+
+/// Leases up to `limit` free rows to the worker, skipping the recipients
+/// at `throttled_domains`. A row is free when its next attempt is due and
+/// it has no lease or its lease has expired. Rows being sent stay marked
+/// as such.
+#[tracing::instrument(skip(pool))]
+async fn claim_tasks(
+  pool: &PgPool,
+  worker_id: &str,
+  limit: i64,
+  throttled_domains: &[String],
+) -> Result<Vec<Task>, anyhow::Error> {
+  let claimed = sqlx::query!(
     r#"
-    SELECT newsletter_issue_id, subscriber_email
-    FROM newsletter_delivery_queue
-    FOR UPDATE
-    SKIP LOCKED
-    LIMIT 1
-    "#
+    UPDATE newsletter_delivery_queue
+    SET
+      locked_until = now() + make_interval(secs => $3),
+      locked_by = $1,
+      status = CASE newsletter_delivery_queue.status WHEN 'sending' THEN 'sending' ELSE 'claimed' END
+    FROM (
+      SELECT
+        newsletter_delivery_queue.newsletter_issue_id,
+        newsletter_delivery_queue.subscriber_email
+      FROM newsletter_delivery_queue
+      JOIN newsletter_issues
+        ON newsletter_issues.newsletter_issue_id = newsletter_delivery_queue.newsletter_issue_id
+      WHERE newsletter_issues.delivery_status = 'sending' AND
+      newsletter_delivery_queue.next_attempt_at <= now() AND (
+        newsletter_delivery_queue.locked_until IS NULL OR
+        newsletter_delivery_queue.locked_until < now()
+      ) AND
+      lower(split_part(newsletter_delivery_queue.subscriber_email, '@', 2)) <> ALL($4) AND (
+        newsletter_delivery_queue.subject_variant IS NOT NULL OR
+        newsletter_issues.ab_test_ends_at IS NULL OR
+        newsletter_issues.winning_subject_variant IS NOT NULL
+      )
+      FOR UPDATE OF newsletter_delivery_queue
+      SKIP LOCKED
+      LIMIT $2
+    ) claimed
+    LEFT JOIN subscriptions
+      ON subscriptions.email = claimed.subscriber_email
+    WHERE
+      newsletter_delivery_queue.newsletter_issue_id = claimed.newsletter_issue_id AND
+      newsletter_delivery_queue.subscriber_email = claimed.subscriber_email
+    RETURNING
+      newsletter_delivery_queue.newsletter_issue_id,
+      newsletter_delivery_queue.subscriber_email,
+      newsletter_delivery_queue.subject_variant,
+      newsletter_delivery_queue.delivery_id,
+      newsletter_delivery_queue.status = 'sending' as "interrupted!",
+      newsletter_delivery_queue.tracking_token,
+      subscriptions.name as "subscriber_name?"
+    "#,
+    worker_id,
+    limit,
+    LEASE_DURATION.as_secs_f64(),
+    throttled_domains,
   )
-  .fetch_optional(&mut *transaction)
+  .fetch_all(pool)
   .await?;
 
-  if let Some(r) = r {
-    Ok(Some((
-      transaction,
-      r.newsletter_issue_id,
-      r.subscriber_email,
-    )))
-  } else {
-    Ok(None)
+  Ok(claimed
+    .into_iter()
+    .map(|r| Task {
+      issue_id: r.newsletter_issue_id,
+      email: r.subscriber_email,
+      name: r.subscriber_name,
+      subject_variant: r.subject_variant,
+      delivery_id: r.delivery_id,
+      interrupted: r.interrupted,
+      tracking_token: r.tracking_token,
+    })
+    .collect())
+}
+
+/// Releases rows the send rate could not take, to be claimed again once
+/// their wait is over.
+#[tracing::instrument(skip_all, fields(deferred=deferred.len()))]
+async fn defer_tasks(
+  pool: &PgPool,
+  worker_id: &str,
+  deferred: Vec<(Task, Duration)>,
+) -> Result<(), anyhow::Error> {
+  if deferred.is_empty() {
+    return Ok(());
+  }
+  let mut issue_ids = Vec::with_capacity(deferred.len());
+  let mut emails = Vec::with_capacity(deferred.len());
+  let mut waits = Vec::with_capacity(deferred.len());
+  for (task, wait) in deferred {
+    issue_ids.push(task.issue_id);
+    emails.push(task.email);
+    waits.push(wait.as_secs_f64());
   }
+  sqlx::query!(
+    r#"
+    UPDATE newsletter_delivery_queue
+    SET
+      next_attempt_at = now() + make_interval(secs => deferred.wait),
+      locked_until = NULL,
+      locked_by = NULL,
+      status = CASE newsletter_delivery_queue.status WHEN 'sending' THEN 'sending' ELSE 'queued' END
+    FROM UNNEST($2::uuid[], $3::text[], $4::float8[])
+      AS deferred(newsletter_issue_id, subscriber_email, wait)
+    WHERE
+      newsletter_delivery_queue.newsletter_issue_id = deferred.newsletter_issue_id AND
+      newsletter_delivery_queue.subscriber_email = deferred.subscriber_email AND
+      newsletter_delivery_queue.locked_by = $1
+    "#,
+    worker_id,
+    &issue_ids,
+    &emails,
+    &waits,
+  )
+  .execute(pool)
+  .await?;
+  Ok(())
 }
 
-#[tracing::instrument(skip_all)]
-async fn delete_task(
-  mut transaction: PgTransaction,
-  issue_id: Uuid,
-  email: &str
+/// Puts deliveries the email provider could not take back in the queue,
+/// to be tried again after `wait`. Nothing was sent, so they go back to
+/// queued rather than being checked with the provider.
+#[tracing::instrument(skip_all, fields(unsent=unsent.len()))]
+async fn requeue_unsent_tasks(
+  pool: &PgPool,
+  worker_id: &str,
+  unsent: Vec<Task>,
+  wait: Duration,
 ) -> Result<(), anyhow::Error> {
+  if unsent.is_empty() {
+    return Ok(());
+  }
+  let delivery_ids: Vec<_> = unsent.iter().map(|task| task.delivery_id).collect();
   sqlx::query!(
+    r#"
+    UPDATE newsletter_delivery_queue
+    SET
+      next_attempt_at = now() + make_interval(secs => $3),
+      locked_until = NULL,
+      locked_by = NULL,
+      status = 'queued'
+    WHERE delivery_id = ANY($2) AND locked_by = $1
+    "#,
+    worker_id,
+    &delivery_ids,
+    wait.as_secs_f64(),
+  )
+  .execute(pool)
+  .await?;
+  Ok(())
+}
+
+/// Marks the deliveries about to be sent, along with the tracking token
+/// each goes out with, and keeps those still leased to the worker: one
+/// whose lease ran out may be with another worker by now.
+#[tracing::instrument(skip_all)]
+async fn mark_sending(
+  pool: &PgPool,
+  worker_id: &str,
+  deliveries: Vec<Delivery>,
+) -> Result<Vec<Delivery>, anyhow::Error> {
+  let (delivery_ids, tracking_tokens): (Vec<_>, Vec<_>) = deliveries
+    .iter()
+    .filter(|delivery| delivery.message.is_some())
+    .map(|delivery| (delivery.task.delivery_id, delivery.tracking_token.clone()))
+    .unzip();
+  if delivery_ids.is_empty() {
+    return Ok(deliveries);
+  }
+  let marked: HashSet<_> = sqlx::query!(
+    r#"
+    UPDATE newsletter_delivery_queue
+    SET status = 'sending', tracking_token = sending.tracking_token
+    FROM UNNEST($2::uuid[], $3::text[]) AS sending(delivery_id, tracking_token)
+    WHERE
+      newsletter_delivery_queue.delivery_id = sending.delivery_id AND
+      newsletter_delivery_queue.locked_by = $1 AND
+      newsletter_delivery_queue.locked_until > now()
+    RETURNING newsletter_delivery_queue.delivery_id
+    "#,
+    worker_id,
+    &delivery_ids,
+    &tracking_tokens as &[Option<String>],
+  )
+  .fetch_all(pool)
+  .await?
+  .into_iter()
+  .map(|r| r.delivery_id)
+  .collect();
+
+  Ok(deliveries
+    .into_iter()
+    .filter(|delivery| {
+      let leased = delivery.message.is_none() || marked.contains(&delivery.task.delivery_id);
+      if !leased {
+        tracing::warn!(
+          delivery_id = %delivery.task.delivery_id,
+          "The lease expired before the email was sent, leaving it to the worker that reclaims it.",
+        );
+      }
+      leased
+    })
+    .collect())
+}
+
+/// Removes the worker's row from the queue and logs the outcome. If the
+/// lease expired and another worker reclaimed the row, that worker records
+/// the outcome instead. If the issue was cancelled while the email was being
+/// sent, the real outcome replaces the cancellation.
+#[tracing::instrument(skip_all)]
+async fn complete_task(
+  pool: &PgPool,
+  worker_id: &str,
+  task: &Task,
+  outcome: DeliveryOutcome,
+  tracking_token: Option<&str>,
+) -> Result<(), anyhow::Error> {
+  let mut transaction = pool.begin().await?;
+  let deleted = sqlx::query!(
     r#"
     DELETE FROM newsletter_delivery_queue
     WHERE
       newsletter_issue_id = $1 AND
-      subscriber_email = $2
+      subscriber_email = $2 AND
+      locked_by = $3
     "#,
-    issue_id,
-    email
+    task.issue_id,
+    task.email,
+    worker_id,
+  )
+  .execute(&mut *transaction)
+  .await?
+  .rows_affected();
+
+  if deleted == 0 {
+    let reclaimed = sqlx::query!(
+      r#"
+      SELECT EXISTS (
+        SELECT 1
+        FROM newsletter_delivery_queue
+        WHERE newsletter_issue_id = $1 AND subscriber_email = $2
+      ) as "reclaimed!"
+      "#,
+      task.issue_id,
+      task.email,
+    )
+    .fetch_one(&mut *transaction)
+    .await?
+    .reclaimed;
+    if reclaimed {
+      tracing::warn!("The lease expired before the email was sent, another worker has reclaimed it.");
+      return Ok(());
+    }
+  }
+
+  sqlx::query!(
+    r#"
+    INSERT INTO newsletter_delivery_log (
+      newsletter_issue_id,
+      subscriber_email,
+      outcome,
+      recorded_at,
+      tracking_token,
+      subject_variant,
+      delivery_id
+    )
+    VALUES ($1, $2, $3, now(), $4, $5, $6)
+    ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+    SET
+      outcome = EXCLUDED.outcome,
+      recorded_at = EXCLUDED.recorded_at,
+      tracking_token = EXCLUDED.tracking_token,
+      subject_variant = EXCLUDED.subject_variant,
+      delivery_id = EXCLUDED.delivery_id
+    WHERE newsletter_delivery_log.outcome = 'cancelled'
+    "#,
+    task.issue_id,
+    task.email,
+    outcome.as_str(),
+    tracking_token,
+    task.subject_variant,
+    task.delivery_id,
   )
   .execute(&mut *transaction)
   .await?;
@@ -115,18 +627,84 @@ async fn delete_task(
 
 struct NewsletterIssue {
   title: String,
+  preheader: String,
   text_content: String,
   html_content: String,
+  track_opens: bool,
+  track_clicks: bool,
+  winning_subject_variant: Option<i16>,
+  asset_delivery: String,
 }
 
-async fn get_issue(
+impl NewsletterIssue {
+  fn content(&self) -> IssueContent<'_> {
+    IssueContent {
+      title: &self.title,
+      preheader: &self.preheader,
+      text_content: &self.text_content,
+      html_content: &self.html_content,
+    }
+  }
+}
+
+/// An issue together with its subject lines and attachments, loaded once
+/// per batch.
+struct LoadedIssue {
+  issue: NewsletterIssue,
+  subjects: HashMap<i16, String>,
+  attached_assets: Vec<AttachedAsset>,
+  attachments: Vec<EmailAttachment>,
+}
+
+struct AttachedAsset {
+  asset_id: Uuid,
+  is_image: bool,
+}
+
+impl LoadedIssue {
+  /// Renders the issue for one subscriber, under the subject line of their
+  /// variant. Attached images are shown inline through their content id,
+  /// other files are linked to their hosted copy as well, since mail
+  /// clients cannot link to an attachment.
+  fn render(
+    &self,
+    subject_variant: Option<i16>,
+    merge_data: &MergeData,
+    base_url: &str,
+  ) -> Result<RenderedIssue, anyhow::Error> {
+    let mut content = self.issue.content();
+    if let Some(variant) = subject_variant.or(self.issue.winning_subject_variant) {
+      content.title = self
+        .subjects
+        .get(&variant)
+        .with_context(|| format!("Subject variant {} of the issue is missing.", variant))?;
+    }
+    let rendered = render_issue(&content, merge_data);
+    if self.attached_assets.is_empty() {
+      return Ok(rendered.with_hosted_assets(base_url));
+    }
+    let html_content = resolve_assets(&rendered.html_content, |asset_id| {
+      match self.attached_assets.iter().find(|a| a.asset_id == asset_id) {
+        Some(asset) if asset.is_image => format!("cid:{}", asset_id),
+        _ => hosted_asset_url(base_url, asset_id),
+      }
+    });
+    Ok(RenderedIssue { html_content, ..rendered }.with_hosted_assets(base_url))
+  }
+}
+
+#[tracing::instrument(skip(pool, asset_storage))]
+async fn load_issue(
   pool: &PgPool,
+  asset_storage: &AssetStorage,
   issue_id: Uuid
-) -> Result<NewsletterIssue, anyhow::Error> {
+) -> Result<LoadedIssue, anyhow::Error> {
   let issue = sqlx::query_as!(
     NewsletterIssue,
     r#"
-    SELECT title, text_content, html_content
+    SELECT
+      title, preheader, text_content, html_content, track_opens, track_clicks,
+      winning_subject_variant, asset_delivery
     FROM newsletter_issues
     WHERE
     newsletter_issue_id = $1
@@ -135,30 +713,389 @@ async fn get_issue(
   )
   .fetch_one(pool)
   .await?;
-  Ok(issue)
+
+  let subjects = sqlx::query!(
+    r#"
+    SELECT subject_variant, subject
+    FROM newsletter_subject_variants
+    WHERE newsletter_issue_id = $1
+    "#,
+    issue_id,
+  )
+  .fetch_all(pool)
+  .await?
+  .into_iter()
+  .map(|r| (r.subject_variant, r.subject))
+  .collect();
+
+  let (attached_assets, attachments) = if issue.asset_delivery == "attached" {
+    load_attachments(pool, asset_storage, issue_id).await?
+  } else {
+    (Vec::new(), Vec::new())
+  };
+  Ok(LoadedIssue { issue, subjects, attached_assets, attachments })
 }
 
-async fn worker_loop(
-  pool: PgPool,
-  email_client: EmailClient,
-) -> Result<(), anyhow::Error> {
+async fn load_attachments(
+  pool: &PgPool,
+  asset_storage: &AssetStorage,
+  issue_id: Uuid,
+) -> Result<(Vec<AttachedAsset>, Vec<EmailAttachment>), anyhow::Error> {
+  let assets = sqlx::query!(
+    r#"
+    SELECT asset_id, file_name, content_type, storage_key
+    FROM newsletter_issue_assets
+    JOIN newsletter_assets USING (asset_id)
+    WHERE newsletter_issue_id = $1
+    "#,
+    issue_id,
+  )
+  .fetch_all(pool)
+  .await?;
+
+  let mut attached_assets = Vec::with_capacity(assets.len());
+  let mut attachments = Vec::with_capacity(assets.len());
+  for asset in assets {
+    let content = asset_storage
+      .get(&asset.storage_key)
+      .await?
+      .ok_or_else(|| anyhow::anyhow!("Asset {} is missing from storage.", asset.asset_id))?;
+    let is_image = asset.content_type.starts_with("image/");
+    attachments.push(EmailAttachment::new(
+      asset.file_name,
+      asset.content_type,
+      &content,
+      is_image.then(|| format!("cid:{}", asset.asset_id)),
+    ));
+    attached_assets.push(AttachedAsset { asset_id: asset.asset_id, is_image });
+  }
+  Ok((attached_assets, attachments))
+}
+
+/// Wakes the workers up. Sent from inside a transaction, the notification
+/// only goes out once it commits.
+#[tracing::instrument(skip_all)]
+pub async fn notify_workers<'e, E>(executor: E) -> Result<(), sqlx::Error>
+where
+  E: sqlx::PgExecutor<'e>,
+{
+  sqlx::query!("SELECT pg_notify($1, '')", DELIVERY_CHANNEL)
+    .execute(executor)
+    .await?;
+  Ok(())
+}
+
+/// Wakes every worker whenever new work is announced on the delivery
+/// channel. Notifications sent while the connection is down are lost, so
+/// the workers keep polling as well.
+async fn listen_for_work(pool: &PgPool, wake_up: &watch::Sender<()>) {
   loop {
-    match try_execute_task(&pool, &email_client).await {
-      Ok(ExecutionOutcome::EmptyQueue) => {
-        tokio::time::sleep(Duration::from_secs(10)).await;
+    let mut listener = match PgListener::connect_with(pool).await {
+      Ok(listener) => listener,
+      Err(e) => {
+        tracing::warn!(
+          error.cause_chain = ?e,
+          error.message = %e,
+          "Failed to connect the listener, workers will poll for work.",
+        );
+        tokio::time::sleep(POLL_INTERVAL).await;
+        continue;
       }
-      Err(_) => {
-        tokio::time::sleep(Duration::from_secs(1)).await;
+    };
+    if let Err(e) = listener.listen(DELIVERY_CHANNEL).await {
+      tracing::warn!(
+        error.cause_chain = ?e,
+        error.message = %e,
+        "Failed to listen on the delivery channel, workers will poll for work.",
+      );
+      tokio::time::sleep(POLL_INTERVAL).await;
+      continue;
+    }
+    loop {
+      match listener.try_recv().await {
+        Ok(Some(_)) => {}
+        // The listener reconnects on the next call, in the meantime
+        // a notification may have been missed.
+        Ok(None) => tracing::warn!("The listener lost its connection, reconnecting."),
+        Err(e) => {
+          tracing::warn!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "The listener failed, workers will poll for work until it is back.",
+          );
+          break;
+        }
       }
+      wake_up.send_replace(());
+    }
+    tokio::time::sleep(POLL_INTERVAL).await;
+  }
+}
+
+/// Closes the subject line tests whose window has elapsed, picking the
+/// variant with the best open or click rate among its test deliveries.
+/// Ties go to the earliest variant, i.e. the issue title. The workers are
+/// woken up for the deliveries held back until then.
+#[tracing::instrument(skip_all, err)]
+pub async fn pick_subject_test_winners(pool: &PgPool) -> Result<(), anyhow::Error> {
+  let winners = sqlx::query!(
+    r#"
+    UPDATE newsletter_issues
+    SET winning_subject_variant = (
+      SELECT variants.subject_variant
+      FROM newsletter_subject_variants variants
+      LEFT JOIN newsletter_delivery_log log
+        ON log.newsletter_issue_id = variants.newsletter_issue_id
+        AND log.subject_variant = variants.subject_variant
+        AND log.outcome = 'delivered'
+      WHERE variants.newsletter_issue_id = newsletter_issues.newsletter_issue_id
+      GROUP BY variants.subject_variant
+      ORDER BY
+        COUNT(*) FILTER (WHERE
+          CASE newsletter_issues.ab_test_metric
+            WHEN 'clicks' THEN EXISTS (
+              SELECT 1
+              FROM newsletter_link_clicks clicks
+              WHERE clicks.newsletter_issue_id = log.newsletter_issue_id
+                AND clicks.subscriber_email = log.subscriber_email
+            )
+            ELSE log.first_opened_at IS NOT NULL
+          END
+        )::float8 / GREATEST(COUNT(log.subscriber_email), 1) DESC,
+        variants.subject_variant
+      LIMIT 1
+    )
+    WHERE ab_test_ends_at <= now() AND winning_subject_variant IS NULL
+    RETURNING newsletter_issue_id, winning_subject_variant
+    "#
+  )
+  .fetch_all(pool)
+  .await?;
+
+  for winner in &winners {
+    tracing::info!(
+      newsletter_issue_id = %winner.newsletter_issue_id,
+      winning_subject_variant = ?winner.winning_subject_variant,
+      "Picked the winner of a subject line test."
+    );
+  }
+  if !winners.is_empty() {
+    notify_workers(pool).await?;
+  }
+  Ok(())
+}
+
+/// Runs the periodic jobs the workers depend on, once per process rather
+/// than in every worker round. The jobs log their own errors, and are tried
+/// again on the next tick.
+async fn run_scheduler(pool: &PgPool) {
+  let mut interval = tokio::time::interval(SCHEDULER_INTERVAL);
+  interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+  loop {
+    interval.tick().await;
+    let _ = pick_subject_test_winners(pool).await;
+    let _ = schedule_welcome_sequence_steps(pool).await;
+  }
+}
+
+fn generate_tracking_token() -> String {
+  let mut rng = thread_rng();
+  std::iter::repeat_with(|| rng.sample(Alphanumeric))
+    .map(char::from)
+    .take(25)
+    .collect()
+}
+
+/// What an idle worker waits on besides the clock: new work being
+/// announced, and shutdown.
+struct WorkerSignals {
+  wake_up: watch::Receiver<()>,
+  shutdown: Shutdown,
+}
+
+impl WorkerSignals {
+  /// Waits for `duration`, or less if new work is announced or shutdown is
+  /// requested.
+  async fn idle(&mut self, duration: Duration) {
+    tokio::select! {
+      _ = tokio::time::sleep(duration) => {}
+      _ = self.wake_up.changed() => {}
+      _ = self.shutdown.requested() => {}
+    }
+  }
+
+  /// Waits for `duration`, or less if shutdown is requested.
+  async fn pause(&mut self, duration: Duration) {
+    tokio::select! {
+      _ = tokio::time::sleep(duration) => {}
+      _ = self.shutdown.requested() => {}
+    }
+  }
+}
+
+/// Runs until shutdown is requested. A batch that has been claimed is
+/// always sent and recorded before the worker stops.
+async fn worker_loop(
+  pool: &PgPool,
+  email_client: &EmailClient,
+  tracking_links: &TrackingLinks,
+  asset_storage: &AssetStorage,
+  base_url: &str,
+  worker: Worker<'_>,
+  mut signals: WorkerSignals,
+) -> Result<(), anyhow::Error> {
+  let mut lanes = LaneScheduler::new(worker.bulk_min_share);
+  while !signals.shutdown.is_requested() {
+    // Anything announced from here on is picked up by this round, or wakes
+    // the worker once it goes idle.
+    signals.wake_up.borrow_and_update();
+    let first_lane = lanes.first_lane();
+    let outcome = try_execute_round(
+      pool, email_client, tracking_links, asset_storage, base_url, &worker, first_lane
+    ).await;
+    match outcome {
+      Ok(ExecutionOutcome::EmptyQueue) => signals.idle(POLL_INTERVAL).await,
+      Ok(ExecutionOutcome::RateLimited(wait)) => signals.pause(wait).await,
+      Err(_) => signals.pause(Duration::from_secs(1)).await,
       Ok(ExecutionOutcome::TaskCompleted) => {}
     }
   }
+  tracing::info!(worker_id = %worker.id, "Delivery worker stopped.");
+  Ok(())
+}
+
+/// Hands the rows still leased to the workers back to the queue, for
+/// workers that did not stop within the shutdown timeout. Rows that were
+/// being sent stay marked as such, for the next worker to check whether
+/// they went out.
+#[tracing::instrument(skip(pool))]
+async fn release_leases(pool: &PgPool, worker_ids: &[String]) -> Result<(), anyhow::Error> {
+  let mut transaction = pool.begin().await?;
+  sqlx::query!(
+    r#"
+    UPDATE newsletter_delivery_queue
+    SET
+      locked_until = NULL,
+      locked_by = NULL,
+      status = CASE status WHEN 'sending' THEN 'sending' ELSE 'queued' END
+    WHERE locked_by = ANY($1)
+    "#,
+    worker_ids,
+  )
+  .execute(&mut *transaction)
+  .await?;
+  sqlx::query!(
+    r#"
+    UPDATE welcome_sequence_queue
+    SET locked_until = NULL, locked_by = NULL
+    WHERE locked_by = ANY($1)
+    "#,
+    worker_ids,
+  )
+  .execute(&mut *transaction)
+  .await?;
+  sqlx::query!(
+    r#"
+    UPDATE transactional_email_queue
+    SET locked_until = NULL, locked_by = NULL
+    WHERE locked_by = ANY($1)
+    "#,
+    worker_ids,
+  )
+  .execute(&mut *transaction)
+  .await?;
+  transaction.commit().await?;
+  Ok(())
+}
+
+/// How many rows a delivery queue holds, how many of them are leased to a
+/// worker right now and how many wait on a later next attempt.
+pub struct QueueStatus {
+  pub queue: String,
+  pub queued: i64,
+  pub leased: i64,
+  pub deferred: i64,
+}
+
+#[tracing::instrument(skip_all, err)]
+pub async fn queue_status(pool: &PgPool) -> Result<Vec<QueueStatus>, anyhow::Error> {
+  let status = sqlx::query_as!(
+    QueueStatus,
+    r#"
+    SELECT
+      'newsletter_delivery_queue' AS "queue!",
+      COUNT(*) AS "queued!",
+      COUNT(*) FILTER (WHERE locked_until >= now()) AS "leased!",
+      COUNT(*) FILTER (WHERE next_attempt_at > now()) AS "deferred!"
+    FROM newsletter_delivery_queue
+    UNION ALL
+    SELECT
+      'welcome_sequence_queue',
+      COUNT(*),
+      COUNT(*) FILTER (WHERE locked_until >= now()),
+      COUNT(*) FILTER (WHERE next_attempt_at > now())
+    FROM welcome_sequence_queue
+    UNION ALL
+    SELECT
+      'transactional_email_queue',
+      COUNT(*),
+      COUNT(*) FILTER (WHERE locked_until >= now()),
+      COUNT(*) FILTER (WHERE next_attempt_at > now())
+    FROM transactional_email_queue
+    "#
+  )
+  .fetch_all(pool)
+  .await?;
+  Ok(status)
 }
 
+/// Runs the delivery workers until `shutdown` is requested. Workers finish
+/// the batch they are sending; those still busy when the shutdown timeout
+/// runs out are dropped and their leases released.
 pub async fn run_worker_till_stopped(
-  configuration: Settings
+  configuration: Settings,
+  shutdown: Shutdown,
 ) -> Result<(), anyhow::Error> {
-  let connection_pool = get_connection_pool(&configuration.database);
+  let delivery = configuration.delivery;
+  let connection_pool = get_connection_pool(
+    &configuration.database,
+    delivery.connection_pool_size(),
+  );
   let email_client = configuration.email_client.client();
-  worker_loop(connection_pool, email_client).await
+  let asset_storage = configuration.asset_storage.storage();
+  let rate_limiter = delivery.rate_limiter();
+  let shutdown_timeout = Duration::from_secs(configuration.application.shutdown_timeout_secs);
+  let base_url = configuration.application.base_url;
+  let tracking_links = TrackingLinks::new(
+    base_url.clone(),
+    configuration.application.hmac_secret,
+  );
+  let worker_ids: Vec<_> = (0..delivery.workers).map(|_| Uuid::new_v4().to_string()).collect();
+  let (wake_up, woken_up) = watch::channel(());
+  let workers = try_join_all(worker_ids.iter().map(|worker_id| {
+    let worker = Worker {
+      id: worker_id.clone(),
+      batch_size: delivery.sends_per_worker,
+      rate_limiter: &rate_limiter,
+      bulk_min_share: delivery.bulk_min_share,
+    };
+    let signals = WorkerSignals {
+      wake_up: woken_up.clone(),
+      shutdown: shutdown.clone(),
+    };
+    worker_loop(&connection_pool, &email_client, &tracking_links, &asset_storage, &base_url, worker, signals)
+  }));
+  let mut stopping = shutdown.clone();
+  tokio::select! {
+    outcome = workers => outcome.map(|_| ()),
+    _ = listen_for_work(&connection_pool, &wake_up) => Ok(()),
+    _ = run_scheduler(&connection_pool) => Ok(()),
+    _ = async {
+      stopping.requested().await;
+      tokio::time::sleep(shutdown_timeout).await;
+    } => {
+      tracing::warn!("Delivery workers did not stop in time, releasing their leases.");
+      release_leases(&connection_pool, &worker_ids).await
+    }
+  }
 }
\ No newline at end of file
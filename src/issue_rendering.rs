@@ -0,0 +1,341 @@
+use uuid::Uuid;
+
+pub struct MergeData {
+  pub name: String,
+  pub email: String,
+}
+
+impl MergeData {
+  pub fn sample() -> Self {
+    Self {
+      name: "Ursula Le Guin".into(),
+      email: "ursula_le_guin@example.com".into(),
+    }
+  }
+
+  /// Used when an issue is read on the web rather than delivered to someone.
+  pub fn anonymous() -> Self {
+    Self {
+      name: "reader".into(),
+      email: String::new(),
+    }
+  }
+}
+
+pub struct IssueContent<'a> {
+  pub title: &'a str,
+  /// The preview text shown by inboxes next to the subject. Empty when the
+  /// issue has none.
+  pub preheader: &'a str,
+  pub text_content: &'a str,
+  pub html_content: &'a str,
+}
+
+pub struct RenderedIssue {
+  pub title: String,
+  pub preheader: String,
+  pub text_content: String,
+  pub html_content: String,
+}
+
+/// Replaces the `{{name}}` and `{{email}}` merge tags in an issue with the
+/// details of the subscriber it is being rendered for. The preheader is
+/// added to the HTML body as a hidden leading element.
+pub fn render_issue(content: &IssueContent<'_>, merge_data: &MergeData) -> RenderedIssue {
+  let preheader = merge(content.preheader, &merge_data.name, &merge_data.email);
+  let html_content = merge(
+    content.html_content,
+    &escape_html(&merge_data.name),
+    &escape_html(&merge_data.email),
+  );
+  RenderedIssue {
+    title: merge(content.title, &merge_data.name, &merge_data.email),
+    text_content: merge(content.text_content, &merge_data.name, &merge_data.email),
+    html_content: with_preheader(&html_content, &preheader),
+    preheader,
+  }
+}
+
+impl RenderedIssue {
+  /// Points every asset reference at the copy served by this application.
+  pub fn with_hosted_assets(self, base_url: &str) -> Self {
+    let hosted = |asset_id| hosted_asset_url(base_url, asset_id);
+    Self {
+      text_content: resolve_assets(&self.text_content, hosted),
+      html_content: resolve_assets(&self.html_content, hosted),
+      ..self
+    }
+  }
+}
+
+pub fn hosted_asset_url(base_url: &str, asset_id: Uuid) -> String {
+  format!("{}/assets/{}", base_url, asset_id)
+}
+
+const ASSET_PREFIX: &str = "asset:";
+const ASSET_ID_LENGTH: usize = 36;
+
+/// Lists the uploaded assets an issue refers to through `asset:<id>`.
+pub fn asset_references(content: &str) -> Vec<Uuid> {
+  let mut references = Vec::new();
+  resolve_assets(content, |asset_id| {
+    references.push(asset_id);
+    String::new()
+  });
+  references.sort();
+  references.dedup();
+  references
+}
+
+/// Replaces every `asset:<id>` reference in `content` with the location
+/// returned by `resolve`.
+pub fn resolve_assets(content: &str, mut resolve: impl FnMut(Uuid) -> String) -> String {
+  let mut resolved = String::with_capacity(content.len());
+  let mut position = 0;
+  while let Some(offset) = content[position..].find(ASSET_PREFIX) {
+    let id_start = position + offset + ASSET_PREFIX.len();
+    let asset_id = content
+      .get(id_start..id_start + ASSET_ID_LENGTH)
+      .and_then(|id| Uuid::try_parse(id).ok());
+    match asset_id {
+      Some(asset_id) => {
+        resolved.push_str(&content[position..position + offset]);
+        resolved.push_str(&resolve(asset_id));
+        position = id_start + ASSET_ID_LENGTH;
+      }
+      None => {
+        resolved.push_str(&content[position..id_start]);
+        position = id_start;
+      }
+    }
+  }
+  resolved.push_str(&content[position..]);
+  resolved
+}
+
+fn merge(s: &str, name: &str, email: &str) -> String {
+  s.replace("{{name}}", name).replace("{{email}}", email)
+}
+
+/// Inboxes preview the first text of an email, so the preheader goes right
+/// after the opening body tag, hidden from the rendered message.
+fn with_preheader(html_content: &str, preheader: &str) -> String {
+  if preheader.trim().is_empty() {
+    return html_content.to_owned();
+  }
+  let preheader = format!(
+    r#"<div style="display:none;max-height:0;overflow:hidden;mso-hide:all">{}</div>"#,
+    escape_html(preheader)
+  );
+  let lowercase = html_content.to_ascii_lowercase();
+  let body_start = lowercase
+    .find("<body")
+    .and_then(|i| lowercase[i..].find('>').map(|end| i + end + 1));
+  match body_start {
+    Some(i) => format!("{}{}{}", &html_content[..i], preheader, &html_content[i..]),
+    None => format!("{}{}", preheader, html_content),
+  }
+}
+
+/// Adds an invisible image pointing at `pixel_url` to the end of the body of
+/// an HTML issue, so that loading the images of the email records an open.
+pub fn with_tracking_pixel(html_content: &str, pixel_url: &str) -> String {
+  let pixel = format!(
+    r#"<img src="{}" width="1" height="1" alt="" style="display:none">"#,
+    escape_html(pixel_url)
+  );
+  match html_content.to_ascii_lowercase().rfind("</body>") {
+    Some(i) => format!("{}{}{}", &html_content[..i], pixel, &html_content[i..]),
+    None => format!("{}{}", html_content, pixel),
+  }
+}
+
+/// Passes the target of every absolute `http(s)` link in an HTML issue
+/// through `rewrite`, leaving every other part of the document untouched.
+pub fn rewrite_links(html_content: &str, rewrite: impl Fn(&str) -> String) -> String {
+  let lowercase = html_content.to_ascii_lowercase();
+  let mut rewritten = String::with_capacity(html_content.len());
+  let mut position = 0;
+  while let Some(offset) = lowercase[position..].find("href=") {
+    let value_start = position + offset + "href=".len();
+    let quote = match html_content[value_start..].chars().next() {
+      Some(c @ ('"' | '\'')) => c,
+      _ => {
+        rewritten.push_str(&html_content[position..value_start]);
+        position = value_start;
+        continue;
+      }
+    };
+    let length = match html_content[value_start + 1..].find(quote) {
+      Some(length) => length,
+      None => break,
+    };
+    let value_end = value_start + 1 + length;
+    let link = unescape_html(&html_content[value_start + 1..value_end]);
+    rewritten.push_str(&html_content[position..value_start + 1]);
+    if link.starts_with("http://") || link.starts_with("https://") {
+      rewritten.push_str(&escape_html(&rewrite(&link)));
+    } else {
+      rewritten.push_str(&html_content[value_start + 1..value_end]);
+    }
+    position = value_end;
+  }
+  rewritten.push_str(&html_content[position..]);
+  rewritten
+}
+
+fn unescape_html(s: &str) -> String {
+  s.replace("&quot;", "\"")
+    .replace("&#39;", "'")
+    .replace("&lt;", "<")
+    .replace("&gt;", ">")
+    .replace("&amp;", "&")
+}
+
+pub fn escape_html(s: &str) -> String {
+  let mut escaped = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      '\'' => escaped.push_str("&#39;"),
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    asset_references, render_issue, escape_html, resolve_assets, rewrite_links,
+    with_tracking_pixel, IssueContent, MergeData,
+  };
+  use uuid::Uuid;
+
+  fn merge_data() -> MergeData {
+    MergeData {
+      name: "Le Guin & co".into(),
+      email: "ursula@example.com".into(),
+    }
+  }
+
+  #[test]
+  fn merge_tags_are_replaced_in_every_part() {
+    let content = IssueContent {
+      title: "News for {{name}}",
+      preheader: "",
+      text_content: "Hi {{name}}, this was sent to {{email}}",
+      html_content: "<p>Hi {{name}}</p>",
+    };
+
+    let rendered = render_issue(&content, &merge_data());
+
+    assert_eq!(rendered.title, "News for Le Guin & co");
+    assert_eq!(rendered.text_content, "Hi Le Guin & co, this was sent to ursula@example.com");
+    assert_eq!(rendered.html_content, "<p>Hi Le Guin &amp; co</p>");
+  }
+
+  #[test]
+  fn content_without_merge_tags_is_left_untouched() {
+    let content = IssueContent {
+      title: "Title",
+      preheader: "",
+      text_content: "Plain {name}",
+      html_content: "<p>HTML</p>",
+    };
+
+    let rendered = render_issue(&content, &merge_data());
+
+    assert_eq!(rendered.text_content, "Plain {name}");
+    assert_eq!(rendered.html_content, "<p>HTML</p>");
+  }
+
+  #[test]
+  fn preheader_is_a_hidden_element_at_the_start_of_the_body() {
+    let content = IssueContent {
+      title: "Title",
+      preheader: "Inside: {{name}}",
+      text_content: "Text",
+      html_content: r#"<html><body class="x"><p>Hi</p></body></html>"#,
+    };
+
+    let rendered = render_issue(&content, &merge_data());
+
+    assert_eq!(rendered.preheader, "Inside: Le Guin & co");
+    assert_eq!(
+      rendered.html_content,
+      r#"<html><body class="x"><div style="display:none;max-height:0;overflow:hidden;mso-hide:all">Inside: Le Guin &amp; co</div><p>Hi</p></body></html>"#
+    );
+  }
+
+  #[test]
+  fn preheader_is_prepended_to_html_fragments() {
+    let content = IssueContent {
+      title: "Title",
+      preheader: "Preview",
+      text_content: "Text",
+      html_content: "<p>Hi</p>",
+    };
+
+    let rendered = render_issue(&content, &merge_data());
+
+    assert!(rendered.html_content.starts_with("<div style="));
+    assert!(rendered.html_content.ends_with(">Preview</div><p>Hi</p>"));
+  }
+
+  #[test]
+  fn html_special_characters_are_escaped() {
+    assert_eq!(
+      escape_html(r#"<a href="x">'&'</a>"#),
+      "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+    );
+  }
+
+  #[test]
+  fn tracking_pixel_is_inserted_before_the_closing_body_tag() {
+    let html = with_tracking_pixel("<html><BODY><p>Hi</p></BODY></html>", "http://t/o/a.gif");
+    assert_eq!(
+      html,
+      r#"<html><BODY><p>Hi</p><img src="http://t/o/a.gif" width="1" height="1" alt="" style="display:none"></BODY></html>"#
+    );
+  }
+
+  #[test]
+  fn tracking_pixel_is_appended_to_html_fragments() {
+    let html = with_tracking_pixel("<p>Hi</p>", "http://t/o/a.gif");
+    assert!(html.starts_with("<p>Hi</p><img src=\"http://t/o/a.gif\""));
+  }
+
+  #[test]
+  fn absolute_links_are_rewritten() {
+    let html = rewrite_links(
+      r#"<a href="https://example.com/?a=1&amp;b=2">x</a> <A HREF='http://example.com'>y</A>"#,
+      |link| format!("https://t/?url={}", link),
+    );
+    assert_eq!(
+      html,
+      r#"<a href="https://t/?url=https://example.com/?a=1&amp;b=2">x</a> <A HREF='https://t/?url=http://example.com'>y</A>"#
+    );
+  }
+
+  #[test]
+  fn relative_mailto_and_unquoted_links_are_left_alone() {
+    let html = r#"<a href="/issues">a</a><a href="mailto:me@example.com">b</a><a href=https://x.com>c</a>"#;
+    assert_eq!(rewrite_links(html, |_| "rewritten".into()), html);
+  }
+
+  #[test]
+  fn asset_references_are_resolved() {
+    let asset_id = Uuid::new_v4();
+    let html = format!(r#"<img src="asset:{}"> asset:not-an-id"#, asset_id);
+
+    assert_eq!(asset_references(&html), [asset_id]);
+    assert_eq!(
+      resolve_assets(&html, |id| format!("cid:{}", id)),
+      format!(r#"<img src="cid:{}"> asset:not-an-id"#, asset_id)
+    );
+  }
+}
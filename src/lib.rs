@@ -8,4 +8,14 @@ pub mod authentication;
 pub mod session_state;
 pub mod utils;
 pub mod issue_delivery_workers;
-mod idempotency;
\ No newline at end of file
+pub mod issue_rendering;
+pub mod tracking;
+pub mod asset_storage;
+mod idempotency;
+pub mod text_diff;
+pub mod audit;
+pub mod welcome_sequences;
+pub mod rate_limiter;
+pub mod shutdown;
+pub mod transactional_emails;
+pub mod priority_lanes;
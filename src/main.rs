@@ -1,28 +1,180 @@
+use anyhow::Context;
+use clap::{Parser, Subcommand};
 use scoop::{
-  telemetry::{init_subscriber, get_subscriber}, 
-  configuration::get_configuration, 
-  startup::Application, issue_delivery_workers::run_worker_till_stopped
+  telemetry::{init_subscriber, get_subscriber},
+  configuration::{get_configuration, Settings},
+  startup::{get_connection_pool, Application},
+  issue_delivery_workers::{queue_status, run_worker_till_stopped},
+  shutdown::{shutdown_channel, termination_signal},
+  authentication::create_user, domain::SubscriberEmail,
 };
+use secrecy::Secret;
 use std::fmt::{Debug, Display};
+use std::io::Write;
 use tokio::task::JoinError;
 
+/// A newsletter delivery service. Without a command, runs the API server
+/// and the delivery workers in one process.
+#[derive(Parser)]
+#[command(name = "scoop")]
+struct Cli {
+  #[command(subcommand)]
+  command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+  /// Runs the API server only.
+  Serve,
+  /// Runs the delivery workers only.
+  Worker,
+  /// Applies the pending database migrations.
+  Migrate,
+  /// Adds an admin, reading their password from stdin.
+  CreateAdmin {
+    #[arg(long)]
+    username: String,
+    #[arg(long)]
+    email: Option<String>,
+  },
+  /// Inspects the delivery queues.
+  Queue {
+    #[command(subcommand)]
+    command: QueueCommand,
+  },
+  /// Sends an email through the configured provider to check the setup.
+  SendTestEmail {
+    #[arg(long)]
+    to: String,
+  },
+}
+
+#[derive(Subcommand)]
+enum QueueCommand {
+  /// Counts the queued, leased and deferred emails of each queue.
+  Status,
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-  let subscriber = get_subscriber(
-      "scoop".into(), "info".into(), std::io::stdout
-  );
-  init_subscriber(subscriber);
+  let cli = Cli::parse();
+  let configuration = get_configuration().context("Failed to read user configuration.")?;
 
-  let configuration = get_configuration().expect("Failed to read user configuration.");
-  let application = Application::build(configuration.clone()).await?;
-  let application_task = tokio::spawn(application.run_until_stopped());
-  let worker_task= tokio::spawn(run_worker_till_stopped(configuration));
+  // Long running processes log to stdout, one-off commands keep it for
+  // their output.
+  let Some(command) = cli.command else {
+    init_subscriber(get_subscriber("scoop".into(), "info".into(), std::io::stdout));
+    return run_until_stopped(configuration, true, true).await;
+  };
+  match command {
+    Command::Serve | Command::Worker => {
+      init_subscriber(get_subscriber("scoop".into(), "info".into(), std::io::stdout));
+    }
+    _ => init_subscriber(get_subscriber("scoop".into(), "warn".into(), std::io::stderr)),
+  }
+  match command {
+    Command::Serve => run_until_stopped(configuration, true, false).await,
+    Command::Worker => run_until_stopped(configuration, false, true).await,
+    Command::Migrate => migrate(configuration).await,
+    Command::CreateAdmin { username, email } => create_admin(configuration, &username, email.as_deref()).await,
+    Command::Queue { command: QueueCommand::Status } => print_queue_status(configuration).await,
+    Command::SendTestEmail { to } => send_test_email(configuration, to).await,
+  }
+}
 
-  tokio::select! {
-    o = application_task => report_exit("API", o),
-    o = worker_task => report_exit("Background Worker", o),
+/// Runs the API server, the delivery workers or both until a termination
+/// signal, or until either of them exits on its own. Both are waited on
+/// before returning.
+async fn run_until_stopped(configuration: Settings, api: bool, workers: bool) -> anyhow::Result<()> {
+  let (shutdown_switch, shutdown) = shutdown_channel();
+  let application = if api {
+    Some(Application::build(configuration.clone()).await?)
+  } else {
+    None
   };
+  let server = application.as_ref().map(Application::handle);
+  let application_task = application.map(|application| tokio::spawn(application.run_until_stopped()));
+  let worker_task = workers.then(|| tokio::spawn(run_worker_till_stopped(configuration, shutdown.clone())));
+
+  let mut stopping = shutdown;
+  tokio::join!(
+    async {
+      tokio::select! {
+        _ = termination_signal() => tracing::info!("Received a termination signal, shutting down."),
+        _ = stopping.requested() => {}
+      }
+      shutdown_switch.trigger();
+      if let Some(server) = server {
+        server.stop(true).await;
+      }
+    },
+    async {
+      if let Some(application_task) = application_task {
+        report_exit("API", application_task.await);
+        shutdown_switch.trigger();
+      }
+    },
+    async {
+      if let Some(worker_task) = worker_task {
+        report_exit("Background Worker", worker_task.await);
+        shutdown_switch.trigger();
+      }
+    },
+  );
+
+  Ok(())
+}
+
+async fn migrate(configuration: Settings) -> anyhow::Result<()> {
+  let pool = get_connection_pool(&configuration.database, 1);
+  sqlx::migrate!("./migrations")
+    .run(&pool)
+    .await
+    .context("Failed to migrate the database.")?;
+  println!("The database is up to date.");
+  Ok(())
+}
+
+async fn create_admin(configuration: Settings, username: &str, email: Option<&str>) -> anyhow::Result<()> {
+  eprint!("Password: ");
+  std::io::stderr().flush()?;
+  let mut password = String::new();
+  std::io::stdin().read_line(&mut password).context("Failed to read the password.")?;
+  let password = password.trim_end_matches(['\r', '\n']).to_owned();
+  anyhow::ensure!(
+    (12..=128).contains(&password.chars().count()),
+    "The password must be between 12 and 128 characters long."
+  );
+
+  let pool = get_connection_pool(&configuration.database, 1);
+  let user_id = create_user(username, email, Secret::new(password), &pool).await?;
+  println!("Created admin {} ({}).", username, user_id);
+  Ok(())
+}
+
+async fn print_queue_status(configuration: Settings) -> anyhow::Result<()> {
+  let pool = get_connection_pool(&configuration.database, 1);
+  println!("{:<28}{:>10}{:>10}{:>10}", "QUEUE", "QUEUED", "LEASED", "DEFERRED");
+  for status in queue_status(&pool).await? {
+    println!("{:<28}{:>10}{:>10}{:>10}", status.queue, status.queued, status.leased, status.deferred);
+  }
+  Ok(())
+}
 
+async fn send_test_email(configuration: Settings, to: String) -> anyhow::Result<()> {
+  let receiver = SubscriberEmail::parse(to).map_err(anyhow::Error::msg)?;
+  configuration
+    .email_client
+    .client()
+    .send_email(
+      &receiver,
+      "scoop test email",
+      "This is a test email sent with `scoop send-test-email`.",
+      "<p>This is a test email sent with <code>scoop send-test-email</code>.</p>",
+    )
+    .await
+    .context("Failed to send the test email.")?;
+  println!("Sent a test email to {}.", receiver.as_ref());
   Ok(())
 }
 
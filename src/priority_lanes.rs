@@ -0,0 +1,88 @@
+/// The lanes of outgoing email. Transactional email, which someone is
+/// waiting on, goes ahead of the bulk deliveries of issues and welcome
+/// sequences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+  Transactional,
+  Bulk,
+}
+
+impl Lane {
+  /// The lanes in the order a round tries them, starting with this one.
+  pub fn with_fallback(self) -> [Lane; 2] {
+    match self {
+      Lane::Transactional => [Lane::Transactional, Lane::Bulk],
+      Lane::Bulk => [Lane::Bulk, Lane::Transactional],
+    }
+  }
+}
+
+/// Picks the lane a worker serves first in each round: the transactional
+/// lane, except in at least `bulk_min_share` of the rounds, spread evenly,
+/// so that a steady stream of transactional email cannot starve bulk
+/// deliveries.
+pub struct LaneScheduler {
+  bulk_min_share: f64,
+  bulk_credit: f64,
+}
+
+impl LaneScheduler {
+  pub fn new(bulk_min_share: f64) -> Self {
+    // Checked when the configuration is read.
+    assert!(
+      (0.0..=1.0).contains(&bulk_min_share),
+      "The minimum share of bulk deliveries must be between 0 and 1."
+    );
+    Self { bulk_min_share, bulk_credit: 0.0 }
+  }
+
+  pub fn first_lane(&mut self) -> Lane {
+    self.bulk_credit += self.bulk_min_share;
+    // Leaves room for the rounding of shares like 0.1 adding up.
+    if self.bulk_credit >= 1.0 - 1e-9 {
+      self.bulk_credit -= 1.0;
+      Lane::Bulk
+    } else {
+      Lane::Transactional
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Lane, LaneScheduler};
+
+  fn first_lanes(bulk_min_share: f64, rounds: usize) -> Vec<Lane> {
+    let mut scheduler = LaneScheduler::new(bulk_min_share);
+    (0..rounds).map(|_| scheduler.first_lane()).collect()
+  }
+
+  #[test]
+  fn transactional_email_goes_first_without_a_bulk_share() {
+    assert!(first_lanes(0.0, 100).iter().all(|lane| *lane == Lane::Transactional));
+  }
+
+  #[test]
+  fn bulk_deliveries_go_first_in_their_share_of_rounds() {
+    let lanes = first_lanes(0.2, 10);
+    let bulk_rounds: Vec<_> = (0..10).filter(|round| lanes[*round] == Lane::Bulk).collect();
+    assert_eq!(bulk_rounds, [4, 9]);
+  }
+
+  #[test]
+  fn shares_that_do_not_add_up_exactly_are_kept_to() {
+    let lanes = first_lanes(0.1, 1000);
+    assert_eq!(lanes.iter().filter(|lane| **lane == Lane::Bulk).count(), 100);
+  }
+
+  #[test]
+  fn bulk_deliveries_always_go_first_with_a_full_share() {
+    assert!(first_lanes(1.0, 100).iter().all(|lane| *lane == Lane::Bulk));
+  }
+
+  #[test]
+  #[should_panic]
+  fn shares_above_one_are_rejected() {
+    LaneScheduler::new(1.5);
+  }
+}
\ No newline at end of file
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Keeps outgoing email under a global rate and under the rate of each
+/// throttled recipient domain. Limits are shared by the workers of one
+/// instance only, each allowing a burst of up to a second's worth of
+/// emails: instances running side by side each send at the full rates.
+pub struct RateLimiter {
+  buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+  global: Option<TokenBucket>,
+  domains: HashMap<String, TokenBucket>,
+}
+
+impl RateLimiter {
+  pub fn new(messages_per_second: Option<f64>, domain_limits: &[(String, f64)]) -> Self {
+    let now = Instant::now();
+    Self {
+      buckets: Mutex::new(Buckets {
+        global: messages_per_second.map(|rate| TokenBucket::new(rate, now)),
+        domains: domain_limits
+          .iter()
+          .map(|(domain, rate)| (domain.to_lowercase(), TokenBucket::new(*rate, now)))
+          .collect(),
+      }),
+    }
+  }
+
+  pub fn unlimited() -> Self {
+    Self::new(None, &[])
+  }
+
+  /// How many emails can go out right now, at most `limit`, or how long
+  /// until the next one can.
+  pub fn allowance(&self, limit: usize) -> Result<usize, Duration> {
+    self.allowance_at(limit, Instant::now())
+  }
+
+  /// The domains that cannot take another email right now.
+  pub fn throttled_domains(&self) -> Vec<String> {
+    self.throttled_domains_at(Instant::now())
+  }
+
+  /// Takes a slot for an email to `recipient`, or returns how long until
+  /// one frees up. Nothing is taken when either limit is reached.
+  pub fn try_acquire(&self, recipient: &str) -> Result<(), Duration> {
+    self.try_acquire_at(recipient, Instant::now())
+  }
+
+  fn allowance_at(&self, limit: usize, now: Instant) -> Result<usize, Duration> {
+    let mut buckets = self.buckets.lock().unwrap();
+    match &mut buckets.global {
+      Some(global) => match global.available(now) {
+        0 => Err(global.wait()),
+        available => Ok(available.min(limit)),
+      },
+      None => Ok(limit),
+    }
+  }
+
+  fn throttled_domains_at(&self, now: Instant) -> Vec<String> {
+    let mut buckets = self.buckets.lock().unwrap();
+    buckets
+      .domains
+      .iter_mut()
+      .filter_map(|(domain, bucket)| (bucket.available(now) == 0).then(|| domain.clone()))
+      .collect()
+  }
+
+  fn try_acquire_at(&self, recipient: &str, now: Instant) -> Result<(), Duration> {
+    let domain = recipient_domain(recipient);
+    let mut buckets = self.buckets.lock().unwrap();
+    let Buckets { global, domains } = &mut *buckets;
+    let mut wait = Duration::ZERO;
+    for bucket in global.iter_mut().chain(domains.get_mut(&domain)) {
+      if bucket.available(now) == 0 {
+        wait = wait.max(bucket.wait());
+      }
+    }
+    if wait > Duration::ZERO {
+      return Err(wait);
+    }
+    for bucket in global.iter_mut().chain(domains.get_mut(&domain)) {
+      bucket.tokens -= 1.0;
+    }
+    Ok(())
+  }
+}
+
+struct TokenBucket {
+  rate: f64,
+  capacity: f64,
+  tokens: f64,
+  refilled_at: Instant,
+}
+
+impl TokenBucket {
+  fn new(rate: f64, now: Instant) -> Self {
+    // Checked when the configuration is read.
+    assert!(rate > 0.0, "Send rates must be positive.");
+    let capacity = rate.max(1.0);
+    Self { rate, capacity, tokens: capacity, refilled_at: now }
+  }
+
+  /// Tops the bucket up for the time elapsed and returns how many whole
+  /// emails it allows.
+  fn available(&mut self, now: Instant) -> usize {
+    let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+    self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+    self.refilled_at = now;
+    self.tokens as usize
+  }
+
+  fn wait(&self) -> Duration {
+    Duration::from_secs_f64((1.0 - self.tokens).max(0.0) / self.rate)
+  }
+}
+
+fn recipient_domain(recipient: &str) -> String {
+  recipient
+    .rsplit_once('@')
+    .map(|(_, domain)| domain)
+    .unwrap_or_default()
+    .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, Instant};
+
+  use super::RateLimiter;
+
+  #[test]
+  fn unlimited_senders_are_never_held_back() {
+    let limiter = RateLimiter::unlimited();
+    for _ in 0..1000 {
+      assert!(limiter.try_acquire("someone@gmail.com").is_ok());
+    }
+    assert_eq!(limiter.allowance(100), Ok(100));
+    assert!(limiter.throttled_domains().is_empty());
+  }
+
+  #[test]
+  fn the_global_rate_allows_a_burst_of_one_second() {
+    let limiter = RateLimiter::new(Some(2.0), &[]);
+    let now = Instant::now();
+    assert_eq!(limiter.allowance_at(10, now), Ok(2));
+    assert!(limiter.try_acquire_at("a@example.com", now).is_ok());
+    assert!(limiter.try_acquire_at("b@example.com", now).is_ok());
+
+    assert_eq!(limiter.try_acquire_at("c@example.com", now), Err(Duration::from_millis(500)));
+    assert_eq!(limiter.allowance_at(10, now), Err(Duration::from_millis(500)));
+
+    let later = now + Duration::from_millis(500);
+    assert!(limiter.try_acquire_at("c@example.com", later).is_ok());
+  }
+
+  #[test]
+  fn throttled_domains_do_not_hold_back_other_domains() {
+    let limiter = RateLimiter::new(None, &[("Gmail.com".to_owned(), 1.0)]);
+    let now = Instant::now();
+    assert!(limiter.try_acquire_at("a@gmail.com", now).is_ok());
+
+    assert_eq!(limiter.try_acquire_at("b@GMAIL.com", now), Err(Duration::from_secs(1)));
+    assert_eq!(limiter.throttled_domains_at(now), ["gmail.com"]);
+    assert!(limiter.try_acquire_at("c@example.com", now).is_ok());
+  }
+
+  #[test]
+  fn a_domain_held_back_does_not_use_up_the_global_rate() {
+    let limiter = RateLimiter::new(Some(1.0), &[("gmail.com".to_owned(), 1.0)]);
+    let now = Instant::now();
+    assert!(limiter.try_acquire_at("a@gmail.com", now).is_ok());
+
+    let later = now + Duration::from_millis(500);
+    assert_eq!(limiter.try_acquire_at("b@gmail.com", later), Err(Duration::from_millis(500)));
+    let later = now + Duration::from_secs(1);
+    assert!(limiter.try_acquire_at("c@example.com", later).is_ok());
+  }
+}
\ No newline at end of file
@@ -0,0 +1,82 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::issue_rendering::escape_html;
+use crate::utils::e500;
+
+pub async fn asset_library(
+  pool: web::Data<PgPool>,
+  flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+  let assets = get_assets(&pool).await.map_err(e500)?;
+
+  let mut msg_html = String::new();
+  for m in flash_messages.iter() {
+    writeln!(msg_html,
+      r#"
+      <div class="alert alert-info">
+      <strong>Info!</strong> {}
+      </div>
+      "#,
+      m.content()
+    ).unwrap();
+  }
+
+  let mut rows_html = String::new();
+  for asset in &assets {
+    writeln!(rows_html,
+      r#"
+      <tr>
+        <td><a href="/assets/{}">{}</a></td>
+        <td>{}</td>
+        <td>{} KB</td>
+        <td>{}</td>
+        <td><code>asset:{}</code></td>
+      </tr>
+      "#,
+      asset.asset_id,
+      escape_html(&asset.file_name),
+      escape_html(&asset.content_type),
+      (asset.size_bytes + 1023) / 1024,
+      asset.uploaded_at.format("%Y-%m-%d %H:%M UTC"),
+      asset.asset_id,
+    ).unwrap();
+  }
+  if assets.is_empty() {
+    rows_html.push_str(r#"<tr><td colspan="5">No assets have been uploaded yet.</td></tr>"#);
+  }
+
+  Ok(HttpResponse::Ok()
+    .content_type(ContentType::html())
+    .body(format!(include_str!("assets.html"), msg_html, rows_html))
+  )
+}
+
+struct Asset {
+  asset_id: Uuid,
+  file_name: String,
+  content_type: String,
+  size_bytes: i64,
+  uploaded_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_assets(pool: &PgPool) -> Result<Vec<Asset>, anyhow::Error> {
+  let assets = sqlx::query_as!(
+    Asset,
+    r#"
+    SELECT asset_id, file_name, content_type, size_bytes, uploaded_at
+    FROM newsletter_assets
+    ORDER BY uploaded_at DESC
+    "#
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to retrieve the uploaded assets.")?;
+  Ok(assets)
+}
@@ -0,0 +1,5 @@
+mod get;
+pub use get::asset_library;
+
+mod post;
+pub use post::upload_asset;
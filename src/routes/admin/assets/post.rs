@@ -0,0 +1,92 @@
+use actix_multipart::form::{tempfile::TempFile, MultipartForm};
+use actix_web::web::ReqData;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::asset_storage::{is_allowed_content_type, AssetStorage};
+use crate::authentication::UserId;
+use crate::utils::{e500, see_other};
+
+/// Postmark refuses messages over 10 MB, attachments included, and
+/// attachments grow by a third once base64 encoded.
+#[derive(MultipartForm)]
+pub struct UploadForm {
+  #[multipart(limit = "7MB")]
+  file: TempFile,
+}
+
+#[tracing::instrument(
+  name = "Uploading an asset",
+  skip_all,
+  fields(user_id=%&*user_id, asset_id=tracing::field::Empty)
+)]
+pub async fn upload_asset(
+  form: MultipartForm<UploadForm>,
+  pool: web::Data<PgPool>,
+  asset_storage: web::Data<AssetStorage>,
+  user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let file = form.into_inner().file;
+  let file_name = match file.file_name.as_deref().map(str::trim) {
+    Some(file_name) if !file_name.is_empty() => file_name.to_owned(),
+    _ => {
+      FlashMessage::error("Please choose a file to upload.").send();
+      return Ok(see_other("/admin/assets"));
+    }
+  };
+  let content_type = match file.content_type.map(|mime| mime.essence_str().to_owned()) {
+    Some(content_type) if is_allowed_content_type(&content_type) => content_type,
+    _ => {
+      FlashMessage::error(
+        "Only PNG, JPEG, GIF and WebP images, PDF documents and text files can be uploaded.",
+      )
+      .send();
+      return Ok(see_other("/admin/assets"));
+    }
+  };
+  let content = tokio::fs::read(file.file.path())
+    .await
+    .context("Failed to read the uploaded file.")
+    .map_err(e500)?;
+
+  let asset_id = Uuid::new_v4();
+  tracing::Span::current().record("asset_id", tracing::field::display(asset_id));
+  let storage_key = format!("assets/{}", asset_id);
+  let size_bytes = content.len() as i64;
+  asset_storage.put(&storage_key, &content_type, content)
+    .await
+    .map_err(e500)?;
+  sqlx::query!(
+    r#"
+    INSERT INTO newsletter_assets (
+      asset_id,
+      file_name,
+      content_type,
+      size_bytes,
+      storage_key,
+      uploaded_by,
+      uploaded_at
+    )
+    VALUES ($1, $2, $3, $4, $5, $6, now())
+    "#,
+    asset_id,
+    file_name,
+    content_type,
+    size_bytes,
+    storage_key,
+    *user_id.into_inner(),
+  )
+  .execute(pool.get_ref())
+  .await
+  .context("Failed to store the asset details.")
+  .map_err(e500)?;
+
+  FlashMessage::info(format!(
+    "The file has been uploaded. Reference it as asset:{} in your issues.",
+    asset_id
+  )).send();
+  Ok(see_other("/admin/assets"))
+}
@@ -0,0 +1,318 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::issue_rendering::escape_html;
+use crate::text_diff::{diff_lines, DiffLine};
+use crate::utils::e500;
+
+pub async fn draft_list(
+  pool: web::Data<PgPool>,
+  flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+  let drafts = get_draft_summaries(&pool).await.map_err(e500)?;
+
+  let mut msg_html = String::new();
+  for m in flash_messages.iter() {
+    writeln!(msg_html,
+      r#"
+      <div class="alert alert-info">
+      <strong>Info!</strong> {}
+      </div>
+      "#,
+      m.content()
+    ).unwrap();
+  }
+
+  let mut rows_html = String::new();
+  for draft in &drafts {
+    let status = match draft.published_issue_id {
+      Some(issue_id) => format!(r#"<a href="/admin/issues/{}">Sent</a>"#, issue_id),
+      None => draft.review_status().to_owned(),
+    };
+    writeln!(rows_html,
+      r#"
+      <tr>
+        <td><a href="/admin/newsletters?draft_id={}">{}</a></td>
+        <td>{}</td>
+        <td>{}</td>
+        <td><a href="/admin/drafts/{}/revisions">{}</a></td>
+        <td>{}</td>
+      </tr>
+      "#,
+      draft.draft_id,
+      escape_html(&draft.title),
+      escape_html(&draft.author),
+      format_timestamp(&draft.updated_at),
+      draft.draft_id,
+      draft.revisions,
+      status,
+    ).unwrap();
+  }
+  if drafts.is_empty() {
+    rows_html.push_str(r#"<tr><td colspan="5">No drafts have been saved yet.</td></tr>"#);
+  }
+
+  Ok(HttpResponse::Ok()
+    .content_type(ContentType::html())
+    .body(format!(
+      include_str!("drafts.html"),
+      msg_html = msg_html,
+      rows_html = rows_html,
+    ))
+  )
+}
+
+pub async fn draft_revisions(
+  draft_id: web::Path<Uuid>,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let draft_id = draft_id.into_inner();
+  let published_issue_id = match sqlx::query!(
+    "SELECT published_issue_id FROM newsletter_drafts WHERE draft_id = $1",
+    draft_id,
+  )
+  .fetch_optional(pool.get_ref())
+  .await
+  .context("Failed to retrieve the draft.")
+  .map_err(e500)?
+  {
+    Some(draft) => draft.published_issue_id,
+    None => return Ok(HttpResponse::NotFound().finish()),
+  };
+  let revisions = get_revisions(&pool, draft_id).await.map_err(e500)?;
+  let latest = revisions.last().map_or(0, |r| r.revision);
+
+  // Newest first, each compared with the revision it replaced.
+  let mut revisions_html = String::new();
+  for (i, revision) in revisions.iter().enumerate().rev() {
+    let changes_html = match i.checked_sub(1).map(|i| &revisions[i]) {
+      Some(previous) => render_changes(previous, revision),
+      None => r#"<p class="small-text">First revision.</p>"#.to_owned(),
+    };
+    let restore_html = if published_issue_id.is_none() && revision.revision != latest {
+      format!(
+        r#"
+        <form action="/admin/drafts/{}/revisions/{}/restore" method="post" class="d-inline">
+          <button type="submit" class="btn">Restore</button>
+        </form>
+        "#,
+        draft_id,
+        revision.revision,
+      )
+    } else {
+      String::new()
+    };
+    writeln!(revisions_html,
+      r#"
+      <div class="mb-4">
+        <h5>Revision {}</h5>
+        <p class="small-text">Edited by {}, {}</p>
+        {}
+        {}
+      </div>
+      "#,
+      revision.revision,
+      escape_html(&revision.editor),
+      format_timestamp(&revision.edited_at),
+      changes_html,
+      restore_html,
+    ).unwrap();
+  }
+
+  let status = match published_issue_id {
+    Some(issue_id) => format!(
+      r#"Published as <a href="/admin/issues/{}">an issue</a>, revisions can no longer be restored."#,
+      issue_id
+    ),
+    None => "Draft".to_owned(),
+  };
+  Ok(HttpResponse::Ok()
+    .content_type(ContentType::html())
+    .body(format!(
+      include_str!("revisions.html"),
+      title = escape_html(revisions.last().map_or("", |r| &r.title)),
+      status = status,
+      revisions_html = revisions_html,
+      draft_id = draft_id,
+    ))
+  )
+}
+
+/// Renders what changed between two revisions: short fields and settings as
+/// before and after, content and subject line variants as a line diff.
+fn render_changes(previous: &Revision, revision: &Revision) -> String {
+  let mut html = String::new();
+  let short_fields = [
+    ("Title", previous.title.clone(), revision.title.clone()),
+    ("Preheader", previous.preheader.clone(), revision.preheader.clone()),
+  ]
+  .into_iter()
+  .chain(
+    previous.settings()
+      .into_iter()
+      .zip(revision.settings())
+      .map(|((label, old), (_, new))| (label, old, new))
+  );
+  for (label, old, new) in short_fields {
+    if old != new {
+      writeln!(html,
+        "<p><strong>{}:</strong> <del>{}</del> &rarr; <ins>{}</ins></p>",
+        label,
+        escape_html(&old),
+        escape_html(&new),
+      ).unwrap();
+    }
+  }
+  for (label, old, new) in [
+    ("Text Content", &previous.text_content, &revision.text_content),
+    ("HTML Content", &previous.html_content, &revision.html_content),
+    ("Subject Line Variants", &previous.subject_variants, &revision.subject_variants),
+  ] {
+    if old == new {
+      continue;
+    }
+    let mut diff_html = String::new();
+    for line in diff_lines(old, new) {
+      match line {
+        DiffLine::Unchanged(line) => writeln!(diff_html, "  {}", escape_html(line)),
+        DiffLine::Added(line) => writeln!(diff_html, r#"<span class="diff-added">+ {}</span>"#, escape_html(line)),
+        DiffLine::Removed(line) => writeln!(diff_html, r#"<span class="diff-removed">- {}</span>"#, escape_html(line)),
+      }.unwrap();
+    }
+    writeln!(html,
+      r#"<p><strong>{}:</strong></p><div class="diff">{}</div>"#,
+      label,
+      diff_html,
+    ).unwrap();
+  }
+  if html.is_empty() {
+    html.push_str(r#"<p class="small-text">No changes.</p>"#);
+  }
+  html
+}
+
+fn format_timestamp(timestamp: &DateTime<Utc>) -> String {
+  timestamp.format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+struct DraftSummary {
+  draft_id: Uuid,
+  title: String,
+  author: String,
+  updated_at: DateTime<Utc>,
+  revisions: i64,
+  published_issue_id: Option<Uuid>,
+  review_status: String,
+}
+
+impl DraftSummary {
+  fn review_status(&self) -> &'static str {
+    match self.review_status.as_str() {
+      "submitted" => "Submitted",
+      "approved" => "Approved",
+      "sent" => "Sent",
+      _ => "Draft",
+    }
+  }
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_draft_summaries(pool: &PgPool) -> Result<Vec<DraftSummary>, anyhow::Error> {
+  let drafts = sqlx::query_as!(
+    DraftSummary,
+    r#"
+    SELECT
+      newsletter_drafts.draft_id,
+      newsletter_drafts.title,
+      users.username as author,
+      newsletter_drafts.updated_at,
+      (
+        SELECT COUNT(*)
+        FROM newsletter_draft_revisions
+        WHERE newsletter_draft_revisions.draft_id = newsletter_drafts.draft_id
+      ) as "revisions!",
+      newsletter_drafts.published_issue_id,
+      newsletter_drafts.review_status
+    FROM newsletter_drafts
+    JOIN users ON users.user_id = newsletter_drafts.created_by
+    ORDER BY newsletter_drafts.updated_at DESC
+    "#,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to retrieve the drafts.")?;
+  Ok(drafts)
+}
+
+struct Revision {
+  revision: i32,
+  title: String,
+  preheader: String,
+  text_content: String,
+  html_content: String,
+  subscribers_only: bool,
+  track_opens: bool,
+  track_clicks: bool,
+  subject_variants: String,
+  ab_test_percentage: i32,
+  ab_test_window_minutes: i32,
+  ab_test_metric: String,
+  asset_delivery: String,
+  editor: String,
+  edited_at: DateTime<Utc>,
+}
+
+impl Revision {
+  /// The settings the issue is published with, as shown in the history.
+  fn settings(&self) -> [(&'static str, String); 7] {
+    let on_off = |on: bool| if on { "On" } else { "Off" }.to_owned();
+    [
+      ("Subscribers Only", if self.subscribers_only { "Yes" } else { "No" }.to_owned()),
+      ("Open Tracking", on_off(self.track_opens)),
+      ("Click Tracking", on_off(self.track_clicks)),
+      ("Test Audience", format!("{}%", self.ab_test_percentage)),
+      ("Test Window", format!("{} minutes", self.ab_test_window_minutes)),
+      ("Pick Winner By", self.ab_test_metric.clone()),
+      ("Asset Delivery", self.asset_delivery.clone()),
+    ]
+  }
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_revisions(pool: &PgPool, draft_id: Uuid) -> Result<Vec<Revision>, anyhow::Error> {
+  let revisions = sqlx::query_as!(
+    Revision,
+    r#"
+    SELECT
+      revision,
+      title,
+      preheader,
+      text_content,
+      html_content,
+      subscribers_only,
+      track_opens,
+      track_clicks,
+      subject_variants,
+      ab_test_percentage,
+      ab_test_window_minutes,
+      ab_test_metric,
+      asset_delivery,
+      users.username as editor,
+      edited_at
+    FROM newsletter_draft_revisions
+    JOIN users ON users.user_id = newsletter_draft_revisions.edited_by
+    WHERE draft_id = $1
+    ORDER BY revision
+    "#,
+    draft_id,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to retrieve the draft revisions.")?;
+  Ok(revisions)
+}
@@ -0,0 +1,5 @@
+mod get;
+pub use get::{draft_list, draft_revisions};
+
+mod post;
+pub use post::{approve_draft, create_draft, duplicate_issue, restore_revision, save_draft, submit_draft};
@@ -0,0 +1,622 @@
+use actix_web::web::ReqData;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::Utc;
+use serde::Deserialize;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::audit::{record_issue_event, IssueAction};
+use crate::authentication::UserId;
+use crate::domain::{AssetDelivery, SubjectTestMetric};
+use crate::utils::{e500, see_other};
+
+/// The content of a draft along with the settings it is published with,
+/// posted by the same form as a published issue.
+#[derive(Deserialize)]
+pub struct DraftContent {
+  title: String,
+  #[serde(default)]
+  preheader: String,
+  text_content: String,
+  html_content: String,
+  #[serde(default)]
+  subscribers_only: bool,
+  #[serde(default)]
+  disable_open_tracking: bool,
+  #[serde(default)]
+  disable_click_tracking: bool,
+  #[serde(default)]
+  subject_variants: String,
+  #[serde(default = "default_ab_test_percentage")]
+  ab_test_percentage: i32,
+  #[serde(default = "default_ab_test_window_minutes")]
+  ab_test_window_minutes: i32,
+  #[serde(default)]
+  ab_test_metric: SubjectTestMetric,
+  #[serde(default)]
+  asset_delivery: AssetDelivery,
+}
+
+fn default_ab_test_percentage() -> i32 {
+  20
+}
+
+fn default_ab_test_window_minutes() -> i32 {
+  240
+}
+
+#[tracing::instrument(
+  name = "Creating a draft",
+  skip_all,
+  fields(user_id=%&*user_id)
+)]
+pub async fn create_draft(
+  form: web::Form<DraftContent>,
+  pool: web::Data<PgPool>,
+  user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let mut transaction = pool.begin()
+    .await
+    .context("Failed to acquire a Postgres connection from the pool")
+    .map_err(e500)?;
+  let draft_id = insert_draft(&mut transaction, &form, *user_id.into_inner())
+    .await
+    .context("Failed to store the draft.")
+    .map_err(e500)?;
+  transaction.commit()
+    .await
+    .context("Failed to commit SQL transaction to store a new draft.")
+    .map_err(e500)?;
+
+  FlashMessage::info("The draft has been saved.").send();
+  Ok(see_other(&editor_url(draft_id)))
+}
+
+#[tracing::instrument(
+  name = "Saving a draft",
+  skip(form, pool, user_id),
+  fields(user_id=%&*user_id)
+)]
+pub async fn save_draft(
+  draft_id: web::Path<Uuid>,
+  form: web::Form<DraftContent>,
+  pool: web::Data<PgPool>,
+  user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let draft_id = draft_id.into_inner();
+  let mut transaction = pool.begin()
+    .await
+    .context("Failed to acquire a Postgres connection from the pool")
+    .map_err(e500)?;
+  let revision = match lock_editable_draft(&mut transaction, draft_id).await.map_err(e500)? {
+    DraftLock::Editable(revision) => revision,
+    DraftLock::Published => {
+      FlashMessage::error("This draft has already been published and can no longer be edited.").send();
+      return Ok(see_other(&editor_url(draft_id)));
+    }
+    DraftLock::Missing => return Ok(HttpResponse::NotFound().finish()),
+  };
+
+  if update_draft(&mut transaction, draft_id, revision + 1, &form, *user_id.into_inner())
+    .await
+    .context("Failed to store the new draft revision.")
+    .map_err(e500)?
+  {
+    transaction.commit()
+      .await
+      .context("Failed to commit SQL transaction to store a draft revision.")
+      .map_err(e500)?;
+    FlashMessage::info("The draft has been saved.").send();
+  } else {
+    FlashMessage::info("There were no changes to save.").send();
+  }
+  Ok(see_other(&editor_url(draft_id)))
+}
+
+#[tracing::instrument(
+  name = "Restoring a draft revision",
+  skip(pool, user_id),
+  fields(user_id=%&*user_id)
+)]
+pub async fn restore_revision(
+  path: web::Path<(Uuid, i32)>,
+  pool: web::Data<PgPool>,
+  user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let (draft_id, restored_revision) = path.into_inner();
+  let mut transaction = pool.begin()
+    .await
+    .context("Failed to acquire a Postgres connection from the pool")
+    .map_err(e500)?;
+  let revision = match lock_editable_draft(&mut transaction, draft_id).await.map_err(e500)? {
+    DraftLock::Editable(revision) => revision,
+    DraftLock::Published => {
+      FlashMessage::error("This draft has already been published and can no longer be edited.").send();
+      return Ok(see_other(&editor_url(draft_id)));
+    }
+    DraftLock::Missing => return Ok(HttpResponse::NotFound().finish()),
+  };
+
+  let revision_content = sqlx::query!(
+    r#"
+    SELECT
+      title,
+      preheader,
+      text_content,
+      html_content,
+      subscribers_only,
+      track_opens,
+      track_clicks,
+      subject_variants,
+      ab_test_percentage,
+      ab_test_window_minutes,
+      ab_test_metric,
+      asset_delivery
+    FROM newsletter_draft_revisions
+    WHERE draft_id = $1 AND revision = $2
+    "#,
+    draft_id,
+    restored_revision,
+  )
+  .fetch_optional(&mut *transaction)
+  .await
+  .context("Failed to retrieve the draft revision.")
+  .map_err(e500)?;
+  let content = match revision_content {
+    Some(r) => DraftContent {
+      title: r.title,
+      preheader: r.preheader,
+      text_content: r.text_content,
+      html_content: r.html_content,
+      subscribers_only: r.subscribers_only,
+      disable_open_tracking: !r.track_opens,
+      disable_click_tracking: !r.track_clicks,
+      subject_variants: r.subject_variants,
+      ab_test_percentage: r.ab_test_percentage,
+      ab_test_window_minutes: r.ab_test_window_minutes,
+      ab_test_metric: r.ab_test_metric.try_into().map_err(e500)?,
+      asset_delivery: r.asset_delivery.try_into().map_err(e500)?,
+    },
+    None => return Ok(HttpResponse::NotFound().finish()),
+  };
+
+  if update_draft(&mut transaction, draft_id, revision + 1, &content, *user_id.into_inner())
+    .await
+    .context("Failed to store the restored draft revision.")
+    .map_err(e500)?
+  {
+    transaction.commit()
+      .await
+      .context("Failed to commit SQL transaction to restore a draft revision.")
+      .map_err(e500)?;
+    FlashMessage::info(format!("Revision {} has been restored.", restored_revision)).send();
+  } else {
+    FlashMessage::info(format!(
+      "The draft is already the same as revision {}, there was nothing to restore.",
+      restored_revision
+    ))
+    .send();
+  }
+  Ok(see_other(&editor_url(draft_id)))
+}
+
+#[tracing::instrument(
+  name = "Duplicating an issue as a draft",
+  skip(pool, user_id),
+  fields(user_id=%&*user_id)
+)]
+pub async fn duplicate_issue(
+  issue_id: web::Path<Uuid>,
+  pool: web::Data<PgPool>,
+  user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let issue = sqlx::query!(
+    r#"
+    SELECT
+      title,
+      preheader,
+      text_content,
+      html_content,
+      subscribers_only,
+      track_opens,
+      track_clicks,
+      asset_delivery
+    FROM newsletter_issues
+    WHERE newsletter_issue_id = $1
+    "#,
+    *issue_id,
+  )
+  .fetch_optional(pool.get_ref())
+  .await
+  .context("Failed to retrieve the issue to duplicate.")
+  .map_err(e500)?;
+  // The subject line test belonged to the original send and is not carried
+  // over.
+  let content = match issue {
+    Some(issue) => DraftContent {
+      title: issue.title,
+      preheader: issue.preheader,
+      text_content: issue.text_content,
+      html_content: issue.html_content,
+      subscribers_only: issue.subscribers_only,
+      disable_open_tracking: !issue.track_opens,
+      disable_click_tracking: !issue.track_clicks,
+      subject_variants: String::new(),
+      ab_test_percentage: default_ab_test_percentage(),
+      ab_test_window_minutes: default_ab_test_window_minutes(),
+      ab_test_metric: SubjectTestMetric::default(),
+      asset_delivery: issue.asset_delivery.try_into().map_err(e500)?,
+    },
+    None => return Ok(HttpResponse::NotFound().finish()),
+  };
+
+  let mut transaction = pool.begin()
+    .await
+    .context("Failed to acquire a Postgres connection from the pool")
+    .map_err(e500)?;
+  let user_id = *user_id.into_inner();
+  let draft_id = insert_draft(&mut transaction, &content, user_id)
+    .await
+    .context("Failed to store the duplicated draft.")
+    .map_err(e500)?;
+  record_issue_event(
+    &mut transaction,
+    *issue_id,
+    IssueAction::Duplicated,
+    user_id,
+    &format!("Into draft {}", draft_id),
+    Utc::now(),
+  )
+  .await
+  .context("Failed to record the duplication in the audit trail.")
+  .map_err(e500)?;
+  transaction.commit()
+    .await
+    .context("Failed to commit SQL transaction to store a duplicated draft.")
+    .map_err(e500)?;
+
+  FlashMessage::info("The issue has been duplicated into a new draft.").send();
+  Ok(see_other(&editor_url(draft_id)))
+}
+
+#[tracing::instrument(
+  name = "Submitting a draft for review",
+  skip(pool, user_id),
+  fields(user_id=%&*user_id)
+)]
+pub async fn submit_draft(
+  draft_id: web::Path<Uuid>,
+  pool: web::Data<PgPool>,
+  user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let draft_id = draft_id.into_inner();
+  let mut transaction = pool.begin()
+    .await
+    .context("Failed to acquire a Postgres connection from the pool")
+    .map_err(e500)?;
+  match lock_editable_draft(&mut transaction, draft_id).await.map_err(e500)? {
+    DraftLock::Editable(_) => {}
+    DraftLock::Published => {
+      FlashMessage::error("This draft has already been published.").send();
+      return Ok(see_other(&editor_url(draft_id)));
+    }
+    DraftLock::Missing => return Ok(HttpResponse::NotFound().finish()),
+  }
+
+  let submitted = sqlx::query!(
+    r#"
+    UPDATE newsletter_drafts
+    SET
+      review_status = 'submitted',
+      submitted_by = $2,
+      submitted_at = now()
+    WHERE draft_id = $1 AND review_status = 'draft'
+    "#,
+    draft_id,
+    *user_id.into_inner(),
+  )
+  .execute(&mut transaction)
+  .await
+  .context("Failed to submit the draft for review.")
+  .map_err(e500)?
+  .rows_affected();
+  transaction.commit()
+    .await
+    .context("Failed to commit SQL transaction to submit a draft for review.")
+    .map_err(e500)?;
+
+  if submitted == 1 {
+    FlashMessage::info("The draft has been submitted for review.").send();
+  } else {
+    FlashMessage::error("This draft is already under review.").send();
+  }
+  Ok(see_other(&editor_url(draft_id)))
+}
+
+#[tracing::instrument(
+  name = "Approving a draft",
+  skip(pool, user_id),
+  fields(user_id=%&*user_id)
+)]
+pub async fn approve_draft(
+  draft_id: web::Path<Uuid>,
+  pool: web::Data<PgPool>,
+  user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let draft_id = draft_id.into_inner();
+  let user_id = *user_id.into_inner();
+  let mut transaction = pool.begin()
+    .await
+    .context("Failed to acquire a Postgres connection from the pool")
+    .map_err(e500)?;
+  match lock_editable_draft(&mut transaction, draft_id).await.map_err(e500)? {
+    DraftLock::Editable(_) => {}
+    DraftLock::Published => {
+      FlashMessage::error("This draft has already been published.").send();
+      return Ok(see_other(&editor_url(draft_id)));
+    }
+    DraftLock::Missing => return Ok(HttpResponse::NotFound().finish()),
+  }
+
+  // Anyone who wrote a revision counts as an author of the draft.
+  let is_author = sqlx::query!(
+    r#"
+    SELECT EXISTS (
+      SELECT 1
+      FROM newsletter_draft_revisions
+      WHERE draft_id = $1 AND edited_by = $2
+    ) as "is_author!"
+    "#,
+    draft_id,
+    user_id,
+  )
+  .fetch_one(&mut transaction)
+  .await
+  .context("Failed to retrieve the draft authors.")
+  .map_err(e500)?
+  .is_author;
+  if is_author {
+    FlashMessage::error("Drafts must be approved by an admin who did not edit them.").send();
+    return Ok(see_other(&editor_url(draft_id)));
+  }
+
+  let approved = sqlx::query!(
+    r#"
+    UPDATE newsletter_drafts
+    SET
+      review_status = 'approved',
+      approved_by = $2,
+      approved_at = now()
+    WHERE draft_id = $1 AND review_status = 'submitted'
+    "#,
+    draft_id,
+    user_id,
+  )
+  .execute(&mut transaction)
+  .await
+  .context("Failed to approve the draft.")
+  .map_err(e500)?
+  .rows_affected();
+  transaction.commit()
+    .await
+    .context("Failed to commit SQL transaction to approve a draft.")
+    .map_err(e500)?;
+
+  if approved == 1 {
+    FlashMessage::info("The draft has been approved and can now be sent.").send();
+  } else {
+    FlashMessage::error("Only drafts submitted for review can be approved.").send();
+  }
+  Ok(see_other(&editor_url(draft_id)))
+}
+
+fn editor_url(draft_id: Uuid) -> String {
+  format!("/admin/newsletters?draft_id={}", draft_id)
+}
+
+enum DraftLock {
+  Editable(i32),
+  Published,
+  Missing,
+}
+
+/// Locks the draft for the rest of the transaction so that concurrent edits
+/// get consecutive revision numbers, and returns its latest revision.
+#[tracing::instrument(skip(transaction))]
+async fn lock_editable_draft(
+  transaction: &mut Transaction<'_, Postgres>,
+  draft_id: Uuid,
+) -> Result<DraftLock, anyhow::Error> {
+  let draft = sqlx::query!(
+    r#"
+    SELECT
+      published_issue_id,
+      (
+        SELECT MAX(revision)
+        FROM newsletter_draft_revisions
+        WHERE newsletter_draft_revisions.draft_id = newsletter_drafts.draft_id
+      ) as "revision!"
+    FROM newsletter_drafts
+    WHERE draft_id = $1
+    FOR UPDATE
+    "#,
+    draft_id,
+  )
+  .fetch_optional(&mut *transaction)
+  .await
+  .context("Failed to lock the draft.")?;
+
+  Ok(match draft {
+    None => DraftLock::Missing,
+    Some(draft) if draft.published_issue_id.is_some() => DraftLock::Published,
+    Some(draft) => DraftLock::Editable(draft.revision),
+  })
+}
+
+#[tracing::instrument(skip_all)]
+async fn insert_draft(
+  transaction: &mut Transaction<'_, Postgres>,
+  content: &DraftContent,
+  user_id: Uuid,
+) -> Result<Uuid, sqlx::Error> {
+  let draft_id = Uuid::new_v4();
+  sqlx::query!(
+    r#"
+    INSERT INTO newsletter_drafts (
+      draft_id,
+      title,
+      preheader,
+      text_content,
+      html_content,
+      subscribers_only,
+      track_opens,
+      track_clicks,
+      subject_variants,
+      ab_test_percentage,
+      ab_test_window_minutes,
+      ab_test_metric,
+      asset_delivery,
+      created_by,
+      created_at,
+      updated_at
+    )
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, now(), now())
+    "#,
+    draft_id,
+    content.title,
+    content.preheader,
+    content.text_content,
+    content.html_content,
+    content.subscribers_only,
+    !content.disable_open_tracking,
+    !content.disable_click_tracking,
+    content.subject_variants,
+    content.ab_test_percentage,
+    content.ab_test_window_minutes,
+    content.ab_test_metric.as_str(),
+    content.asset_delivery.as_str(),
+    user_id,
+  )
+  .execute(&mut *transaction)
+  .await?;
+  insert_revision(transaction, draft_id, 1, content, user_id).await?;
+  Ok(draft_id)
+}
+
+/// Stores `content` as the given revision of the draft, unless it is the
+/// same as the current content and settings. Returns whether a revision was
+/// stored. A changed draft has to go through review again.
+#[tracing::instrument(skip(transaction, content))]
+async fn update_draft(
+  transaction: &mut Transaction<'_, Postgres>,
+  draft_id: Uuid,
+  revision: i32,
+  content: &DraftContent,
+  user_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+  let updated = sqlx::query!(
+    r#"
+    UPDATE newsletter_drafts
+    SET
+      title = $2,
+      preheader = $3,
+      text_content = $4,
+      html_content = $5,
+      subscribers_only = $6,
+      track_opens = $7,
+      track_clicks = $8,
+      subject_variants = $9,
+      ab_test_percentage = $10,
+      ab_test_window_minutes = $11,
+      ab_test_metric = $12,
+      asset_delivery = $13,
+      updated_at = now(),
+      review_status = 'draft',
+      submitted_by = NULL,
+      submitted_at = NULL,
+      approved_by = NULL,
+      approved_at = NULL
+    WHERE
+      draft_id = $1 AND
+      (
+        title, preheader, text_content, html_content,
+        subscribers_only, track_opens, track_clicks, subject_variants,
+        ab_test_percentage, ab_test_window_minutes, ab_test_metric, asset_delivery
+      ) IS DISTINCT FROM ($2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+    "#,
+    draft_id,
+    content.title,
+    content.preheader,
+    content.text_content,
+    content.html_content,
+    content.subscribers_only,
+    !content.disable_open_tracking,
+    !content.disable_click_tracking,
+    content.subject_variants,
+    content.ab_test_percentage,
+    content.ab_test_window_minutes,
+    content.ab_test_metric.as_str(),
+    content.asset_delivery.as_str(),
+  )
+  .execute(&mut *transaction)
+  .await?
+  .rows_affected();
+  if updated == 0 {
+    return Ok(false);
+  }
+  insert_revision(transaction, draft_id, revision, content, user_id).await?;
+  Ok(true)
+}
+
+#[tracing::instrument(skip(transaction, content))]
+async fn insert_revision(
+  transaction: &mut Transaction<'_, Postgres>,
+  draft_id: Uuid,
+  revision: i32,
+  content: &DraftContent,
+  user_id: Uuid,
+) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    r#"
+    INSERT INTO newsletter_draft_revisions (
+      draft_id,
+      revision,
+      title,
+      preheader,
+      text_content,
+      html_content,
+      subscribers_only,
+      track_opens,
+      track_clicks,
+      subject_variants,
+      ab_test_percentage,
+      ab_test_window_minutes,
+      ab_test_metric,
+      asset_delivery,
+      edited_by,
+      edited_at
+    )
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, now())
+    "#,
+    draft_id,
+    revision,
+    content.title,
+    content.preheader,
+    content.text_content,
+    content.html_content,
+    content.subscribers_only,
+    !content.disable_open_tracking,
+    !content.disable_click_tracking,
+    content.subject_variants,
+    content.ab_test_percentage,
+    content.ab_test_window_minutes,
+    content.ab_test_metric.as_str(),
+    content.asset_delivery.as_str(),
+    user_id,
+  )
+  .execute(&mut *transaction)
+  .await?;
+  Ok(())
+}
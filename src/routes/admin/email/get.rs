@@ -0,0 +1,54 @@
+use crate::authentication::UserId;
+use crate::issue_rendering::escape_html;
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+pub async fn change_email_form(
+  pool: web::Data<PgPool>,
+  user_id: web::ReqData<UserId>,
+  flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+  let email = get_email(*user_id.into_inner(), &pool).await.map_err(e500)?;
+
+  let mut msg_html = String::new();
+  for m in flash_messages.iter() {
+    writeln!(msg_html,
+      r#"
+      <div class="alert alert-info">
+      <strong>Info!</strong> {}
+      </div>
+      "#,
+      m.content()
+    ).unwrap();
+  }
+  Ok(HttpResponse::Ok()
+    .content_type(ContentType::html())
+    .body(format!(
+      include_str!("email.html"),
+      msg_html,
+      escape_html(email.as_deref().unwrap_or_default()),
+    )
+  ))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_email(user_id: Uuid, pool: &PgPool) -> Result<Option<String>, anyhow::Error> {
+  let row = sqlx::query!(
+    r#"
+    SELECT email
+    FROM users
+    WHERE user_id = $1
+    "#,
+    user_id,
+  )
+  .fetch_one(pool)
+  .await
+  .context("Failed to perform query to retrieve the user's email.")?;
+  Ok(row.email)
+}
\ No newline at end of file
@@ -0,0 +1,5 @@
+mod get;
+pub use get::change_email_form;
+
+mod post;
+pub use post::change_email;
\ No newline at end of file
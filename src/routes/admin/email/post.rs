@@ -0,0 +1,55 @@
+use crate::authentication::UserId;
+use crate::domain::SubscriberEmail;
+use crate::utils::{e500, see_other};
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+  email: String,
+}
+
+#[tracing::instrument(name = "Changing the account email", skip_all, fields(user_id=%&*user_id))]
+pub async fn change_email(
+  form: web::Form<FormData>,
+  pool: web::Data<PgPool>,
+  user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let email = match SubscriberEmail::parse(form.0.email) {
+    Ok(email) => email,
+    Err(_) => {
+      FlashMessage::error("Please enter a valid email address.").send();
+      return Ok(see_other("/admin/email"));
+    }
+  };
+
+  set_email(*user_id.into_inner(), &email, &pool)
+    .await
+    .map_err(e500)?;
+  FlashMessage::info("Your email address has been saved.").send();
+  Ok(see_other("/admin/email"))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn set_email(
+  user_id: Uuid,
+  email: &SubscriberEmail,
+  pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+  sqlx::query!(
+    r#"
+    UPDATE users
+    SET email = $2
+    WHERE user_id = $1
+    "#,
+    user_id,
+    email.as_ref(),
+  )
+  .execute(pool)
+  .await
+  .context("Failed to update the user's email.")?;
+  Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,294 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::issue_rendering::escape_html;
+use crate::utils::e500;
+
+#[derive(Serialize)]
+pub struct IssueAnalytics {
+  newsletter_issue_id: Uuid,
+  title: String,
+  delivered: i64,
+  failed: i64,
+  opened: i64,
+  clicked: i64,
+  unsubscribed: i64,
+  open_rate: f64,
+  click_rate: f64,
+  unsubscribe_rate: f64,
+  top_links: Vec<LinkClicks>,
+  opens_over_time: Vec<OpensBucket>,
+  subject_variants: Vec<SubjectVariantResults>,
+}
+
+#[derive(Serialize)]
+struct LinkClicks {
+  url: String,
+  clicks: i64,
+  unique_clicks: i64,
+}
+
+/// Results of a subject line test. Only deliveries made to the test sample
+/// are counted, so the variants can be compared with each other.
+#[derive(Serialize)]
+struct SubjectVariantResults {
+  subject_variant: i16,
+  subject: String,
+  delivered: i64,
+  opened: i64,
+  clicked: i64,
+  winner: bool,
+}
+
+#[derive(Serialize)]
+struct OpensBucket {
+  hour: DateTime<Utc>,
+  opens: i64,
+}
+
+pub async fn issue_analytics(
+  issue_id: web::Path<Uuid>,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let analytics = match get_issue_analytics(&pool, *issue_id).await.map_err(e500)? {
+    Some(analytics) => analytics,
+    None => return Ok(HttpResponse::NotFound().finish()),
+  };
+
+  let mut links_html = String::new();
+  for link in &analytics.top_links {
+    writeln!(links_html,
+      r#"
+      <tr>
+        <td class="issue-content">{}</td>
+        <td>{}</td>
+        <td>{}</td>
+      </tr>
+      "#,
+      escape_html(&link.url),
+      link.clicks,
+      link.unique_clicks,
+    ).unwrap();
+  }
+  if analytics.top_links.is_empty() {
+    links_html.push_str(r#"<tr><td colspan="3">No links have been clicked yet.</td></tr>"#);
+  }
+
+  let mut opens_html = String::new();
+  for bucket in &analytics.opens_over_time {
+    writeln!(opens_html,
+      "<tr><td>{}</td><td>{}</td></tr>",
+      bucket.hour.format("%Y-%m-%d %H:00 UTC"),
+      bucket.opens,
+    ).unwrap();
+  }
+  if analytics.opens_over_time.is_empty() {
+    opens_html.push_str(r#"<tr><td colspan="2">No opens have been recorded yet.</td></tr>"#);
+  }
+
+  let mut variants_html = String::new();
+  if !analytics.subject_variants.is_empty() {
+    variants_html.push_str(
+      r#"
+      <h5 class="mt-4">Subject Line Test</h5>
+      <table class="table">
+        <thead>
+          <tr>
+            <th>Subject</th>
+            <th>Delivered</th>
+            <th>Opened</th>
+            <th>Clicked</th>
+            <th></th>
+          </tr>
+        </thead>
+        <tbody>
+      "#
+    );
+    for variant in &analytics.subject_variants {
+      writeln!(variants_html,
+        r#"
+          <tr>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td>{}</td>
+          </tr>
+        "#,
+        escape_html(&variant.subject),
+        variant.delivered,
+        variant.opened,
+        variant.clicked,
+        if variant.winner { "Winner" } else { "" },
+      ).unwrap();
+    }
+    variants_html.push_str("</tbody></table>");
+  }
+
+  Ok(HttpResponse::Ok()
+    .content_type(ContentType::html())
+    .body(format!(
+      include_str!("analytics.html"),
+      issue_id = analytics.newsletter_issue_id,
+      title = escape_html(&analytics.title),
+      delivered = analytics.delivered,
+      failed = analytics.failed,
+      opened = analytics.opened,
+      open_rate = analytics.open_rate * 100.0,
+      clicked = analytics.clicked,
+      click_rate = analytics.click_rate * 100.0,
+      unsubscribed = analytics.unsubscribed,
+      unsubscribe_rate = analytics.unsubscribe_rate * 100.0,
+      links_html = links_html,
+      opens_html = opens_html,
+      variants_html = variants_html,
+    ))
+  )
+}
+
+pub async fn issue_analytics_json(
+  issue_id: web::Path<Uuid>,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+  match get_issue_analytics(&pool, *issue_id).await.map_err(e500)? {
+    Some(analytics) => Ok(HttpResponse::Ok().json(analytics)),
+    None => Ok(HttpResponse::NotFound().finish()),
+  }
+}
+
+/// Rates are relative to successful deliveries, as failed or cancelled
+/// recipients never had a chance to open the issue.
+fn rate(count: i64, delivered: i64) -> f64 {
+  if delivered > 0 {
+    count as f64 / delivered as f64
+  } else {
+    0.0
+  }
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_issue_analytics(
+  pool: &PgPool,
+  issue_id: Uuid,
+) -> Result<Option<IssueAnalytics>, anyhow::Error> {
+  let counts = sqlx::query!(
+    r#"
+    SELECT
+      newsletter_issues.title,
+      delivery_log.delivered as "delivered!",
+      delivery_log.failed as "failed!",
+      delivery_log.opened as "opened!",
+      delivery_log.unsubscribed as "unsubscribed!",
+      link_clicks.clicked as "clicked!"
+    FROM newsletter_issues
+    CROSS JOIN LATERAL (
+      SELECT
+        COUNT(*) FILTER (WHERE outcome = 'delivered') as delivered,
+        COUNT(*) FILTER (WHERE outcome = 'failed') as failed,
+        COUNT(*) FILTER (WHERE first_opened_at IS NOT NULL) as opened,
+        COUNT(*) FILTER (WHERE unsubscribed_at IS NOT NULL) as unsubscribed
+      FROM newsletter_delivery_log
+      WHERE newsletter_delivery_log.newsletter_issue_id = newsletter_issues.newsletter_issue_id
+    ) delivery_log
+    CROSS JOIN LATERAL (
+      SELECT COUNT(DISTINCT subscriber_email) as clicked
+      FROM newsletter_link_clicks
+      WHERE newsletter_link_clicks.newsletter_issue_id = newsletter_issues.newsletter_issue_id
+    ) link_clicks
+    WHERE newsletter_issues.newsletter_issue_id = $1
+    "#,
+    issue_id,
+  )
+  .fetch_optional(pool)
+  .await
+  .context("Failed to retrieve the issue engagement counts.")?;
+  let counts = match counts {
+    Some(counts) => counts,
+    None => return Ok(None),
+  };
+
+  let top_links = sqlx::query_as!(
+    LinkClicks,
+    r#"
+    SELECT
+      url,
+      COUNT(*) as "clicks!",
+      COUNT(DISTINCT subscriber_email) as "unique_clicks!"
+    FROM newsletter_link_clicks
+    WHERE newsletter_issue_id = $1
+    GROUP BY url
+    ORDER BY 2 DESC, url
+    LIMIT 10
+    "#,
+    issue_id,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to retrieve the most clicked links.")?;
+
+  let opens_over_time = sqlx::query_as!(
+    OpensBucket,
+    r#"
+    SELECT
+      date_trunc('hour', first_opened_at) as "hour!",
+      COUNT(*) as "opens!"
+    FROM newsletter_delivery_log
+    WHERE newsletter_issue_id = $1 AND first_opened_at IS NOT NULL
+    GROUP BY 1
+    ORDER BY 1
+    "#,
+    issue_id,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to retrieve the opens over time.")?;
+
+  let subject_variants = sqlx::query_as!(
+    SubjectVariantResults,
+    r#"
+    SELECT
+      variants.subject_variant,
+      variants.subject,
+      COUNT(DISTINCT log.subscriber_email) FILTER (WHERE log.outcome = 'delivered') as "delivered!",
+      COUNT(DISTINCT log.subscriber_email) FILTER (WHERE log.first_opened_at IS NOT NULL) as "opened!",
+      COUNT(DISTINCT clicks.subscriber_email) as "clicked!",
+      (variants.subject_variant = newsletter_issues.winning_subject_variant) IS TRUE as "winner!"
+    FROM newsletter_subject_variants variants
+    JOIN newsletter_issues USING (newsletter_issue_id)
+    LEFT JOIN newsletter_delivery_log log
+      ON log.newsletter_issue_id = variants.newsletter_issue_id
+      AND log.subject_variant = variants.subject_variant
+    LEFT JOIN newsletter_link_clicks clicks
+      ON clicks.newsletter_issue_id = log.newsletter_issue_id
+      AND clicks.subscriber_email = log.subscriber_email
+    WHERE variants.newsletter_issue_id = $1
+    GROUP BY variants.subject_variant, variants.subject, newsletter_issues.winning_subject_variant
+    ORDER BY variants.subject_variant
+    "#,
+    issue_id,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to retrieve the subject line test results.")?;
+
+  Ok(Some(IssueAnalytics {
+    newsletter_issue_id: issue_id,
+    title: counts.title,
+    delivered: counts.delivered,
+    failed: counts.failed,
+    opened: counts.opened,
+    clicked: counts.clicked,
+    unsubscribed: counts.unsubscribed,
+    open_rate: rate(counts.opened, counts.delivered),
+    click_rate: rate(counts.clicked, counts.delivered),
+    unsubscribe_rate: rate(counts.unsubscribed, counts.delivered),
+    top_links,
+    opens_over_time,
+    subject_variants,
+  }))
+}
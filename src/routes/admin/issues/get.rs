@@ -0,0 +1,274 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::issue_rendering::escape_html;
+use crate::utils::e500;
+
+pub async fn issue_history(
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let issues = get_issue_summaries(&pool, None).await.map_err(e500)?;
+
+  let mut rows_html = String::new();
+  for issue in &issues {
+    writeln!(rows_html,
+      r#"
+      <tr>
+        <td><a href="/admin/issues/{}">{}</a></td>
+        <td>{}</td>
+        <td>{}</td>
+        <td>{}</td>
+        <td>{}</td>
+        <td>{}</td>
+        <td>{}</td>
+        <td>{}</td>
+        <td>{}</td>
+      </tr>
+      "#,
+      issue.newsletter_issue_id,
+      escape_html(&issue.title),
+      escape_html(issue.author.as_deref().unwrap_or("-")),
+      escape_html(issue.publisher.as_deref().unwrap_or("-")),
+      format_timestamp(&issue.published_at),
+      issue.status(),
+      issue.enqueued,
+      issue.delivered,
+      issue.failed,
+      issue.remaining,
+    ).unwrap();
+  }
+  if issues.is_empty() {
+    rows_html.push_str(r#"<tr><td colspan="9">No issues have been published yet.</td></tr>"#);
+  }
+
+  Ok(HttpResponse::Ok()
+    .content_type(ContentType::html())
+    .body(format!(include_str!("issues.html"), rows_html))
+  )
+}
+
+pub async fn issue_progress(
+  issue_id: web::Path<Uuid>,
+  pool: web::Data<PgPool>,
+  flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+  let issue = match get_issue_summaries(&pool, Some(*issue_id))
+    .await
+    .map_err(e500)?
+    .pop()
+  {
+    Some(issue) => issue,
+    None => return Ok(HttpResponse::NotFound().finish()),
+  };
+
+  let mut msg_html = String::new();
+  for m in flash_messages.iter() {
+    writeln!(msg_html,
+      r#"
+      <div class="alert alert-info">
+      <strong>Info!</strong> {}
+      </div>
+      "#,
+      m.content()
+    ).unwrap();
+  }
+
+  let refresh_html = if issue.status() == "Sending" {
+    r#"<meta http-equiv="refresh" content="5">"#
+  } else {
+    ""
+  };
+  let progress = if issue.enqueued > 0 {
+    (issue.enqueued - issue.remaining) * 100 / issue.enqueued
+  } else {
+    100
+  };
+
+  let mut actions_html = String::new();
+  let actions: &[(&str, &str)] = match issue.delivery_status.as_str() {
+    "sending" if issue.remaining > 0 => &[("pause", "Pause"), ("cancel", "Cancel")],
+    "paused" => &[("resume", "Resume"), ("cancel", "Cancel")],
+    _ => &[],
+  };
+  for (action, label) in actions {
+    writeln!(actions_html,
+      r#"
+      <form action="/admin/issues/{}/{}" method="post" class="d-inline">
+        <button type="submit" class="btn">{}</button>
+      </form>
+      "#,
+      issue.newsletter_issue_id,
+      action,
+      label,
+    ).unwrap();
+  }
+
+  let mut audit_html = String::new();
+  for event in get_issue_events(&pool, issue.newsletter_issue_id).await.map_err(e500)? {
+    writeln!(audit_html,
+      r#"
+      <tr>
+        <td>{}</td>
+        <td>{}</td>
+        <td>{}</td>
+        <td>{}</td>
+      </tr>
+      "#,
+      format_timestamp(&event.occurred_at),
+      event.action,
+      escape_html(event.username.as_deref().unwrap_or("-")),
+      escape_html(&event.details),
+    ).unwrap();
+  }
+
+  Ok(HttpResponse::Ok()
+    .content_type(ContentType::html())
+    .body(format!(
+      include_str!("issue.html"),
+      refresh_html = refresh_html,
+      issue_id = issue.newsletter_issue_id,
+      msg_html = msg_html,
+      title = escape_html(&issue.title),
+      author = escape_html(issue.author.as_deref().unwrap_or("-")),
+      publisher = escape_html(issue.publisher.as_deref().unwrap_or("-")),
+      published_at = format_timestamp(&issue.published_at),
+      idempotency_key = escape_html(issue.idempotency_key.as_deref().unwrap_or("-")),
+      approval = match (&issue.approved_by, &issue.approved_at) {
+        (Some(approver), Some(approved_at)) => format!(
+          "Approved by {} on {}",
+          escape_html(approver),
+          format_timestamp(approved_at),
+        ),
+        _ => "Sent without approval".to_owned(),
+      },
+      status = issue.status(),
+      progress = progress,
+      enqueued = issue.enqueued,
+      delivered = issue.delivered,
+      audit_html = audit_html,
+      failed = issue.failed,
+      cancelled = issue.cancelled,
+      remaining = issue.remaining,
+      actions_html = actions_html,
+    ))
+  )
+}
+
+fn format_timestamp(timestamp: &DateTime<Utc>) -> String {
+  timestamp.format("%Y-%m-%d %H:%M UTC").to_string()
+}
+
+struct IssueSummary {
+  newsletter_issue_id: Uuid,
+  title: String,
+  author: Option<String>,
+  publisher: Option<String>,
+  published_at: DateTime<Utc>,
+  idempotency_key: Option<String>,
+  approved_by: Option<String>,
+  approved_at: Option<DateTime<Utc>>,
+  delivery_status: String,
+  enqueued: i64,
+  delivered: i64,
+  failed: i64,
+  cancelled: i64,
+  remaining: i64,
+}
+
+impl IssueSummary {
+  fn status(&self) -> &'static str {
+    match self.delivery_status.as_str() {
+      "paused" => "Paused",
+      "cancelled" => "Cancelled",
+      _ if self.remaining > 0 => "Sending",
+      _ => "Finished",
+    }
+  }
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_issue_summaries(
+  pool: &PgPool,
+  issue_id: Option<Uuid>,
+) -> Result<Vec<IssueSummary>, anyhow::Error> {
+  let issues = sqlx::query_as!(
+    IssueSummary,
+    r#"
+    SELECT
+      newsletter_issues.newsletter_issue_id,
+      newsletter_issues.title,
+      users.username as "author?",
+      publishers.username as "publisher?",
+      newsletter_issues.published_at,
+      newsletter_issues.idempotency_key,
+      approvers.username as "approved_by?",
+      newsletter_issues.approved_at,
+      newsletter_issues.delivery_status,
+      (delivery_log.logged + delivery_queue.remaining) as "enqueued!",
+      delivery_log.delivered as "delivered!",
+      delivery_log.failed as "failed!",
+      delivery_log.cancelled as "cancelled!",
+      delivery_queue.remaining as "remaining!"
+    FROM newsletter_issues
+    LEFT JOIN users ON users.user_id = newsletter_issues.author_id
+    LEFT JOIN users publishers ON publishers.user_id = newsletter_issues.published_by
+    LEFT JOIN users approvers ON approvers.user_id = newsletter_issues.approved_by
+    CROSS JOIN LATERAL (
+      SELECT
+        COUNT(*) as logged,
+        COUNT(*) FILTER (WHERE outcome = 'delivered') as delivered,
+        COUNT(*) FILTER (WHERE outcome = 'failed') as failed,
+        COUNT(*) FILTER (WHERE outcome = 'cancelled') as cancelled
+      FROM newsletter_delivery_log
+      WHERE newsletter_delivery_log.newsletter_issue_id = newsletter_issues.newsletter_issue_id
+    ) delivery_log
+    CROSS JOIN LATERAL (
+      SELECT COUNT(*) as remaining
+      FROM newsletter_delivery_queue
+      WHERE newsletter_delivery_queue.newsletter_issue_id = newsletter_issues.newsletter_issue_id
+    ) delivery_queue
+    WHERE $1::uuid IS NULL OR newsletter_issues.newsletter_issue_id = $1
+    ORDER BY newsletter_issues.published_at DESC
+    "#,
+    issue_id,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to retrieve the issue history.")?;
+  Ok(issues)
+}
+
+struct IssueEvent {
+  action: String,
+  username: Option<String>,
+  details: String,
+  occurred_at: DateTime<Utc>,
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_issue_events(pool: &PgPool, issue_id: Uuid) -> Result<Vec<IssueEvent>, anyhow::Error> {
+  let events = sqlx::query_as!(
+    IssueEvent,
+    r#"
+    SELECT
+      newsletter_issue_events.action,
+      users.username as "username?",
+      newsletter_issue_events.details,
+      newsletter_issue_events.occurred_at
+    FROM newsletter_issue_events
+    LEFT JOIN users ON users.user_id = newsletter_issue_events.user_id
+    WHERE newsletter_issue_events.newsletter_issue_id = $1
+    ORDER BY newsletter_issue_events.occurred_at
+    "#,
+    issue_id,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to retrieve the audit trail of the issue.")?;
+  Ok(events)
+}
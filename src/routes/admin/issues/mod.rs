@@ -0,0 +1,8 @@
+mod analytics;
+pub use analytics::{issue_analytics, issue_analytics_json};
+
+mod get;
+pub use get::{issue_history, issue_progress};
+
+mod post;
+pub use post::{cancel_issue, pause_issue, resume_issue};
@@ -0,0 +1,194 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::audit::{record_issue_event, IssueAction};
+use crate::authentication::UserId;
+use crate::issue_delivery_workers::notify_workers;
+use crate::utils::{e500, see_other};
+
+#[tracing::instrument(
+  name = "Pausing an issue delivery",
+  skip(pool, user_id),
+  fields(user_id=%&*user_id)
+)]
+pub async fn pause_issue(
+  issue_id: web::Path<Uuid>,
+  pool: web::Data<PgPool>,
+  user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let issue_id = issue_id.into_inner();
+  let mut transaction = pool.begin()
+    .await
+    .context("Failed to acquire a Postgres connection from pool")
+    .map_err(e500)?;
+  let updated = set_delivery_status(&mut *transaction, issue_id, &["sending"], "paused")
+    .await
+    .map_err(e500)?;
+  if !updated {
+    FlashMessage::error("Only an issue that is sending can be paused.").send();
+    return Ok(see_other(&format!("/admin/issues/{}", issue_id)));
+  }
+  record_issue_event(&mut *transaction, issue_id, IssueAction::Paused, **user_id, "", Utc::now())
+    .await
+    .context("Failed to record the pause in the audit trail.")
+    .map_err(e500)?;
+  transaction.commit()
+    .await
+    .context("Failed to commit the transaction to database.")
+    .map_err(e500)?;
+  FlashMessage::info("The delivery has been paused.").send();
+  Ok(see_other(&format!("/admin/issues/{}", issue_id)))
+}
+
+#[tracing::instrument(
+  name = "Resuming an issue delivery",
+  skip(pool, user_id),
+  fields(user_id=%&*user_id)
+)]
+pub async fn resume_issue(
+  issue_id: web::Path<Uuid>,
+  pool: web::Data<PgPool>,
+  user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let issue_id = issue_id.into_inner();
+  let mut transaction = pool.begin()
+    .await
+    .context("Failed to acquire a Postgres connection from pool")
+    .map_err(e500)?;
+  let updated = set_delivery_status(&mut *transaction, issue_id, &["paused"], "sending")
+    .await
+    .map_err(e500)?;
+  if !updated {
+    FlashMessage::error("Only a paused issue can be resumed.").send();
+    return Ok(see_other(&format!("/admin/issues/{}", issue_id)));
+  }
+  record_issue_event(&mut *transaction, issue_id, IssueAction::Resumed, **user_id, "", Utc::now())
+    .await
+    .context("Failed to record the resume in the audit trail.")
+    .map_err(e500)?;
+  notify_workers(&mut *transaction)
+    .await
+    .context("Failed to notify the delivery workers.")
+    .map_err(e500)?;
+  transaction.commit()
+    .await
+    .context("Failed to commit the transaction to database.")
+    .map_err(e500)?;
+  FlashMessage::info("The delivery has been resumed.").send();
+  Ok(see_other(&format!("/admin/issues/{}", issue_id)))
+}
+
+#[tracing::instrument(
+  name = "Cancelling an issue delivery",
+  skip(pool, user_id),
+  fields(user_id=%&*user_id)
+)]
+pub async fn cancel_issue(
+  issue_id: web::Path<Uuid>,
+  pool: web::Data<PgPool>,
+  user_id: web::ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let issue_id = issue_id.into_inner();
+  let mut transaction = pool.begin()
+    .await
+    .context("Failed to acquire a Postgres connection from pool")
+    .map_err(e500)?;
+  let updated = set_delivery_status(&mut *transaction, issue_id, &["sending", "paused"], "cancelled")
+    .await
+    .map_err(e500)?;
+  if !updated {
+    FlashMessage::error("Only an issue that is sending or paused can be cancelled.").send();
+    return Ok(see_other(&format!("/admin/issues/{}", issue_id)));
+  }
+  let n_cancelled = cancel_pending_deliveries(&mut transaction, issue_id)
+    .await
+    .context("Failed to cancel the pending deliveries.")
+    .map_err(e500)?;
+  record_issue_event(
+    &mut *transaction,
+    issue_id,
+    IssueAction::Cancelled,
+    **user_id,
+    &format!("{} pending emails cancelled", n_cancelled),
+    Utc::now(),
+  )
+  .await
+  .context("Failed to record the cancellation in the audit trail.")
+  .map_err(e500)?;
+  transaction.commit()
+    .await
+    .context("Failed to commit the transaction to database.")
+    .map_err(e500)?;
+  FlashMessage::info(format!(
+    "The delivery has been cancelled - {} pending emails will not be sent.",
+    n_cancelled
+  )).send();
+  Ok(see_other(&format!("/admin/issues/{}", issue_id)))
+}
+
+#[tracing::instrument(skip(executor))]
+async fn set_delivery_status<'e, E>(
+  executor: E,
+  issue_id: Uuid,
+  from: &[&str],
+  to: &str,
+) -> Result<bool, anyhow::Error>
+where
+  E: sqlx::PgExecutor<'e>,
+{
+  let from: Vec<String> = from.iter().map(|s| s.to_string()).collect();
+  let result = sqlx::query!(
+    r#"
+    UPDATE newsletter_issues
+    SET delivery_status = $3
+    WHERE
+      newsletter_issue_id = $1 AND
+      delivery_status = ANY($2)
+    "#,
+    issue_id,
+    &from,
+    to,
+  )
+  .execute(executor)
+  .await
+  .context("Failed to update the delivery status of the issue.")?;
+  Ok(result.rows_affected() > 0)
+}
+
+/// Moves the tasks still waiting in the queue to the delivery log, so that
+/// cancelled deliveries remain visible instead of silently disappearing.
+/// Tasks that a worker is currently sending are logged as cancelled too,
+/// and the worker records the real outcome once the send completes.
+#[tracing::instrument(skip(transaction))]
+async fn cancel_pending_deliveries(
+  transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+  issue_id: Uuid,
+) -> Result<u64, sqlx::Error> {
+  let result = sqlx::query!(
+    r#"
+    WITH cancelled AS (
+      DELETE FROM newsletter_delivery_queue
+      WHERE newsletter_issue_id = $1
+      RETURNING newsletter_issue_id, subscriber_email, delivery_id
+    )
+    INSERT INTO newsletter_delivery_log (
+      newsletter_issue_id,
+      subscriber_email,
+      outcome,
+      recorded_at,
+      delivery_id
+    )
+    SELECT newsletter_issue_id, subscriber_email, 'cancelled', now(), delivery_id
+    FROM cancelled
+    ON CONFLICT DO NOTHING
+    "#,
+    issue_id,
+  )
+  .execute(transaction)
+  .await?;
+  Ok(result.rows_affected())
+}
@@ -1,7 +1,17 @@
 mod dashboard;
 mod password;
+mod email;
 mod newsletter;
+mod issues;
+mod assets;
+mod drafts;
+mod sequences;
 
 pub use dashboard::admin_dashboard;
 pub use password::*;
-pub use newsletter::*;
\ No newline at end of file
+pub use email::*;
+pub use newsletter::*;
+pub use issues::*;
+pub use assets::*;
+pub use drafts::*;
+pub use sequences::*;
\ No newline at end of file
@@ -1,11 +1,22 @@
-use actix_web::{HttpResponse, http::header::ContentType};
+use actix_web::{HttpResponse, http::header::ContentType, web};
 use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
 
-use crate::{session_state::TypedSession, utils::{see_other, e500}};
+use crate::{issue_rendering::escape_html, session_state::TypedSession, utils::{see_other, e500}};
 use std::fmt::Write;
 
+#[derive(Deserialize)]
+pub struct EditorParameters {
+  draft_id: Option<Uuid>,
+}
+
 pub async fn newsletter_form(
   session: TypedSession,
+  parameters: web::Query<EditorParameters>,
+  pool: web::Data<PgPool>,
   flash_messages: IncomingFlashMessages,
 ) -> Result<HttpResponse, actix_web::Error> {
   if session.get_user_id().map_err(e500)?.is_none() {
@@ -23,9 +34,156 @@ pub async fn newsletter_form(
       m.content()
     ).unwrap();
   }
+
+  let draft = match parameters.draft_id {
+    Some(draft_id) => match get_draft(&pool, draft_id).await.map_err(e500)? {
+      Some(draft) => Some(draft),
+      None => return Ok(HttpResponse::NotFound().finish()),
+    },
+    None => None,
+  };
+  let (draft_html, save_draft_action) = match &draft {
+    Some(draft) => (
+      format!(
+        r#"
+        <p class="small-text">Editing draft revision {} &middot; <a href="/admin/drafts/{}/revisions">Revision history</a></p>
+        <p class="small-text">{}</p>
+        {}
+        "#,
+        draft.revision,
+        draft.draft_id,
+        draft.review_summary(),
+        draft.review_action_html(),
+      ),
+      format!("/admin/drafts/{}", draft.draft_id),
+    ),
+    None => (String::new(), "/admin/drafts".to_owned()),
+  };
+
   let idempotency_key = uuid::Uuid::new_v4();
   Ok(HttpResponse::Ok()
     .content_type(ContentType::html())
-    .body(format!(include_str!("newsletter.html"), msg_html, idempotency_key))
+    .body(format!(
+      include_str!("newsletter.html"),
+      msg_html = msg_html,
+      draft_html = draft_html,
+      title = escape_html(draft.as_ref().map_or("", |d| &d.title)),
+      preheader = escape_html(draft.as_ref().map_or("", |d| &d.preheader)),
+      text_content = escape_html(draft.as_ref().map_or("", |d| &d.text_content)),
+      html_content = escape_html(draft.as_ref().map_or("", |d| &d.html_content)),
+      subscribers_only_checked = checked(draft.as_ref().is_some_and(|d| d.subscribers_only)),
+      disable_open_tracking_checked = checked(draft.as_ref().is_some_and(|d| !d.track_opens)),
+      disable_click_tracking_checked = checked(draft.as_ref().is_some_and(|d| !d.track_clicks)),
+      subject_variants = escape_html(draft.as_ref().map_or("", |d| &d.subject_variants)),
+      ab_test_percentage = draft.as_ref().map_or(20, |d| d.ab_test_percentage),
+      ab_test_window_minutes = draft.as_ref().map_or(240, |d| d.ab_test_window_minutes),
+      clicks_selected = selected(draft.as_ref().is_some_and(|d| d.ab_test_metric == "clicks")),
+      attached_selected = selected(draft.as_ref().is_some_and(|d| d.asset_delivery == "attached")),
+      draft_id_html = draft.as_ref().map_or(String::new(), |d| format!(
+        r#"<input hidden type="text" name="draft_id" value="{}">"#,
+        d.draft_id
+      )),
+      save_draft_action = save_draft_action,
+      idempotency_key = idempotency_key,
+    ))
+  )
+}
+
+fn checked(on: bool) -> &'static str {
+  if on { " checked" } else { "" }
+}
+
+fn selected(on: bool) -> &'static str {
+  if on { " selected" } else { "" }
+}
+
+struct Draft {
+  draft_id: Uuid,
+  title: String,
+  preheader: String,
+  text_content: String,
+  html_content: String,
+  subscribers_only: bool,
+  track_opens: bool,
+  track_clicks: bool,
+  subject_variants: String,
+  ab_test_percentage: i32,
+  ab_test_window_minutes: i32,
+  ab_test_metric: String,
+  asset_delivery: String,
+  revision: i32,
+  review_status: String,
+  submitted_by: Option<String>,
+  approved_by: Option<String>,
+}
+
+impl Draft {
+  fn review_summary(&self) -> String {
+    let submitted_by = escape_html(self.submitted_by.as_deref().unwrap_or("-"));
+    let approved_by = escape_html(self.approved_by.as_deref().unwrap_or("-"));
+    match self.review_status.as_str() {
+      "submitted" => format!("Submitted for review by {}, awaiting approval from another admin.", submitted_by),
+      "approved" => format!("Submitted by {} and approved by {}, ready to send.", submitted_by, approved_by),
+      "sent" => "Sent, this draft can no longer be edited.".to_owned(),
+      _ => "Not yet submitted for review.".to_owned(),
+    }
+  }
+
+  fn review_action_html(&self) -> String {
+    let (action, label) = match self.review_status.as_str() {
+      "draft" => ("submit", "Submit for Review"),
+      "submitted" => ("approve", "Approve"),
+      _ => return String::new(),
+    };
+    format!(
+      r#"
+      <form action="/admin/drafts/{}/{}" method="post" class="mb-3">
+        <button type="submit" class="btn">{}</button>
+      </form>
+      "#,
+      self.draft_id,
+      action,
+      label,
+    )
+  }
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_draft(pool: &PgPool, draft_id: Uuid) -> Result<Option<Draft>, anyhow::Error> {
+  let draft = sqlx::query_as!(
+    Draft,
+    r#"
+    SELECT
+      draft_id,
+      title,
+      preheader,
+      text_content,
+      html_content,
+      subscribers_only,
+      track_opens,
+      track_clicks,
+      subject_variants,
+      ab_test_percentage,
+      ab_test_window_minutes,
+      ab_test_metric,
+      asset_delivery,
+      (
+        SELECT MAX(revision)
+        FROM newsletter_draft_revisions
+        WHERE newsletter_draft_revisions.draft_id = newsletter_drafts.draft_id
+      ) as "revision!",
+      review_status,
+      submitters.username as "submitted_by?",
+      approvers.username as "approved_by?"
+    FROM newsletter_drafts
+    LEFT JOIN users submitters ON submitters.user_id = newsletter_drafts.submitted_by
+    LEFT JOIN users approvers ON approvers.user_id = newsletter_drafts.approved_by
+    WHERE draft_id = $1
+    "#,
+    draft_id,
   )
-}
\ No newline at end of file
+  .fetch_optional(pool)
+  .await
+  .context("Failed to retrieve the draft.")?;
+  Ok(draft)
+}
@@ -2,4 +2,7 @@ mod get;
 pub use get::newsletter_form;
 
 mod post;
-pub use post::publish_newsletter;
\ No newline at end of file
+pub use post::publish_newsletter;
+
+mod preview;
+pub use preview::{preview_newsletter, send_test_newsletter};
\ No newline at end of file
@@ -1,11 +1,18 @@
+use crate::audit::{record_issue_event, IssueAction};
 use crate::authentication::UserId;
+use crate::domain::{AssetDelivery, IssueSlug, SubjectTest, SubjectTestMetric};
+use crate::email_client::MAX_MESSAGE_BYTES;
+use crate::issue_delivery_workers::notify_workers;
+use crate::issue_rendering::asset_references;
 use crate::idempotency::{IdempotencyKey, save_response, try_processing, NextAction};
+use crate::startup::RequireIssueApproval;
 use crate::utils::{see_other, e400};
 use crate::utils::e500;
 use actix_web::web::ReqData;
 use actix_web::{web, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use serde::Deserialize;
 use sqlx::{PgPool, Transaction, Postgres};
 use uuid::Uuid;
@@ -13,11 +20,39 @@ use uuid::Uuid;
 #[derive(Deserialize)]
 pub struct FormData {
   title: String,
+  #[serde(default)]
+  preheader: String,
   text_content: String,
   html_content: String,
+  #[serde(default)]
+  subscribers_only: bool,
+  #[serde(default)]
+  disable_open_tracking: bool,
+  #[serde(default)]
+  disable_click_tracking: bool,
+  #[serde(default)]
+  subject_variants: String,
+  #[serde(default = "default_ab_test_percentage")]
+  ab_test_percentage: i32,
+  #[serde(default = "default_ab_test_window_minutes")]
+  ab_test_window_minutes: i32,
+  #[serde(default)]
+  ab_test_metric: SubjectTestMetric,
+  #[serde(default)]
+  asset_delivery: AssetDelivery,
+  #[serde(default)]
+  draft_id: Option<Uuid>,
   idempotency_key: String,
 }
 
+fn default_ab_test_percentage() -> i32 {
+  20
+}
+
+fn default_ab_test_window_minutes() -> i32 {
+  240
+}
+
 #[tracing::instrument(
   name = "Publishing a newsletter issue",
   skip_all
@@ -27,15 +62,52 @@ pub async fn publish_newsletter(
   form: web::Form<FormData>, 
   pool: web::Data<PgPool>,
   user_id: ReqData<UserId>,
+  require_approval: web::Data<RequireIssueApproval>,
 ) -> Result<HttpResponse, actix_web::Error> {
   let user_id = user_id.into_inner();
-  let FormData {
-    title,
-    text_content,
-    html_content,
-    idempotency_key
-  } = form.0;
-  let idempotency_key:IdempotencyKey = idempotency_key.try_into().map_err(e400)?;
+  let form = form.0;
+  if require_approval.0 && form.draft_id.is_none() {
+    return Err(e400(
+      "Issues must be approved by a second admin before they are sent. \
+      Save the issue as a draft and submit it for review."
+    ));
+  }
+  let idempotency_key:IdempotencyKey = form.idempotency_key.clone().try_into().map_err(e400)?;
+  let subject_test = SubjectTest::parse(
+    &form.title,
+    &form.subject_variants,
+    form.ab_test_percentage,
+    form.ab_test_window_minutes,
+    form.ab_test_metric,
+  )
+  .map_err(e400)?;
+  let mut asset_ids = asset_references(&form.text_content);
+  asset_ids.extend(asset_references(&form.html_content));
+  asset_ids.sort();
+  asset_ids.dedup();
+  let unknown_assets = find_unknown_assets(&pool, &asset_ids)
+    .await
+    .map_err(e500)?;
+  if !unknown_assets.is_empty() {
+    return Err(e400(format!(
+      "The issue references assets that do not exist: {}",
+      unknown_assets.iter().map(Uuid::to_string).collect::<Vec<_>>().join(", ")
+    )));
+  }
+  if form.asset_delivery == AssetDelivery::Attached {
+    let attachments_bytes = encoded_assets_size(&pool, &asset_ids)
+      .await
+      .map_err(e500)?;
+    let message_bytes = attachments_bytes + form.text_content.len() + form.html_content.len();
+    if message_bytes > MAX_MESSAGE_BYTES {
+      return Err(e400(format!(
+        "The issue would make emails of {:.1} MB with its assets attached, over the {} MB \
+        an email can carry. Link to the hosted copies of the assets instead.",
+        message_bytes as f64 / 1_000_000.0,
+        MAX_MESSAGE_BYTES / 1_000_000,
+      )));
+    }
+  }
   let mut transaction = match try_processing(&pool, &idempotency_key, *user_id)
     .await
     .map_err(e500)? 
@@ -47,17 +119,58 @@ pub async fn publish_newsletter(
     }
   };
 
-  let issue_id = insert_newsletter_issue(
-      &mut transaction,
-      &title,
-      &text_content,
-      &html_content
-    )
+  let issue_id = insert_newsletter_issue(&mut transaction, &form, *user_id, idempotency_key.as_ref())
     .await
     .context("Failed to store newsletter issue details.")
     .map_err(e500)?;
+
+  let mut details = String::new();
+  if let Some(draft_id) = form.draft_id {
+    let review = mark_draft_published(&mut transaction, draft_id, issue_id, &form, require_approval.0)
+      .await
+      .context("Failed to mark the draft as published.")
+      .map_err(e500)?;
+    let review = match review {
+      Some(review) => review,
+      None => return Err(e400(if require_approval.0 {
+        "The draft does not exist, has already been published, \
+        or was not approved as it is."
+      } else {
+        "The draft does not exist or has already been published."
+      })),
+    };
+    for (action, user_id, occurred_at) in [
+      (IssueAction::Submitted, review.submitted_by, review.submitted_at),
+      (IssueAction::Approved, review.approved_by, review.approved_at),
+    ] {
+      if let (Some(user_id), Some(occurred_at)) = (user_id, occurred_at) {
+        record_issue_event(&mut transaction, issue_id, action, user_id, "", occurred_at)
+          .await
+          .context("Failed to record the review of the issue.")
+          .map_err(e500)?;
+      }
+    }
+    details = format!("From draft {}", draft_id);
+  }
+
+  record_issue_event(&mut transaction, issue_id, IssueAction::Published, *user_id, &details, Utc::now())
+    .await
+    .context("Failed to record the publication of the issue.")
+    .map_err(e500)?;
+
+  link_assets(&mut transaction, issue_id, &asset_ids)
+    .await
+    .context("Failed to link the issue to its assets.")
+    .map_err(e500)?;
+
+  if let Some(subject_test) = &subject_test {
+    insert_subject_test(&mut transaction, issue_id, subject_test)
+      .await
+      .context("Failed to store the subject line test.")
+      .map_err(e500)?;
+  }
   
-  enqueue_deliver_tasks(&mut transaction, issue_id)
+  enqueue_deliver_tasks(&mut transaction, issue_id, subject_test.as_ref())
     .await
     .context("Failed to enqueue delivery tasks.")
     .map_err(e500)?;
@@ -77,29 +190,125 @@ fn success_message() -> FlashMessage {
   )
 }
 
+struct DraftReview {
+  submitted_by: Option<Uuid>,
+  submitted_at: Option<DateTime<Utc>>,
+  approved_by: Option<Uuid>,
+  approved_at: Option<DateTime<Utc>>,
+}
+
+/// Links the draft to the issue published from it, which locks the draft,
+/// and copies its author and review onto the issue. Returns `None` if the
+/// draft is unknown or was published already. When approval is required the
+/// draft must also be approved, with the same content and settings as the
+/// issue, so that nothing goes out that the reviewer did not see.
+#[tracing::instrument(skip(transaction, form))]
+async fn mark_draft_published(
+  transaction: &mut Transaction<'_, Postgres>,
+  draft_id: Uuid,
+  issue_id: Uuid,
+  form: &FormData,
+  require_approval: bool,
+) -> Result<Option<DraftReview>, sqlx::Error> {
+  sqlx::query_as!(
+    DraftReview,
+    r#"
+    WITH published_draft AS (
+      UPDATE newsletter_drafts
+      SET
+        published_issue_id = $2,
+        review_status = 'sent'
+      WHERE
+        draft_id = $1 AND
+        published_issue_id IS NULL AND
+        (
+          NOT $3 OR (
+            review_status = 'approved' AND
+            (
+              title, preheader, text_content, html_content,
+              subscribers_only, track_opens, track_clicks, subject_variants,
+              ab_test_percentage, ab_test_window_minutes, ab_test_metric, asset_delivery
+            ) = ($4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+          )
+        )
+      RETURNING created_by, submitted_by, submitted_at, approved_by, approved_at
+    )
+    UPDATE newsletter_issues
+    SET
+      author_id = published_draft.created_by,
+      submitted_by = published_draft.submitted_by,
+      submitted_at = published_draft.submitted_at,
+      approved_by = published_draft.approved_by,
+      approved_at = published_draft.approved_at
+    FROM published_draft
+    WHERE newsletter_issue_id = $2
+    RETURNING
+      newsletter_issues.submitted_by,
+      newsletter_issues.submitted_at,
+      newsletter_issues.approved_by,
+      newsletter_issues.approved_at
+    "#,
+    draft_id,
+    issue_id,
+    require_approval,
+    form.title,
+    form.preheader,
+    form.text_content,
+    form.html_content,
+    form.subscribers_only,
+    !form.disable_open_tracking,
+    !form.disable_click_tracking,
+    form.subject_variants,
+    form.ab_test_percentage,
+    form.ab_test_window_minutes,
+    form.ab_test_metric.as_str(),
+    form.asset_delivery.as_str(),
+  )
+  .fetch_optional(transaction)
+  .await
+}
+
 #[tracing::instrument(skip_all)]
 async fn insert_newsletter_issue(
   transaction: &mut Transaction<'_, Postgres>,
-  title: &str,
-  text_content: &str,
-  html_content: &str,
+  form: &FormData,
+  publisher_id: Uuid,
+  idempotency_key: &str,
 ) -> Result<Uuid, sqlx::Error> {
   let newsletter_issue_id = Uuid::new_v4();
+  let slug = IssueSlug::new(&form.title, newsletter_issue_id);
   sqlx::query!(
     r#"
     INSERT INTO newsletter_issues (
       newsletter_issue_id,
       title,
+      preheader,
       text_content,
       html_content,
-      published_at
+      published_at,
+      slug,
+      subscribers_only,
+      track_opens,
+      track_clicks,
+      author_id,
+      published_by,
+      idempotency_key,
+      asset_delivery
     )
-    VALUES ($1, $2, $3, $4, now())
+    VALUES ($1, $2, $3, $4, $5, now(), $6, $7, $8, $9, $10, $10, $11, $12)
     "#,
     newsletter_issue_id,
-    title,
-    text_content,
-    html_content,
+    form.title,
+    form.preheader.trim(),
+    form.text_content,
+    form.html_content,
+    slug.as_ref(),
+    form.subscribers_only,
+    !form.disable_open_tracking,
+    !form.disable_click_tracking,
+    publisher_id,
+    idempotency_key,
+    form.asset_delivery.as_str(),
   )
   .execute(transaction)
   .await?;
@@ -107,24 +316,148 @@ async fn insert_newsletter_issue(
   Ok(newsletter_issue_id)
 }
 
+#[tracing::instrument(skip(pool))]
+async fn find_unknown_assets(
+  pool: &PgPool,
+  asset_ids: &[Uuid],
+) -> Result<Vec<Uuid>, anyhow::Error> {
+  let unknown = sqlx::query!(
+    r#"
+    SELECT referenced.asset_id as "asset_id!"
+    FROM UNNEST($1::uuid[]) AS referenced(asset_id)
+    LEFT JOIN newsletter_assets USING (asset_id)
+    WHERE newsletter_assets.asset_id IS NULL
+    "#,
+    asset_ids,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to look up the referenced assets.")?;
+  Ok(unknown.into_iter().map(|r| r.asset_id).collect())
+}
+
+/// How large the assets are once base64 encoded, as they are when attached
+/// to an email.
+#[tracing::instrument(skip(pool))]
+async fn encoded_assets_size(
+  pool: &PgPool,
+  asset_ids: &[Uuid],
+) -> Result<usize, anyhow::Error> {
+  let encoded_bytes = sqlx::query!(
+    r#"
+    SELECT COALESCE(SUM(4 * ((size_bytes + 2) / 3)), 0)::bigint as "encoded_bytes!"
+    FROM newsletter_assets
+    WHERE asset_id = ANY($1)
+    "#,
+    asset_ids,
+  )
+  .fetch_one(pool)
+  .await
+  .context("Failed to add up the size of the referenced assets.")?
+  .encoded_bytes;
+  Ok(encoded_bytes as usize)
+}
+
+#[tracing::instrument(skip_all)]
+async fn link_assets(
+  transaction: &mut Transaction<'_, Postgres>,
+  newsletter_issue_id: Uuid,
+  asset_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    r#"
+    INSERT INTO newsletter_issue_assets (newsletter_issue_id, asset_id)
+    SELECT $1, UNNEST($2::uuid[])
+    "#,
+    newsletter_issue_id,
+    asset_ids,
+  )
+  .execute(transaction)
+  .await?;
+  Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn insert_subject_test(
+  transaction: &mut Transaction<'_, Postgres>,
+  newsletter_issue_id: Uuid,
+  subject_test: &SubjectTest,
+) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    r#"
+    UPDATE newsletter_issues
+    SET
+      ab_test_ends_at = now() + make_interval(mins => $2),
+      ab_test_metric = $3
+    WHERE newsletter_issue_id = $1
+    "#,
+    newsletter_issue_id,
+    subject_test.window_minutes(),
+    subject_test.metric().as_str(),
+  )
+  .execute(&mut *transaction)
+  .await?;
+
+  for (variant, subject) in subject_test.subjects().iter().enumerate() {
+    sqlx::query!(
+      r#"
+      INSERT INTO newsletter_subject_variants (
+        newsletter_issue_id,
+        subject_variant,
+        subject
+      )
+      VALUES ($1, $2, $3)
+      "#,
+      newsletter_issue_id,
+      variant as i16,
+      subject,
+    )
+    .execute(&mut *transaction)
+    .await?;
+  }
+  Ok(())
+}
+
+/// When a subject line test runs, a random sample of the audience is spread
+/// over the variants. The remaining deliveries have no variant and are held
+/// back until a winner has been picked.
 #[tracing::instrument(skip_all)]
 async fn enqueue_deliver_tasks(
   transaction: &mut Transaction<'_, Postgres>,
   newsletter_issue_id: Uuid,
+  subject_test: Option<&SubjectTest>,
 ) -> Result<(), sqlx::Error> {
+  let (test_percentage, variants) = match subject_test {
+    Some(subject_test) => (subject_test.test_percentage(), subject_test.subjects().len() as i32),
+    None => (0, 1),
+  };
   sqlx::query!(
     r#"
     INSERT INTO newsletter_delivery_queue (
       newsletter_issue_id,
-      subscriber_email
+      subscriber_email,
+      subject_variant
     )
-    SELECT $1, email
-    FROM subscriptions
-    WHERE status = 'confirmed'
+    SELECT
+      $1,
+      email,
+      CASE
+        WHEN position <= CEIL(audience * $2::integer / 100.0) THEN (position % $3::integer)::smallint
+      END
+    FROM (
+      SELECT
+        email,
+        row_number() OVER (ORDER BY random()) as position,
+        COUNT(*) OVER () as audience
+      FROM subscriptions
+      WHERE status = 'confirmed'
+    ) confirmed
     "#,
     newsletter_issue_id,
+    test_percentage,
+    variants,
   )
-  .execute(transaction)
+  .execute(&mut *transaction)
   .await?;
-  Ok(())
+  notify_workers(transaction).await
 }
\ No newline at end of file
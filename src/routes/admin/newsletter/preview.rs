@@ -0,0 +1,174 @@
+use crate::authentication::UserId;
+use crate::domain::SubscriberEmail;
+use crate::issue_rendering::{escape_html, render_issue, IssueContent, MergeData};
+use crate::startup::ApplicationBaseUrl;
+use crate::transactional_emails::{enqueue_transactional_email, EmailPriority};
+use crate::utils::e500;
+use actix_web::http::header::ContentType;
+use actix_web::web::ReqData;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct FormData {
+  title: String,
+  #[serde(default)]
+  preheader: String,
+  text_content: String,
+  html_content: String,
+}
+
+impl FormData {
+  fn content(&self) -> IssueContent<'_> {
+    IssueContent {
+      title: &self.title,
+      preheader: &self.preheader,
+      text_content: &self.text_content,
+      html_content: &self.html_content,
+    }
+  }
+}
+
+#[tracing::instrument(name = "Previewing a newsletter issue", skip_all)]
+pub async fn preview_newsletter(
+  form: web::Form<FormData>,
+  pool: web::Data<PgPool>,
+  base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let merge_data = get_sample_merge_data(&pool).await.map_err(e500)?;
+  Ok(preview_page(&form.0, &merge_data, &base_url.0, ""))
+}
+
+#[tracing::instrument(
+  name = "Sending a test newsletter issue",
+  skip_all,
+  fields(user_id=%&*user_id)
+)]
+pub async fn send_test_newsletter(
+  form: web::Form<FormData>,
+  pool: web::Data<PgPool>,
+  base_url: web::Data<ApplicationBaseUrl>,
+  user_id: ReqData<UserId>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let merge_data = get_sample_merge_data(&pool).await.map_err(e500)?;
+  let recipient = match get_user_email(*user_id.into_inner(), &pool)
+    .await
+    .map_err(e500)?
+  {
+    Some(email) => email,
+    None => {
+      return Ok(preview_page(
+        &form.0,
+        &merge_data,
+        &base_url.0,
+        &alert(
+          r#"There is no valid email address associated with your account. Add one on the <a href="/admin/email">account email</a> page."#,
+        ),
+      ));
+    }
+  };
+
+  // Test emails go out with the workers, ahead of any issue being sent.
+  let rendered = render_issue(&form.content(), &merge_data).with_hosted_assets(&base_url.0);
+  let mut transaction = pool.begin().await.map_err(e500)?;
+  enqueue_transactional_email(
+    &mut transaction,
+    &recipient,
+    &format!("[Test] {}", rendered.title),
+    &rendered.text_content,
+    &rendered.html_content,
+    EmailPriority::Normal,
+  )
+  .await
+  .context("Failed to queue the test newsletter issue.")
+  .map_err(e500)?;
+  transaction.commit().await.map_err(e500)?;
+
+  let msg = alert(&format!(
+    "A test email is on its way to {}.",
+    escape_html(recipient.as_ref())
+  ));
+  Ok(preview_page(&form.0, &merge_data, &base_url.0, &msg))
+}
+
+fn alert(content: &str) -> String {
+  format!(
+    r#"
+    <div class="alert alert-info">
+    <strong>Info!</strong> {}
+    </div>
+    "#,
+    content
+  )
+}
+
+fn preview_page(
+  form: &FormData,
+  merge_data: &MergeData,
+  base_url: &str,
+  msg_html: &str,
+) -> HttpResponse {
+  let rendered = render_issue(&form.content(), merge_data).with_hosted_assets(base_url);
+  HttpResponse::Ok()
+    .content_type(ContentType::html())
+    .body(format!(
+      include_str!("preview.html"),
+      msg_html,
+      escape_html(&merge_data.name),
+      escape_html(&merge_data.email),
+      escape_html(&rendered.title),
+      escape_html(&rendered.preheader),
+      escape_html(&rendered.html_content),
+      escape_html(&rendered.text_content),
+      escape_html(&form.title),
+      escape_html(&form.preheader),
+      escape_html(&form.text_content),
+      escape_html(&form.html_content),
+    ))
+}
+
+/// Previews are rendered for the oldest confirmed subscriber, so that authors
+/// see realistic merge data. A made-up subscriber is used for empty lists.
+#[tracing::instrument(skip_all)]
+async fn get_sample_merge_data(pool: &PgPool) -> Result<MergeData, anyhow::Error> {
+  let row = sqlx::query!(
+    r#"
+    SELECT name, email
+    FROM subscriptions
+    WHERE status = 'confirmed'
+    ORDER BY subscribed_at
+    LIMIT 1
+    "#
+  )
+  .fetch_optional(pool)
+  .await
+  .context("Failed to retrieve a sample subscriber.")?;
+
+  Ok(match row {
+    Some(row) => MergeData { name: row.name, email: row.email },
+    None => MergeData::sample(),
+  })
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_user_email(
+  user_id: Uuid,
+  pool: &PgPool,
+) -> Result<Option<SubscriberEmail>, anyhow::Error> {
+  let row = sqlx::query!(
+    r#"
+    SELECT email
+    FROM users
+    WHERE user_id = $1
+    "#,
+    user_id,
+  )
+  .fetch_one(pool)
+  .await
+  .context("Failed to perform query to retrieve the user's email.")?;
+
+  Ok(row.email.and_then(|email| SubscriberEmail::parse(email).ok()))
+}
@@ -0,0 +1,222 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::issue_rendering::escape_html;
+use crate::utils::e500;
+
+pub async fn welcome_sequences(
+  pool: web::Data<PgPool>,
+  flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+  let sequences = get_sequence_summaries(&pool, None).await.map_err(e500)?;
+
+  let mut rows_html = String::new();
+  for sequence in &sequences {
+    writeln!(rows_html,
+      r#"
+      <tr>
+        <td><a href="/admin/sequences/{}">{}</a></td>
+        <td>{}</td>
+        <td>{}</td>
+        <td>{}</td>
+      </tr>
+      "#,
+      sequence.sequence_id,
+      escape_html(&sequence.name),
+      sequence.steps,
+      sequence.enrolled,
+      sequence.status(),
+    ).unwrap();
+  }
+  if sequences.is_empty() {
+    rows_html.push_str(r#"<tr><td colspan="4">No welcome sequences have been created yet.</td></tr>"#);
+  }
+
+  Ok(HttpResponse::Ok()
+    .content_type(ContentType::html())
+    .body(format!(
+      include_str!("sequences.html"),
+      msg_html = render_flash_messages(&flash_messages),
+      rows_html = rows_html,
+    ))
+  )
+}
+
+pub async fn welcome_sequence(
+  sequence_id: web::Path<Uuid>,
+  pool: web::Data<PgPool>,
+  flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+  let sequence = match get_sequence_summaries(&pool, Some(*sequence_id))
+    .await
+    .map_err(e500)?
+    .pop()
+  {
+    Some(sequence) => sequence,
+    None => return Ok(HttpResponse::NotFound().finish()),
+  };
+  let steps = get_steps(&pool, sequence.sequence_id).await.map_err(e500)?;
+
+  let mut rows_html = String::new();
+  for step in &steps {
+    writeln!(rows_html,
+      r#"
+      <tr>
+        <td>{}</td>
+        <td>{}</td>
+        <td>{}</td>
+        <td>{}</td>
+        <td>{}</td>
+      </tr>
+      "#,
+      step.delay_days,
+      escape_html(&step.subject),
+      step.delivered,
+      step.failed,
+      step.pending,
+    ).unwrap();
+  }
+  if steps.is_empty() {
+    rows_html.push_str(r#"<tr><td colspan="5">This sequence has no emails yet.</td></tr>"#);
+  }
+
+  let (action, label) = if sequence.active {
+    ("pause", "Pause")
+  } else {
+    ("resume", "Resume")
+  };
+  let actions_html = format!(
+    r#"
+    <form action="/admin/sequences/{}/{}" method="post" class="d-inline">
+      <button type="submit" class="btn">{}</button>
+    </form>
+    "#,
+    sequence.sequence_id,
+    action,
+    label,
+  );
+
+  Ok(HttpResponse::Ok()
+    .content_type(ContentType::html())
+    .body(format!(
+      include_str!("sequence.html"),
+      msg_html = render_flash_messages(&flash_messages),
+      name = escape_html(&sequence.name),
+      status = sequence.status(),
+      enrolled = sequence.enrolled,
+      actions_html = actions_html,
+      rows_html = rows_html,
+      sequence_id = sequence.sequence_id,
+    ))
+  )
+}
+
+fn render_flash_messages(flash_messages: &IncomingFlashMessages) -> String {
+  let mut msg_html = String::new();
+  for m in flash_messages.iter() {
+    writeln!(msg_html,
+      r#"
+      <div class="alert alert-info">
+      <strong>Info!</strong> {}
+      </div>
+      "#,
+      m.content()
+    ).unwrap();
+  }
+  msg_html
+}
+
+struct SequenceSummary {
+  sequence_id: Uuid,
+  name: String,
+  active: bool,
+  steps: i64,
+  enrolled: i64,
+}
+
+impl SequenceSummary {
+  fn status(&self) -> &'static str {
+    if self.active { "Active" } else { "Paused" }
+  }
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_sequence_summaries(
+  pool: &PgPool,
+  sequence_id: Option<Uuid>,
+) -> Result<Vec<SequenceSummary>, anyhow::Error> {
+  let sequences = sqlx::query_as!(
+    SequenceSummary,
+    r#"
+    SELECT
+      sequence_id,
+      name,
+      active,
+      (
+        SELECT COUNT(*)
+        FROM welcome_sequence_steps
+        WHERE welcome_sequence_steps.sequence_id = welcome_sequences.sequence_id
+      ) as "steps!",
+      (
+        SELECT COUNT(*)
+        FROM welcome_sequence_enrollments
+        WHERE welcome_sequence_enrollments.sequence_id = welcome_sequences.sequence_id
+      ) as "enrolled!"
+    FROM welcome_sequences
+    WHERE $1::uuid IS NULL OR sequence_id = $1
+    ORDER BY created_at
+    "#,
+    sequence_id,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to retrieve the welcome sequences.")?;
+  Ok(sequences)
+}
+
+struct Step {
+  delay_days: i32,
+  subject: String,
+  delivered: i64,
+  failed: i64,
+  pending: i64,
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_steps(pool: &PgPool, sequence_id: Uuid) -> Result<Vec<Step>, anyhow::Error> {
+  let steps = sqlx::query_as!(
+    Step,
+    r#"
+    SELECT
+      steps.delay_days,
+      steps.subject,
+      (
+        SELECT COUNT(*)
+        FROM welcome_sequence_log
+        WHERE welcome_sequence_log.step_id = steps.step_id AND outcome = 'delivered'
+      ) as "delivered!",
+      (
+        SELECT COUNT(*)
+        FROM welcome_sequence_log
+        WHERE welcome_sequence_log.step_id = steps.step_id AND outcome = 'failed'
+      ) as "failed!",
+      (
+        SELECT COUNT(*)
+        FROM welcome_sequence_queue
+        WHERE welcome_sequence_queue.step_id = steps.step_id
+      ) as "pending!"
+    FROM welcome_sequence_steps steps
+    WHERE steps.sequence_id = $1
+    ORDER BY steps.delay_days, steps.created_at
+    "#,
+    sequence_id,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to retrieve the welcome sequence emails.")?;
+  Ok(steps)
+}
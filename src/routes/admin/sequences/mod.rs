@@ -0,0 +1,5 @@
+mod get;
+pub use get::{welcome_sequence, welcome_sequences};
+
+mod post;
+pub use post::{add_sequence_step, create_welcome_sequence, pause_welcome_sequence, resume_welcome_sequence};
@@ -0,0 +1,149 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::utils::{e500, see_other};
+
+#[derive(Deserialize)]
+pub struct SequenceFormData {
+  name: String,
+}
+
+#[tracing::instrument(name = "Creating a welcome sequence", skip_all)]
+pub async fn create_welcome_sequence(
+  form: web::Form<SequenceFormData>,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let name = form.0.name.trim().to_owned();
+  if name.is_empty() {
+    FlashMessage::error("The sequence needs a name.").send();
+    return Ok(see_other("/admin/sequences"));
+  }
+
+  let sequence_id = Uuid::new_v4();
+  sqlx::query!(
+    r#"
+    INSERT INTO welcome_sequences (sequence_id, name, active, created_at)
+    VALUES ($1, $2, TRUE, now())
+    "#,
+    sequence_id,
+    name,
+  )
+  .execute(pool.get_ref())
+  .await
+  .context("Failed to store the welcome sequence.")
+  .map_err(e500)?;
+
+  FlashMessage::info("The welcome sequence has been created.").send();
+  Ok(see_other(&format!("/admin/sequences/{}", sequence_id)))
+}
+
+#[derive(Deserialize)]
+pub struct StepFormData {
+  delay_days: i32,
+  subject: String,
+  text_content: String,
+  html_content: String,
+}
+
+#[tracing::instrument(name = "Adding an email to a welcome sequence", skip(form, pool))]
+pub async fn add_sequence_step(
+  sequence_id: web::Path<Uuid>,
+  form: web::Form<StepFormData>,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let sequence_id = sequence_id.into_inner();
+  let sequence_page = format!("/admin/sequences/{}", sequence_id);
+  let form = form.0;
+  if form.delay_days < 0 {
+    FlashMessage::error("Emails cannot be sent before the subscriber confirms.").send();
+    return Ok(see_other(&sequence_page));
+  }
+  if form.subject.trim().is_empty() {
+    FlashMessage::error("The email needs a subject.").send();
+    return Ok(see_other(&sequence_page));
+  }
+
+  let inserted = sqlx::query!(
+    r#"
+    INSERT INTO welcome_sequence_steps (
+      step_id,
+      sequence_id,
+      delay_days,
+      subject,
+      text_content,
+      html_content,
+      created_at
+    )
+    SELECT $1, sequence_id, $3, $4, $5, $6, now()
+    FROM welcome_sequences
+    WHERE sequence_id = $2
+    "#,
+    Uuid::new_v4(),
+    sequence_id,
+    form.delay_days,
+    form.subject,
+    form.text_content,
+    form.html_content,
+  )
+  .execute(pool.get_ref())
+  .await
+  .context("Failed to store the welcome sequence email.")
+  .map_err(e500)?
+  .rows_affected();
+  if inserted == 0 {
+    return Ok(HttpResponse::NotFound().finish());
+  }
+
+  FlashMessage::info(format!(
+    "The email has been added - subscribers will receive it {} days after confirming.",
+    form.delay_days
+  )).send();
+  Ok(see_other(&sequence_page))
+}
+
+#[tracing::instrument(name = "Pausing a welcome sequence", skip(pool))]
+pub async fn pause_welcome_sequence(
+  sequence_id: web::Path<Uuid>,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+  set_active(&pool, sequence_id.into_inner(), false).await
+}
+
+#[tracing::instrument(name = "Resuming a welcome sequence", skip(pool))]
+pub async fn resume_welcome_sequence(
+  sequence_id: web::Path<Uuid>,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+  set_active(&pool, sequence_id.into_inner(), true).await
+}
+
+async fn set_active(
+  pool: &PgPool,
+  sequence_id: Uuid,
+  active: bool,
+) -> Result<HttpResponse, actix_web::Error> {
+  let updated = sqlx::query!(
+    "UPDATE welcome_sequences SET active = $2 WHERE sequence_id = $1",
+    sequence_id,
+    active,
+  )
+  .execute(pool)
+  .await
+  .context("Failed to update the welcome sequence.")
+  .map_err(e500)?
+  .rows_affected();
+  if updated == 0 {
+    return Ok(HttpResponse::NotFound().finish());
+  }
+
+  FlashMessage::info(if active {
+    "The sequence has been resumed."
+  } else {
+    "The sequence has been paused - new subscribers are not enrolled and no emails are sent."
+  }).send();
+  Ok(see_other(&format!("/admin/sequences/{}", sequence_id)))
+}
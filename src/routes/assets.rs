@@ -0,0 +1,57 @@
+use actix_web::http::header::{CacheControl, CacheDirective, ContentDisposition, DispositionParam, DispositionType, X_CONTENT_TYPE_OPTIONS};
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::asset_storage::{is_inline_content_type, AssetStorage};
+use crate::utils::e500;
+
+/// Serves uploaded assets to issues delivered with hosted asset URLs, and to
+/// the web archive. Assets never change once uploaded, so they are cached.
+/// Anything but an image is served as a download, and browsers are told not
+/// to guess at a different content type.
+#[tracing::instrument(name = "Serving an asset", skip(pool, asset_storage))]
+pub async fn serve_asset(
+  asset_id: web::Path<Uuid>,
+  pool: web::Data<PgPool>,
+  asset_storage: web::Data<AssetStorage>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let asset = sqlx::query!(
+    r#"
+    SELECT file_name, content_type, storage_key
+    FROM newsletter_assets
+    WHERE asset_id = $1
+    "#,
+    *asset_id,
+  )
+  .fetch_optional(pool.get_ref())
+  .await
+  .context("Failed to retrieve the asset details.")
+  .map_err(e500)?;
+  let asset = match asset {
+    Some(asset) => asset,
+    None => return Ok(HttpResponse::NotFound().finish()),
+  };
+
+  let disposition = if is_inline_content_type(&asset.content_type) {
+    DispositionType::Inline
+  } else {
+    DispositionType::Attachment
+  };
+  match asset_storage.get(&asset.storage_key).await.map_err(e500)? {
+    Some(content) => Ok(HttpResponse::Ok()
+      .content_type(asset.content_type)
+      .insert_header(ContentDisposition {
+        disposition,
+        parameters: vec![DispositionParam::Filename(asset.file_name)],
+      })
+      .insert_header((X_CONTENT_TYPE_OPTIONS, "nosniff"))
+      .insert_header(CacheControl(vec![
+        CacheDirective::Public,
+        CacheDirective::MaxAge(31_536_000),
+      ]))
+      .body(content)),
+    None => Ok(HttpResponse::NotFound().finish()),
+  }
+}
@@ -0,0 +1,126 @@
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use super::get::{get_public_issues, PublicIssue};
+use crate::issue_rendering::escape_html;
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::e500;
+
+const FEED_TITLE: &str = "Scoop";
+const FEED_DESCRIPTION: &str = "Your source for delicious news and updates!";
+const MAX_FEED_ENTRIES: i64 = 20;
+
+pub async fn rss_feed(
+  pool: web::Data<PgPool>,
+  base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let issues = get_public_issues(&pool, MAX_FEED_ENTRIES).await.map_err(e500)?;
+  let base_url = &base_url.0;
+
+  let mut items = String::new();
+  for issue in &issues {
+    let rendered = issue.render(base_url);
+    let link = issue_url(base_url, issue);
+    write!(items,
+      r#"
+    <item>
+      <title>{}</title>
+      <link>{}</link>
+      <guid isPermaLink="false">{}</guid>
+      <pubDate>{}</pubDate>
+      <description>{}</description>
+    </item>"#,
+      escape_html(&rendered.title),
+      escape_html(&link),
+      issue.newsletter_issue_id,
+      issue.published_at.to_rfc2822(),
+      escape_html(&rendered.html_content),
+    ).unwrap();
+  }
+
+  let body = format!(
+    r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
+  <channel>
+    <title>{}</title>
+    <link>{}/issues</link>
+    <description>{}</description>
+    <atom:link href="{}/feed.xml" rel="self" type="application/rss+xml"/>{}
+  </channel>
+</rss>
+"#,
+    FEED_TITLE,
+    escape_html(base_url),
+    FEED_DESCRIPTION,
+    escape_html(base_url),
+    items,
+  );
+  Ok(HttpResponse::Ok()
+    .content_type("application/rss+xml; charset=utf-8")
+    .body(body))
+}
+
+pub async fn atom_feed(
+  pool: web::Data<PgPool>,
+  base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let issues = get_public_issues(&pool, MAX_FEED_ENTRIES).await.map_err(e500)?;
+  let base_url = &base_url.0;
+  let updated = issues
+    .first()
+    .map(|issue| issue.published_at)
+    .unwrap_or_else(Utc::now);
+
+  let mut entries = String::new();
+  for issue in &issues {
+    let rendered = issue.render(base_url);
+    write!(entries,
+      r#"
+  <entry>
+    <title>{}</title>
+    <link href="{}"/>
+    <id>urn:uuid:{}</id>
+    <published>{}</published>
+    <updated>{}</updated>
+    <content type="html">{}</content>
+  </entry>"#,
+      escape_html(&rendered.title),
+      escape_html(&issue_url(base_url, issue)),
+      issue.newsletter_issue_id,
+      issue.published_at.to_rfc3339(),
+      issue.published_at.to_rfc3339(),
+      escape_html(&rendered.html_content),
+    ).unwrap();
+  }
+
+  let body = format!(
+    r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>{}</title>
+  <subtitle>{}</subtitle>
+  <link href="{}/issues"/>
+  <link href="{}/atom.xml" rel="self"/>
+  <id>{}/issues</id>
+  <updated>{}</updated>
+  <author><name>{}</name></author>{}
+</feed>
+"#,
+    FEED_TITLE,
+    FEED_DESCRIPTION,
+    escape_html(base_url),
+    escape_html(base_url),
+    escape_html(base_url),
+    updated.to_rfc3339(),
+    FEED_TITLE,
+    entries,
+  );
+  Ok(HttpResponse::Ok()
+    .content_type("application/atom+xml; charset=utf-8")
+    .body(body))
+}
+
+fn issue_url(base_url: &str, issue: &PublicIssue) -> String {
+  format!("{}/issues/{}", base_url, issue.path())
+}
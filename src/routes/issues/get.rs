@@ -0,0 +1,238 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::issue_rendering::{escape_html, render_issue, IssueContent, MergeData, RenderedIssue};
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::e500;
+
+/// How many issues a page of the archive lists.
+const ARCHIVE_PAGE_SIZE: i64 = 50;
+
+#[derive(Deserialize)]
+pub struct ArchiveParameters {
+  page: Option<i64>,
+}
+
+pub async fn issue_archive(
+  parameters: web::Query<ArchiveParameters>,
+  pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let page = parameters.page.unwrap_or(1).max(1);
+  let mut issues = get_archived_issues(&pool, ARCHIVE_PAGE_SIZE + 1, (page - 1) * ARCHIVE_PAGE_SIZE)
+    .await
+    .map_err(e500)?;
+  let has_older = issues.len() as i64 > ARCHIVE_PAGE_SIZE;
+  issues.truncate(ARCHIVE_PAGE_SIZE as usize);
+
+  let mut issues_html = String::new();
+  for issue in &issues {
+    let path = issue.path();
+    writeln!(issues_html,
+      r#"
+      <li class="list-group-item">
+      <a href="/issues/{}">{}</a>
+      <span class="small-text float-right">{}</span>
+      <p class="small-text mb-0">{}</p>
+      </li>
+      "#,
+      escape_html(&path),
+      escape_html(&issue.title),
+      issue.published_at.format("%B %-d, %Y"),
+      escape_html(&issue.preheader()),
+    ).unwrap();
+  }
+  if issues.is_empty() {
+    issues_html.push_str(r#"<li class="list-group-item">No issues have been published yet.</li>"#);
+  }
+
+  let mut pager_html = String::new();
+  if page > 1 {
+    write!(pager_html, r#"<a href="/issues?page={}">Newer issues</a>"#, page - 1).unwrap();
+  }
+  if has_older {
+    write!(pager_html, r#"<a class="float-right" href="/issues?page={}">Older issues</a>"#, page + 1).unwrap();
+  }
+
+  Ok(HttpResponse::Ok()
+    .content_type(ContentType::html())
+    .body(format!(include_str!("archive.html"), issues_html, pager_html))
+  )
+}
+
+#[tracing::instrument(name = "Reading an archived issue", skip(pool, base_url))]
+pub async fn issue_page(
+  id_or_slug: web::Path<String>,
+  pool: web::Data<PgPool>,
+  base_url: web::Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let issue = match get_public_issue(&pool, &id_or_slug).await.map_err(e500)? {
+    Some(issue) => issue,
+    None => return Ok(HttpResponse::NotFound().finish()),
+  };
+
+  let rendered = issue.render(&base_url.0);
+  Ok(HttpResponse::Ok()
+    .content_type(ContentType::html())
+    .body(format!(
+      include_str!("issue.html"),
+      escape_html(&rendered.preheader),
+      escape_html(&rendered.title),
+      escape_html(&rendered.title),
+      issue.published_at.format("%B %-d, %Y"),
+      rendered.html_content,
+    ))
+  )
+}
+
+pub(super) struct PublicIssue {
+  pub(super) newsletter_issue_id: Uuid,
+  pub(super) title: String,
+  pub(super) slug: Option<String>,
+  pub(super) preheader: String,
+  pub(super) text_content: String,
+  pub(super) html_content: String,
+  pub(super) published_at: DateTime<Utc>,
+}
+
+impl PublicIssue {
+  pub(super) fn path(&self) -> String {
+    self.slug.clone().unwrap_or_else(|| self.newsletter_issue_id.to_string())
+  }
+
+  pub(super) fn render(&self, base_url: &str) -> RenderedIssue {
+    let content = IssueContent {
+      title: &self.title,
+      preheader: &self.preheader,
+      text_content: &self.text_content,
+      html_content: &self.html_content,
+    };
+    render_issue(&content, &MergeData::anonymous()).with_hosted_assets(base_url)
+  }
+}
+
+/// An issue as listed in the archive, without its content.
+struct ArchivedIssue {
+  newsletter_issue_id: Uuid,
+  title: String,
+  slug: Option<String>,
+  preheader: String,
+  published_at: DateTime<Utc>,
+}
+
+impl ArchivedIssue {
+  fn path(&self) -> String {
+    self.slug.clone().unwrap_or_else(|| self.newsletter_issue_id.to_string())
+  }
+
+  fn preheader(&self) -> String {
+    let content = IssueContent {
+      title: &self.title,
+      preheader: &self.preheader,
+      text_content: "",
+      html_content: "",
+    };
+    render_issue(&content, &MergeData::anonymous()).preheader
+  }
+}
+
+/// Lists up to `limit` public issues, newest first, after skipping `offset`.
+#[tracing::instrument(skip(pool))]
+async fn get_archived_issues(
+  pool: &PgPool,
+  limit: i64,
+  offset: i64,
+) -> Result<Vec<ArchivedIssue>, anyhow::Error> {
+  let issues = sqlx::query_as!(
+    ArchivedIssue,
+    r#"
+    SELECT
+      newsletter_issue_id,
+      title,
+      slug,
+      preheader,
+      published_at
+    FROM newsletter_issues
+    WHERE
+      subscribers_only = false AND
+      delivery_status NOT IN ('cancelled', 'paused')
+    ORDER BY published_at DESC
+    LIMIT $1
+    OFFSET $2
+    "#,
+    limit,
+    offset,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to retrieve the published issues.")?;
+  Ok(issues)
+}
+
+/// Lists the latest public issues, newest first, up to `limit` of them.
+#[tracing::instrument(skip(pool))]
+pub(super) async fn get_public_issues(
+  pool: &PgPool,
+  limit: i64,
+) -> Result<Vec<PublicIssue>, anyhow::Error> {
+  let issues = sqlx::query_as!(
+    PublicIssue,
+    r#"
+    SELECT
+      newsletter_issue_id,
+      title,
+      slug,
+      preheader,
+      text_content,
+      html_content,
+      published_at
+    FROM newsletter_issues
+    WHERE
+      subscribers_only = false AND
+      delivery_status NOT IN ('cancelled', 'paused')
+    ORDER BY published_at DESC
+    LIMIT $1
+    "#,
+    limit,
+  )
+  .fetch_all(pool)
+  .await
+  .context("Failed to retrieve the published issues.")?;
+  Ok(issues)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_public_issue(
+  pool: &PgPool,
+  id_or_slug: &str,
+) -> Result<Option<PublicIssue>, anyhow::Error> {
+  let issue_id = Uuid::parse_str(id_or_slug).ok();
+  let issue = sqlx::query_as!(
+    PublicIssue,
+    r#"
+    SELECT
+      newsletter_issue_id,
+      title,
+      slug,
+      preheader,
+      text_content,
+      html_content,
+      published_at
+    FROM newsletter_issues
+    WHERE
+      (newsletter_issue_id = $1 OR slug = $2) AND
+      subscribers_only = false AND
+      delivery_status NOT IN ('cancelled', 'paused')
+    "#,
+    issue_id,
+    id_or_slug,
+  )
+  .fetch_optional(pool)
+  .await
+  .context("Failed to retrieve the issue.")?;
+  Ok(issue)
+}
@@ -0,0 +1,5 @@
+mod get;
+pub use get::{issue_archive, issue_page};
+
+mod feed;
+pub use feed::{atom_feed, rss_feed};
@@ -5,6 +5,9 @@ mod home;
 mod login;
 mod admin;
 mod unsubscribe;
+mod issues;
+mod tracking;
+mod assets;
 
 pub use health_check::*;
 pub use subscriptions::*;
@@ -12,4 +15,7 @@ pub use subscriptions_confirm::*;
 pub use home::*;
 pub use login::*;
 pub use admin::*;
-pub use unsubscribe::*;
\ No newline at end of file
+pub use unsubscribe::*;
+pub use issues::*;
+pub use tracking::*;
+pub use assets::*;
\ No newline at end of file
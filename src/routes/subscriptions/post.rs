@@ -10,7 +10,7 @@ use uuid::Uuid;
 use crate::utils::{see_other, e500, e400};
 use crate::{
   domain::{NewSubscriber, SubscriberName, SubscriberEmail}, 
-  email_client::EmailClient, startup::ApplicationBaseUrl
+  startup::ApplicationBaseUrl, transactional_emails::{enqueue_transactional_email, EmailPriority},
 };
 
 #[derive(serde::Deserialize)]
@@ -39,7 +39,7 @@ fn generate_subscription_token() -> String {
 
 #[tracing::instrument(
   name = "Adding a new subscriber",
-  skip(form, pool, email_client, base_url),
+  skip(form, pool, base_url),
   fields(
     subscriber_name = %form.name,
     subscriber_email = %form.email 
@@ -48,7 +48,6 @@ fn generate_subscription_token() -> String {
 pub async fn subscribe(
   form: web::Form<FormData>,
   pool: web::Data<PgPool>,
-  email_client: web::Data<EmailClient>,
   base_url: web::Data<ApplicationBaseUrl>,
 ) -> Result<HttpResponse, actix_web::Error> {
   let new_sub = form.0.try_into().map_err(e400)?;
@@ -65,9 +64,9 @@ pub async fn subscribe(
     .await
     .context("Failed to store confirmation token for a new subscriber.")
     .map_err(e500)?;
-  send_confirmation_email(&email_client, new_sub, &base_url.0, &subscriber_token)
+  queue_confirmation_email(&mut transaction, new_sub, &base_url.0, &subscriber_token)
     .await
-    .context("Failed to send the confirmation email to subscriber.")
+    .context("Failed to queue the confirmation email to subscriber.")
     .map_err(e500)?;
   transaction.commit()
     .await
@@ -77,16 +76,18 @@ pub async fn subscribe(
   Ok(see_other("/subscriptions"))
 }
 
+/// The email goes out with the delivery workers once the subscription is
+/// committed, so signing up does not wait on the email provider.
 #[tracing::instrument(
-  name = "Send a confirmation email to new subscribers.",
-  skip(email_client, new_sub, base_url, subscription_token),
+  name = "Queue a confirmation email to new subscribers.",
+  skip(transaction, new_sub, base_url, subscription_token),
 )]
-pub async fn send_confirmation_email(
-  email_client: &EmailClient,
+pub async fn queue_confirmation_email(
+  transaction: &mut Transaction<'_, Postgres>,
   new_sub: NewSubscriber,
   base_url: &str,
   subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), sqlx::Error> {
   let confirmation_link = format!(
     "{}/subscriptions/confirm?subscription_token={}",
     base_url,
@@ -97,7 +98,8 @@ pub async fn send_confirmation_email(
     base_url,
     subscription_token,
   );
-  email_client.send_email(
+  enqueue_transactional_email(
+    transaction,
     &new_sub.email,
     "Welcome!", 
     &format!(
@@ -111,6 +113,7 @@ pub async fn send_confirmation_email(
       confirmation_link,
       unsubscription_link
     ),
+    EmailPriority::High,
   ).await
 }
 
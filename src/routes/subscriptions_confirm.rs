@@ -2,6 +2,7 @@ use actix_web::{HttpResponse, web};
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::issue_delivery_workers::notify_workers;
 use crate::utils::see_other;
 
 #[derive(serde::Deserialize)]
@@ -60,6 +61,8 @@ pub async fn get_subscriber_id_from_token(
   Ok(result.map(|r| r.subscriber_id))
 }
 
+/// Marks the subscriber as confirmed and, the first time round, enrolls
+/// them in every active welcome sequence.
 #[tracing::instrument(
   name = "Mark subscriber as confirmed.",
   skip(pool, subcriber_id)
@@ -68,15 +71,31 @@ async fn confirm_subscriber(
   pool: &PgPool,
   subcriber_id: Uuid,
 ) -> Result<(), sqlx::Error> {
-  sqlx::query!(
-    r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
+  let enrolled = sqlx::query!(
+    r#"
+    WITH confirmed AS (
+      UPDATE subscriptions
+      SET status = 'confirmed'
+      WHERE id = $1 AND status <> 'confirmed'
+      RETURNING id
+    )
+    INSERT INTO welcome_sequence_enrollments (sequence_id, subscriber_id, enrolled_at)
+    SELECT welcome_sequences.sequence_id, confirmed.id, now()
+    FROM confirmed
+    CROSS JOIN welcome_sequences
+    WHERE welcome_sequences.active
+    "#,
     subcriber_id
   )
-  .fetch_optional(pool)
+  .execute(pool)
   .await
   .map_err(|e| {
     tracing::error!("Failed to execute query: {:?}", e);
     e
   })?;
+  // A sequence may start with an email that is due right away.
+  if enrolled.rows_affected() > 0 {
+    notify_workers(pool).await?;
+  }
   Ok(())
 }
\ No newline at end of file
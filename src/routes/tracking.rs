@@ -0,0 +1,108 @@
+use actix_web::http::header::{CacheControl, CacheDirective, LOCATION};
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::tracking::TrackingLinks;
+use crate::utils::e400;
+
+/// A transparent 1x1 GIF.
+const TRACKING_PIXEL: &[u8] = &[
+  0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+  0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+  0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// Serves the tracking pixel embedded in delivered issues. The pixel is
+/// returned even when the open cannot be recorded, so that a tracking
+/// failure never shows up as a broken image in someone's inbox.
+#[tracing::instrument(name = "Recording an issue open", skip(token, pool))]
+pub async fn track_open(
+  token: web::Path<String>,
+  pool: web::Data<PgPool>,
+) -> HttpResponse {
+  if let Err(e) = record_open(&pool, &token).await {
+    tracing::error!(
+      error.cause_chain = ?e,
+      error.message = %e,
+      "Failed to record an issue open."
+    );
+  }
+  HttpResponse::Ok()
+    .content_type("image/gif")
+    .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+    .body(TRACKING_PIXEL)
+}
+
+async fn record_open(pool: &PgPool, tracking_token: &str) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    r#"
+    UPDATE newsletter_delivery_log
+    SET
+      first_opened_at = COALESCE(first_opened_at, now()),
+      open_count = open_count + 1
+    WHERE tracking_token = $1
+    "#,
+    tracking_token
+  )
+  .execute(pool)
+  .await?;
+  Ok(())
+}
+
+#[derive(serde::Deserialize)]
+pub struct ClickParameters {
+  url: String,
+  sig: String,
+}
+
+/// Records a click on a link of a delivered issue and redirects to it.
+/// Only destinations signed by the delivery worker are followed.
+#[tracing::instrument(name = "Recording a link click", skip_all)]
+pub async fn track_click(
+  token: web::Path<String>,
+  parameters: web::Query<ClickParameters>,
+  pool: web::Data<PgPool>,
+  base_url: web::Data<ApplicationBaseUrl>,
+  hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+  let tracking_links = TrackingLinks::new(base_url.0.clone(), hmac_secret.0.clone());
+  if !tracking_links.verify_click(&token, &parameters.url, &parameters.sig) {
+    return Err(e400("The link signature is invalid."));
+  }
+  if let Err(e) = record_click(&pool, &token, &parameters.url).await {
+    tracing::error!(
+      error.cause_chain = ?e,
+      error.message = %e,
+      "Failed to record a link click."
+    );
+  }
+  Ok(HttpResponse::Found()
+    .insert_header((LOCATION, parameters.0.url))
+    .finish())
+}
+
+async fn record_click(
+  pool: &PgPool,
+  tracking_token: &str,
+  url: &str,
+) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    r#"
+    INSERT INTO newsletter_link_clicks (
+      newsletter_issue_id,
+      subscriber_email,
+      url,
+      clicked_at
+    )
+    SELECT newsletter_issue_id, subscriber_email, $2, now()
+    FROM newsletter_delivery_log
+    WHERE tracking_token = $1
+    "#,
+    tracking_token,
+    url,
+  )
+  .execute(pool)
+  .await?;
+  Ok(())
+}
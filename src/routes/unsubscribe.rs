@@ -36,14 +36,29 @@ pub async fn unsubscribe(
   }
 }
 
+/// Removes the subscriber, attributing the unsubscribe to the last issue
+/// they were delivered so that it shows up in that issue's analytics.
 async fn remove_subscriber(
   pool: &PgPool,
   subscriber_id: Uuid
 ) -> Result<(), sqlx::Error> {
   sqlx::query!(
     r#"
-    DELETE FROM subscriptions
-    WHERE id = $1
+    WITH removed AS (
+      DELETE FROM subscriptions
+      WHERE id = $1
+      RETURNING email
+    )
+    UPDATE newsletter_delivery_log
+    SET unsubscribed_at = now()
+    WHERE (newsletter_issue_id, subscriber_email) = (
+      SELECT newsletter_issue_id, subscriber_email
+      FROM newsletter_delivery_log
+      WHERE subscriber_email = (SELECT email FROM removed)
+        AND outcome = 'delivered'
+      ORDER BY recorded_at DESC
+      LIMIT 1
+    )
     "#,
     subscriber_id
   )
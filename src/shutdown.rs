@@ -0,0 +1,51 @@
+use tokio::sync::watch;
+
+/// Creates the switch that starts a shutdown, and the handle the API server
+/// and the delivery workers watch it through.
+pub fn shutdown_channel() -> (ShutdownSwitch, Shutdown) {
+  let (sender, receiver) = watch::channel(false);
+  (ShutdownSwitch(sender), Shutdown(receiver))
+}
+
+pub struct ShutdownSwitch(watch::Sender<bool>);
+
+impl ShutdownSwitch {
+  pub fn trigger(&self) {
+    self.0.send_replace(true);
+  }
+}
+
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+  pub fn is_requested(&self) -> bool {
+    *self.0.borrow()
+  }
+
+  /// Resolves once a shutdown is requested. A switch dropped without
+  /// being triggered never requests one.
+  pub async fn requested(&mut self) {
+    if self.0.wait_for(|requested| *requested).await.is_err() {
+      std::future::pending::<()>().await;
+    }
+  }
+}
+
+/// Resolves when the process is asked to stop, by SIGTERM on deploys or
+/// by Ctrl-C.
+pub async fn termination_signal() {
+  #[cfg(unix)]
+  {
+    let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+      .expect("Failed to install the SIGTERM handler.");
+    tokio::select! {
+      _ = sigterm.recv() => {}
+      _ = tokio::signal::ctrl_c() => {}
+    }
+  }
+  #[cfg(not(unix))]
+  {
+    let _ = tokio::signal::ctrl_c().await;
+  }
+}
\ No newline at end of file
@@ -4,7 +4,7 @@ use actix_session::storage::RedisSessionStore;
 use actix_web::cookie::Key;
 use actix_web::web::Data;
 use actix_web::{HttpServer, web, App};
-use actix_web::dev::Server;
+use actix_web::dev::{Server, ServerHandle};
 use actix_web_flash_messages::FlashMessagesFramework;
 use actix_web_flash_messages::storage::CookieMessageStore;
 use actix_session::SessionMiddleware;
@@ -16,8 +16,9 @@ use sqlx::postgres::PgPoolOptions;
 use tracing_actix_web::TracingLogger;
 
 use crate::authentication::reject_anonymous_users;
-use crate::configuration::{Settings, DatabaseSettings};
-use crate::routes::{admin_dashboard, change_password, change_password_form, home, log_out, login, login_form, newsletter_form, publish_newsletter, subscribe_form, unsubscribe};
+use crate::asset_storage::AssetStorage;
+use crate::configuration::{ApplicationSettings, Settings, DatabaseSettings};
+use crate::routes::{add_sequence_step, admin_dashboard, approve_draft, asset_library, atom_feed, cancel_issue, change_email, change_email_form, change_password, change_password_form, create_draft, create_welcome_sequence, draft_list, draft_revisions, duplicate_issue, home, issue_analytics, issue_analytics_json, issue_archive, issue_history, issue_page, issue_progress, log_out, login, login_form, newsletter_form, pause_issue, pause_welcome_sequence, preview_newsletter, publish_newsletter, restore_revision, resume_issue, resume_welcome_sequence, rss_feed, save_draft, send_test_newsletter, serve_asset, submit_draft, subscribe_form, upload_asset, track_click, track_open, unsubscribe, welcome_sequence, welcome_sequences};
 use crate::email_client::EmailClient;
 use crate::routes::{health_check, subscribe, confirm};
 
@@ -29,7 +30,11 @@ pub struct Application {
 impl Application {
   pub async fn build(configuration: Settings) -> Result<Application, anyhow::Error> {
     let email_client = configuration.email_client.client();
-    let connection_pool = get_connection_pool(&configuration.database);
+    let asset_storage = configuration.asset_storage.storage();
+    let connection_pool = get_connection_pool(
+      &configuration.database,
+      configuration.database.max_connections,
+    );
     let address = format!("{}:{}", 
         configuration.application.host, configuration.application.port);
     let listener = TcpListener::bind(address)?;
@@ -39,8 +44,8 @@ impl Application {
       listener,
       connection_pool,
       email_client,
-      configuration.application.base_url,
-      configuration.application.hmac_secret,
+      asset_storage,
+      configuration.application,
       configuration.redis_uri,
     ).await?;
 
@@ -51,31 +56,44 @@ impl Application {
     self.port
   }
 
+  /// Stops the server. A graceful stop lets in-flight requests finish
+  /// within the shutdown timeout.
+  pub fn handle(&self) -> ServerHandle {
+    self.server.handle()
+  }
+
   pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
     self.server.await
   }
 }
 
-pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
+pub fn get_connection_pool(configuration: &DatabaseSettings, max_connections: u32) -> PgPool {
   PgPoolOptions::new()
+    .max_connections(max_connections)
     .acquire_timeout(std::time::Duration::from_secs(2))
     .connect_lazy_with(configuration.with_db())
 }
 
 pub struct ApplicationBaseUrl(pub String);
 pub struct HmacSecret(pub Secret<String>);
+/// Whether issues can only be published from drafts approved by a second admin.
+pub struct RequireIssueApproval(pub bool);
 
 async fn run(
   listener: TcpListener,
   db_pool: PgPool,
   email_client: EmailClient,
-  base_url: String,
-  hmac_secret: Secret<String>,
+  asset_storage: AssetStorage,
+  application: ApplicationSettings,
   redis_uri: Secret<String>,
 ) -> Result<Server, anyhow::Error> {
+  let hmac_secret = application.hmac_secret;
+  let shutdown_timeout = application.shutdown_timeout_secs;
   let db_pool = web::Data::new(db_pool);
   let email_client = web::Data::new(email_client);
-  let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+  let asset_storage = web::Data::new(asset_storage);
+  let base_url = web::Data::new(ApplicationBaseUrl(application.base_url));
+  let require_issue_approval = web::Data::new(RequireIssueApproval(application.require_issue_approval));
   
   let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
   let message_store = CookieMessageStore::builder(
@@ -96,24 +114,63 @@ async fn run(
           .route("/subscriptions", web::post().to(subscribe))
           .route("/subscriptions/confirm", web::get().to(confirm))
           .route("/unsubscribe", web::get().to(unsubscribe))
+          .route("/issues", web::get().to(issue_archive))
+          .route("/issues/{id_or_slug}", web::get().to(issue_page))
+          .route("/feed.xml", web::get().to(rss_feed))
+          .route("/atom.xml", web::get().to(atom_feed))
+          .route("/t/o/{token}.gif", web::get().to(track_open))
+          .route("/t/c/{token}", web::get().to(track_click))
+          .route("/assets/{asset_id}", web::get().to(serve_asset))
           .service(
             web::scope("/admin")
               .wrap(from_fn(reject_anonymous_users))
               .route("/dashboard", web::get().to(admin_dashboard))
               .route("/password", web::get().to(change_password_form))
               .route("/password", web::post().to(change_password))
+              .route("/email", web::get().to(change_email_form))
+              .route("/email", web::post().to(change_email))
               .route("/logout", web::post().to(log_out))
               .route("/newsletters", web::get().to(newsletter_form))
               .route("/newsletters", web::post().to(publish_newsletter))
+              .route("/newsletters/preview", web::post().to(preview_newsletter))
+              .route("/newsletters/test", web::post().to(send_test_newsletter))
+              .route("/drafts", web::get().to(draft_list))
+              .route("/drafts", web::post().to(create_draft))
+              .route("/drafts/{draft_id}", web::post().to(save_draft))
+              .route("/drafts/{draft_id}/revisions", web::get().to(draft_revisions))
+              .route("/drafts/{draft_id}/revisions/{revision}/restore", web::post().to(restore_revision))
+              .route("/drafts/{draft_id}/submit", web::post().to(submit_draft))
+              .route("/drafts/{draft_id}/approve", web::post().to(approve_draft))
+              .route("/sequences", web::get().to(welcome_sequences))
+              .route("/sequences", web::post().to(create_welcome_sequence))
+              .route("/sequences/{sequence_id}", web::get().to(welcome_sequence))
+              .route("/sequences/{sequence_id}/steps", web::post().to(add_sequence_step))
+              .route("/sequences/{sequence_id}/pause", web::post().to(pause_welcome_sequence))
+              .route("/sequences/{sequence_id}/resume", web::post().to(resume_welcome_sequence))
+              .route("/assets", web::get().to(asset_library))
+              .route("/assets", web::post().to(upload_asset))
+              .route("/issues", web::get().to(issue_history))
+              .route("/issues/{issue_id}", web::get().to(issue_progress))
+              .route("/issues/{issue_id}/analytics", web::get().to(issue_analytics))
+              .route("/issues/{issue_id}/analytics.json", web::get().to(issue_analytics_json))
+              .route("/issues/{issue_id}/pause", web::post().to(pause_issue))
+              .route("/issues/{issue_id}/resume", web::post().to(resume_issue))
+              .route("/issues/{issue_id}/cancel", web::post().to(cancel_issue))
+              .route("/issues/{issue_id}/duplicate", web::post().to(duplicate_issue))
           )
           .service(fs::Files::new("/static", "./static").show_files_listing())
           .service(fs::Files::new("/admin/static", "./static").show_files_listing())
           .app_data(db_pool.clone())
           .app_data(email_client.clone())
+          .app_data(asset_storage.clone())
           .app_data(base_url.clone())
+          .app_data(require_issue_approval.clone())
           .app_data(Data::new(HmacSecret(hmac_secret.clone())))
     })
   .listen(listener)?
+  // Signals are handled by `main`, which stops the workers as well.
+  .disable_signals()
+  .shutdown_timeout(shutdown_timeout)
   .run();
 
   Ok(server)
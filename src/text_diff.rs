@@ -0,0 +1,111 @@
+#[derive(Debug, PartialEq)]
+pub enum DiffLine<'a> {
+  Unchanged(&'a str),
+  Added(&'a str),
+  Removed(&'a str),
+}
+
+/// The largest table of common subsequences a diff builds, in cells, which
+/// keeps it to a few megabytes. Changes spanning more lines than that are
+/// shown as the old lines removed and the new ones added.
+const MAX_TABLE_CELLS: usize = 1_000_000;
+
+/// Computes a line by line diff of two texts from their longest common
+/// subsequence of lines. Removed lines come before the lines replacing them.
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+  let old: Vec<&str> = old.lines().collect();
+  let new: Vec<&str> = new.lines().collect();
+
+  // Only the lines between the common start and end of the texts need the
+  // table, which is usually a small part of them.
+  let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+  let suffix = old[prefix..]
+    .iter()
+    .rev()
+    .zip(new[prefix..].iter().rev())
+    .take_while(|(a, b)| a == b)
+    .count();
+  let changed_old = &old[prefix..old.len() - suffix];
+  let changed_new = &new[prefix..new.len() - suffix];
+
+  let mut diff = Vec::with_capacity(old.len().max(new.len()));
+  diff.extend(old[..prefix].iter().map(|line| DiffLine::Unchanged(line)));
+  if (changed_old.len() + 1).saturating_mul(changed_new.len() + 1) > MAX_TABLE_CELLS {
+    diff.extend(changed_old.iter().map(|line| DiffLine::Removed(line)));
+    diff.extend(changed_new.iter().map(|line| DiffLine::Added(line)));
+  } else {
+    diff_changed_lines(changed_old, changed_new, &mut diff);
+  }
+  diff.extend(old[old.len() - suffix..].iter().map(|line| DiffLine::Unchanged(line)));
+  diff
+}
+
+fn diff_changed_lines<'a>(old: &[&'a str], new: &[&'a str], diff: &mut Vec<DiffLine<'a>>) {
+  // common[i][j] is the length of the longest common subsequence of
+  // old[i..] and new[j..].
+  let mut common = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+  for i in (0..old.len()).rev() {
+    for j in (0..new.len()).rev() {
+      common[i][j] = if old[i] == new[j] {
+        common[i + 1][j + 1] + 1
+      } else {
+        common[i + 1][j].max(common[i][j + 1])
+      };
+    }
+  }
+
+  let (mut i, mut j) = (0, 0);
+  while i < old.len() && j < new.len() {
+    if old[i] == new[j] {
+      diff.push(DiffLine::Unchanged(old[i]));
+      i += 1;
+      j += 1;
+    } else if common[i + 1][j] >= common[i][j + 1] {
+      diff.push(DiffLine::Removed(old[i]));
+      i += 1;
+    } else {
+      diff.push(DiffLine::Added(new[j]));
+      j += 1;
+    }
+  }
+  diff.extend(old[i..].iter().map(|line| DiffLine::Removed(line)));
+  diff.extend(new[j..].iter().map(|line| DiffLine::Added(line)));
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{diff_lines, DiffLine::*};
+
+  #[test]
+  fn identical_texts_are_unchanged() {
+    assert_eq!(diff_lines("a\nb", "a\nb"), [Unchanged("a"), Unchanged("b")]);
+  }
+
+  #[test]
+  fn replaced_lines_are_removed_then_added() {
+    assert_eq!(
+      diff_lines("a\nb\nc", "a\nx\nc"),
+      [Unchanged("a"), Removed("b"), Added("x"), Unchanged("c")]
+    );
+  }
+
+  #[test]
+  fn lines_can_be_added_and_removed_at_either_end() {
+    assert_eq!(diff_lines("", "a"), [Added("a")]);
+    assert_eq!(diff_lines("a\nb", "b"), [Removed("a"), Unchanged("b")]);
+    assert_eq!(diff_lines("a", "a\nb"), [Unchanged("a"), Added("b")]);
+  }
+
+  #[test]
+  fn large_rewrites_are_shown_as_removed_then_added() {
+    let old: String = (0..2000).map(|i| format!("old {}\n", i)).collect();
+    let new: String = (0..2000).map(|i| format!("new {}\n", i)).collect();
+    let (old, new) = (format!("a\n{}z", old), format!("a\n{}z", new));
+    let diff = diff_lines(&old, &new);
+    assert_eq!(diff.len(), 4002);
+    assert_eq!(diff[0], Unchanged("a"));
+    assert_eq!(diff[1], Removed("old 0"));
+    assert_eq!(diff[2001], Added("new 0"));
+    assert_eq!(diff[4001], Unchanged("z"));
+  }
+}
\ No newline at end of file
@@ -0,0 +1,102 @@
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use secrecy::{ExposeSecret, Secret};
+
+/// Builds the open and click tracking URLs embedded in delivered issues.
+///
+/// Click tracking URLs carry the destination they redirect to. They are
+/// signed with the application HMAC secret so that the redirector only
+/// sends people to links that were actually part of an issue.
+#[derive(Clone)]
+pub struct TrackingLinks {
+  base_url: String,
+  hmac_secret: Secret<String>,
+}
+
+impl TrackingLinks {
+  pub fn new(base_url: String, hmac_secret: Secret<String>) -> Self {
+    Self { base_url, hmac_secret }
+  }
+
+  pub fn open_pixel_url(&self, tracking_token: &str) -> String {
+    format!("{}/t/o/{}.gif", self.base_url, tracking_token)
+  }
+
+  pub fn click_url(&self, tracking_token: &str, destination: &str) -> String {
+    let signature = hex::encode(self.mac(tracking_token, destination).finalize().into_bytes());
+    Url::parse_with_params(
+      &format!("{}/t/c/{}", self.base_url, tracking_token),
+      &[("url", destination), ("sig", &signature)],
+    )
+    .map(String::from)
+    .unwrap_or_else(|_| destination.to_owned())
+  }
+
+  pub fn verify_click(&self, tracking_token: &str, destination: &str, signature: &str) -> bool {
+    match hex::decode(signature) {
+      Ok(signature) => self.mac(tracking_token, destination).verify_slice(&signature).is_ok(),
+      Err(_) => false,
+    }
+  }
+
+  fn mac(&self, tracking_token: &str, destination: &str) -> Hmac<sha2::Sha256> {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(
+      self.hmac_secret.expose_secret().as_bytes()
+    ).unwrap();
+    mac.update(tracking_token.as_bytes());
+    mac.update(b"\n");
+    mac.update(destination.as_bytes());
+    mac
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::TrackingLinks;
+  use reqwest::Url;
+  use secrecy::Secret;
+
+  fn tracking_links(secret: &str) -> TrackingLinks {
+    TrackingLinks::new("http://127.0.0.1".into(), Secret::new(secret.into()))
+  }
+
+  fn signature(click_url: &str) -> String {
+    let url = Url::parse(click_url).unwrap();
+    url.query_pairs().find(|(k, _)| k == "sig").unwrap().1.into_owned()
+  }
+
+  #[test]
+  fn click_urls_carry_the_encoded_destination() {
+    let url = tracking_links("secret").click_url("token", "https://example.com/?a=1&b=2");
+    let url = Url::parse(&url).unwrap();
+
+    assert_eq!(url.path(), "/t/c/token");
+    let destination = url.query_pairs().find(|(k, _)| k == "url").unwrap().1;
+    assert_eq!(destination, "https://example.com/?a=1&b=2");
+  }
+
+  #[test]
+  fn signed_click_urls_are_verified() {
+    let links = tracking_links("secret");
+    let url = links.click_url("token", "https://example.com");
+
+    assert!(links.verify_click("token", "https://example.com", &signature(&url)));
+  }
+
+  #[test]
+  fn tampered_destinations_are_rejected() {
+    let links = tracking_links("secret");
+    let url = links.click_url("token", "https://example.com");
+
+    assert!(!links.verify_click("token", "https://evil.example.com", &signature(&url)));
+    assert!(!links.verify_click("other-token", "https://example.com", &signature(&url)));
+    assert!(!links.verify_click("token", "https://example.com", "not-hex"));
+  }
+
+  #[test]
+  fn signatures_depend_on_the_secret() {
+    let url = tracking_links("secret").click_url("token", "https://example.com");
+
+    assert!(!tracking_links("another-secret").verify_click("token", "https://example.com", &signature(&url)));
+  }
+}
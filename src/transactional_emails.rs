@@ -0,0 +1,275 @@
+use std::time::Duration;
+
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+  domain::SubscriberEmail, email_client::{EmailClient, EmailMessage, SendError},
+  issue_delivery_workers::{notify_workers, ExecutionOutcome, Worker, LEASE_DURATION},
+};
+
+/// How many times an email is tried before it is given up on.
+const MAX_ATTEMPTS: i32 = 5;
+
+/// How long until a failed email is tried again, doubled after every
+/// further failure.
+const RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Orders the emails of the transactional lane. All of them go out ahead of
+/// bulk deliveries.
+#[derive(Debug, Clone, Copy)]
+pub enum EmailPriority {
+  /// Emails someone is waiting on to carry on, like a confirmation link.
+  High,
+  Normal,
+}
+
+impl EmailPriority {
+  fn as_i16(self) -> i16 {
+    match self {
+      EmailPriority::High => 1,
+      EmailPriority::Normal => 0,
+    }
+  }
+}
+
+/// Queues an email for the workers to send. Written in the caller's
+/// transaction, it only goes out once the transaction commits, and the
+/// request that queued it does not wait on the email provider.
+#[tracing::instrument(skip(transaction, text_body, html_body))]
+pub async fn enqueue_transactional_email(
+  transaction: &mut Transaction<'_, Postgres>,
+  recipient: &SubscriberEmail,
+  subject: &str,
+  text_body: &str,
+  html_body: &str,
+  priority: EmailPriority,
+) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    r#"
+    INSERT INTO transactional_email_queue (email_id, recipient, subject, text_body, html_body, priority)
+    VALUES ($1, $2, $3, $4, $5, $6)
+    "#,
+    Uuid::new_v4(),
+    recipient.as_ref(),
+    subject,
+    text_body,
+    html_body,
+    priority.as_i16(),
+  )
+  .execute(&mut *transaction)
+  .await?;
+  notify_workers(&mut *transaction).await
+}
+
+/// Claims a batch of queued transactional emails, highest priority first,
+/// and sends them, leasing the rows to the worker and keeping to the send
+/// rates in the same way as issue deliveries. Failed emails are retried
+/// with a growing delay.
+#[tracing::instrument(skip_all, fields(worker_id=%worker.id), err)]
+pub async fn try_execute_transactional_task(
+  pool: &PgPool,
+  email_client: &EmailClient,
+  worker: &Worker<'_>,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+  let batch_size = match worker.rate_limiter.allowance(worker.batch_size) {
+    Ok(batch_size) => batch_size,
+    Err(wait) => return Ok(ExecutionOutcome::RateLimited(wait)),
+  };
+  let throttled_domains = worker.rate_limiter.throttled_domains();
+  let tasks = sqlx::query_as!(
+    TransactionalTask,
+    r#"
+    UPDATE transactional_email_queue
+    SET
+      locked_until = now() + make_interval(secs => $3),
+      locked_by = $1
+    FROM (
+      SELECT email_id
+      FROM transactional_email_queue
+      WHERE
+        next_attempt_at <= now() AND (
+          locked_until IS NULL OR
+          locked_until < now()
+        ) AND
+        lower(split_part(recipient, '@', 2)) <> ALL($4)
+      ORDER BY priority DESC, enqueued_at
+      FOR UPDATE
+      SKIP LOCKED
+      LIMIT $2
+    ) claimed
+    WHERE transactional_email_queue.email_id = claimed.email_id
+    RETURNING
+      transactional_email_queue.email_id,
+      transactional_email_queue.recipient,
+      transactional_email_queue.subject,
+      transactional_email_queue.text_body,
+      transactional_email_queue.html_body,
+      transactional_email_queue.n_attempts
+    "#,
+    worker.id,
+    batch_size as i64,
+    LEASE_DURATION.as_secs_f64(),
+    &throttled_domains,
+  )
+  .fetch_all(pool)
+  .await?;
+  if tasks.is_empty() {
+    return Ok(ExecutionOutcome::EmptyQueue);
+  }
+
+  let mut deferred = Vec::new();
+  let mut sendable = Vec::with_capacity(tasks.len());
+  for task in tasks {
+    match worker.rate_limiter.try_acquire(&task.recipient) {
+      Ok(()) => sendable.push(task),
+      Err(wait) => deferred.push((task.email_id, wait)),
+    }
+  }
+  let tasks = sendable;
+  defer_transactional_tasks(pool, &worker.id, deferred).await?;
+  let recipients: Vec<_> = tasks
+    .iter()
+    .map(|task| SubscriberEmail::parse(task.recipient.clone()))
+    .collect();
+  let messages: Vec<_> = tasks
+    .iter()
+    .zip(&recipients)
+    .filter_map(|(task, recipient)| {
+      Some(EmailMessage {
+        to: recipient.as_ref().ok()?,
+        subject: &task.subject,
+        text_body: &task.text_body,
+        html_body: &task.html_body,
+        attachments: &[],
+        reference: None,
+      })
+    })
+    .collect();
+  let mut results = email_client.send_emails(&messages).await.into_iter();
+  for (task, recipient) in tasks.iter().zip(&recipients) {
+    let result = match recipient {
+      Ok(_) => results.next().expect("Every message has a result."),
+      Err(e) => Err(SendError::Rejected(e.clone())),
+    };
+    match result {
+      Ok(()) => complete_transactional_task(pool, &worker.id, task.email_id).await?,
+      Err(reason) if recipient.is_err() || task.n_attempts + 1 >= MAX_ATTEMPTS => {
+        tracing::error!(
+          email_id = %task.email_id,
+          recipient = %task.recipient,
+          reason = %reason,
+          "Failed to send a transactional email. Giving up.",
+        );
+        complete_transactional_task(pool, &worker.id, task.email_id).await?
+      }
+      Err(reason) => {
+        tracing::warn!(
+          email_id = %task.email_id,
+          recipient = %task.recipient,
+          reason = %reason,
+          "Failed to send a transactional email. Retrying later.",
+        );
+        retry_transactional_task(pool, &worker.id, task, &reason.to_string()).await?
+      }
+    }
+  }
+  Ok(ExecutionOutcome::TaskCompleted)
+}
+
+struct TransactionalTask {
+  email_id: Uuid,
+  recipient: String,
+  subject: String,
+  text_body: String,
+  html_body: String,
+  n_attempts: i32,
+}
+
+/// Releases rows the send rate could not take, to be claimed again once
+/// their wait is over.
+#[tracing::instrument(skip_all, fields(deferred=deferred.len()))]
+async fn defer_transactional_tasks(
+  pool: &PgPool,
+  worker_id: &str,
+  deferred: Vec<(Uuid, Duration)>,
+) -> Result<(), anyhow::Error> {
+  if deferred.is_empty() {
+    return Ok(());
+  }
+  let (email_ids, waits): (Vec<_>, Vec<_>) = deferred
+    .into_iter()
+    .map(|(email_id, wait)| (email_id, wait.as_secs_f64()))
+    .unzip();
+  sqlx::query!(
+    r#"
+    UPDATE transactional_email_queue
+    SET
+      next_attempt_at = now() + make_interval(secs => deferred.wait),
+      locked_until = NULL,
+      locked_by = NULL
+    FROM UNNEST($2::uuid[], $3::float8[]) AS deferred(email_id, wait)
+    WHERE
+      transactional_email_queue.email_id = deferred.email_id AND
+      transactional_email_queue.locked_by = $1
+    "#,
+    worker_id,
+    &email_ids,
+    &waits,
+  )
+  .execute(pool)
+  .await?;
+  Ok(())
+}
+
+/// Puts a failed email back in the queue, to be tried again after a delay
+/// that doubles with each attempt.
+#[tracing::instrument(skip(pool, task), fields(email_id=%task.email_id))]
+async fn retry_transactional_task(
+  pool: &PgPool,
+  worker_id: &str,
+  task: &TransactionalTask,
+  reason: &str,
+) -> Result<(), anyhow::Error> {
+  let backoff = RETRY_BACKOFF * 2u32.pow(task.n_attempts as u32);
+  sqlx::query!(
+    r#"
+    UPDATE transactional_email_queue
+    SET
+      n_attempts = n_attempts + 1,
+      last_error = $3,
+      next_attempt_at = now() + make_interval(secs => $4),
+      locked_until = NULL,
+      locked_by = NULL
+    WHERE email_id = $1 AND locked_by = $2
+    "#,
+    task.email_id,
+    worker_id,
+    reason,
+    backoff.as_secs_f64(),
+  )
+  .execute(pool)
+  .await?;
+  Ok(())
+}
+
+/// Removes the worker's row from the queue, unless the lease expired and
+/// another worker reclaimed it.
+#[tracing::instrument(skip(pool))]
+async fn complete_transactional_task(
+  pool: &PgPool,
+  worker_id: &str,
+  email_id: Uuid,
+) -> Result<(), anyhow::Error> {
+  sqlx::query!(
+    r#"
+    DELETE FROM transactional_email_queue
+    WHERE email_id = $1 AND locked_by = $2
+    "#,
+    email_id,
+    worker_id,
+  )
+  .execute(pool)
+  .await?;
+  Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,285 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+  domain::SubscriberEmail, email_client::{EmailClient, EmailMessage, SendError},
+  issue_delivery_workers::{notify_workers, ExecutionOutcome, Worker, LEASE_DURATION, UNAVAILABLE_PROVIDER_RETRY},
+  issue_rendering::{render_issue, IssueContent, MergeData, RenderedIssue},
+};
+
+/// Enqueues the welcome sequence steps that have fallen due. A step is due
+/// `delay_days` after the subscriber was enrolled; steps added to a sequence
+/// after they would have fallen due are skipped for that subscriber rather
+/// than sent late. The workers are woken up when anything was enqueued.
+#[tracing::instrument(skip_all, err)]
+pub async fn schedule_welcome_sequence_steps(pool: &PgPool) -> Result<(), anyhow::Error> {
+  let scheduled = sqlx::query!(
+    r#"
+    INSERT INTO welcome_sequence_queue (step_id, subscriber_id)
+    SELECT steps.step_id, enrollments.subscriber_id
+    FROM welcome_sequence_enrollments enrollments
+    JOIN welcome_sequences sequences
+      ON sequences.sequence_id = enrollments.sequence_id
+    JOIN welcome_sequence_steps steps
+      ON steps.sequence_id = enrollments.sequence_id
+    JOIN subscriptions
+      ON subscriptions.id = enrollments.subscriber_id
+    WHERE
+      sequences.active AND
+      subscriptions.status = 'confirmed' AND
+      enrollments.enrolled_at + make_interval(days => steps.delay_days) <= now() AND
+      steps.created_at <= enrollments.enrolled_at + make_interval(days => steps.delay_days) AND
+      NOT EXISTS (
+        SELECT 1
+        FROM welcome_sequence_log log
+        WHERE log.step_id = steps.step_id AND log.subscriber_id = enrollments.subscriber_id
+      )
+    ON CONFLICT DO NOTHING
+    "#
+  )
+  .execute(pool)
+  .await?
+  .rows_affected();
+
+  if scheduled > 0 {
+    tracing::info!(scheduled, "Scheduled welcome sequence emails.");
+    notify_workers(pool).await?;
+  }
+  Ok(())
+}
+
+/// Claims a batch of due welcome emails and sends them, leasing the queue
+/// rows to the worker and keeping to the send rates in the same way as
+/// issue deliveries. Emails the provider could not take, or that failed
+/// without an answer, are tried again later.
+#[tracing::instrument(skip_all, fields(worker_id=%worker.id), err)]
+pub async fn try_execute_sequence_task(
+  pool: &PgPool,
+  email_client: &EmailClient,
+  worker: &Worker<'_>,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+  let batch_size = match worker.rate_limiter.allowance(worker.batch_size) {
+    Ok(batch_size) => batch_size,
+    Err(wait) => return Ok(ExecutionOutcome::RateLimited(wait)),
+  };
+  let throttled_domains = worker.rate_limiter.throttled_domains();
+  let tasks = sqlx::query_as!(
+    SequenceTask,
+    r#"
+    UPDATE welcome_sequence_queue
+    SET
+      locked_until = now() + make_interval(secs => $3),
+      locked_by = $1
+    FROM (
+      SELECT welcome_sequence_queue.step_id, welcome_sequence_queue.subscriber_id
+      FROM welcome_sequence_queue
+      JOIN subscriptions
+        ON subscriptions.id = welcome_sequence_queue.subscriber_id
+      WHERE
+        welcome_sequence_queue.next_attempt_at <= now() AND (
+          welcome_sequence_queue.locked_until IS NULL OR
+          welcome_sequence_queue.locked_until < now()
+        ) AND
+        lower(split_part(subscriptions.email, '@', 2)) <> ALL($4)
+      FOR UPDATE OF welcome_sequence_queue
+      SKIP LOCKED
+      LIMIT $2
+    ) claimed
+    JOIN subscriptions
+      ON subscriptions.id = claimed.subscriber_id
+    JOIN welcome_sequence_steps steps
+      ON steps.step_id = claimed.step_id
+    WHERE
+      welcome_sequence_queue.step_id = claimed.step_id AND
+      welcome_sequence_queue.subscriber_id = claimed.subscriber_id
+    RETURNING
+      welcome_sequence_queue.step_id,
+      welcome_sequence_queue.subscriber_id,
+      subscriptions.email,
+      subscriptions.name,
+      steps.subject,
+      steps.text_content,
+      steps.html_content
+    "#,
+    worker.id,
+    batch_size as i64,
+    LEASE_DURATION.as_secs_f64(),
+    &throttled_domains,
+  )
+  .fetch_all(pool)
+  .await?;
+  if tasks.is_empty() {
+    return Ok(ExecutionOutcome::EmptyQueue);
+  }
+
+  let mut deferred = Vec::new();
+  let mut sendable = Vec::with_capacity(tasks.len());
+  for task in tasks {
+    match worker.rate_limiter.try_acquire(&task.email) {
+      Ok(()) => sendable.push(task),
+      Err(wait) => deferred.push((task, wait)),
+    }
+  }
+  let tasks = sendable;
+  defer_sequence_tasks(pool, &worker.id, deferred).await?;
+  let rendered: Vec<_> = tasks.iter().map(render_sequence_task).collect();
+  let messages: Vec<_> = rendered
+    .iter()
+    .flatten()
+    .map(|(email, rendered)| EmailMessage {
+      to: email,
+      subject: &rendered.title,
+      text_body: &rendered.text_content,
+      html_body: &rendered.html_content,
+      attachments: &[],
+      reference: None,
+    })
+    .collect();
+  let mut results = email_client.send_emails(&messages).await.into_iter();
+  let mut retried = Vec::new();
+  for (task, rendered) in tasks.into_iter().zip(&rendered) {
+    let outcome = match rendered.as_ref().map(|_| results.next().expect("Every message has a result.")) {
+      Some(Ok(())) => "delivered",
+      Some(Err(SendError::Rejected(reason))) => {
+        tracing::error!(
+          step_id = %task.step_id,
+          subscriber_email = %task.email,
+          reason,
+          "Failed to deliver a welcome sequence email. Skipping.",
+        );
+        "failed"
+      }
+      Some(Err(reason)) => {
+        tracing::warn!(
+          step_id = %task.step_id,
+          subscriber_email = %task.email,
+          reason = %reason,
+          "Failed to deliver a welcome sequence email. Retrying later.",
+        );
+        retried.push((task, UNAVAILABLE_PROVIDER_RETRY));
+        continue;
+      }
+      None => "failed",
+    };
+    complete_sequence_task(pool, &worker.id, task.step_id, task.subscriber_id, outcome).await?;
+  }
+  defer_sequence_tasks(pool, &worker.id, retried).await?;
+  Ok(ExecutionOutcome::TaskCompleted)
+}
+
+struct SequenceTask {
+  step_id: Uuid,
+  subscriber_id: Uuid,
+  email: String,
+  name: String,
+  subject: String,
+  text_content: String,
+  html_content: String,
+}
+
+/// Renders a welcome email for its subscriber, or nothing when their stored
+/// address is invalid.
+fn render_sequence_task(task: &SequenceTask) -> Option<(SubscriberEmail, RenderedIssue)> {
+  let email = match SubscriberEmail::parse(task.email.clone()) {
+    Ok(email) => email,
+    Err(e) => {
+      tracing::error!(
+        error.cause_chain = ?e,
+        error.message = %e,
+        step_id = %task.step_id,
+        subscriber_email = %task.email,
+        "Skipping a welcome sequence email.\
+        The subscriber's stored contact details are invalid."
+      );
+      return None;
+    }
+  };
+  let content = IssueContent {
+    title: &task.subject,
+    preheader: "",
+    text_content: &task.text_content,
+    html_content: &task.html_content,
+  };
+  let merge_data = MergeData {
+    name: task.name.clone(),
+    email: task.email.clone(),
+  };
+  Some((email, render_issue(&content, &merge_data)))
+}
+
+/// Releases rows the send rate or the provider could not take, to be
+/// claimed again once their wait is over.
+#[tracing::instrument(skip_all, fields(deferred=deferred.len()))]
+async fn defer_sequence_tasks(
+  pool: &PgPool,
+  worker_id: &str,
+  deferred: Vec<(SequenceTask, Duration)>,
+) -> Result<(), anyhow::Error> {
+  if deferred.is_empty() {
+    return Ok(());
+  }
+  let mut step_ids = Vec::with_capacity(deferred.len());
+  let mut subscriber_ids = Vec::with_capacity(deferred.len());
+  let mut waits = Vec::with_capacity(deferred.len());
+  for (task, wait) in deferred {
+    step_ids.push(task.step_id);
+    subscriber_ids.push(task.subscriber_id);
+    waits.push(wait.as_secs_f64());
+  }
+  sqlx::query!(
+    r#"
+    UPDATE welcome_sequence_queue
+    SET
+      next_attempt_at = now() + make_interval(secs => deferred.wait),
+      locked_until = NULL,
+      locked_by = NULL
+    FROM UNNEST($2::uuid[], $3::uuid[], $4::float8[])
+      AS deferred(step_id, subscriber_id, wait)
+    WHERE
+      welcome_sequence_queue.step_id = deferred.step_id AND
+      welcome_sequence_queue.subscriber_id = deferred.subscriber_id AND
+      welcome_sequence_queue.locked_by = $1
+    "#,
+    worker_id,
+    &step_ids,
+    &subscriber_ids,
+    &waits,
+  )
+  .execute(pool)
+  .await?;
+  Ok(())
+}
+
+/// Removes the worker's row from the queue and logs the outcome, unless the
+/// lease expired and another worker reclaimed the row, or the subscriber
+/// unsubscribed in the meantime.
+#[tracing::instrument(skip(pool))]
+async fn complete_sequence_task(
+  pool: &PgPool,
+  worker_id: &str,
+  step_id: Uuid,
+  subscriber_id: Uuid,
+  outcome: &str,
+) -> Result<(), anyhow::Error> {
+  sqlx::query!(
+    r#"
+    WITH completed AS (
+      DELETE FROM welcome_sequence_queue
+      WHERE step_id = $1 AND subscriber_id = $2 AND locked_by = $3
+      RETURNING step_id, subscriber_id
+    )
+    INSERT INTO welcome_sequence_log (step_id, subscriber_id, outcome, recorded_at)
+    SELECT step_id, subscriber_id, $4, now()
+    FROM completed
+    "#,
+    step_id,
+    subscriber_id,
+    worker_id,
+    outcome,
+  )
+  .execute(pool)
+  .await?;
+  Ok(())
+}
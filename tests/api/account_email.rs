@@ -0,0 +1,60 @@
+use serde_json::json;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{spawn_app, assert_is_redirect_to};
+use crate::newsletter::when_sending_an_email;
+
+#[tokio::test]
+async fn must_be_logged_in_to_change_the_account_email() {
+  let app = spawn_app().await;
+
+  let response = app.post_change_email(&json!({"email": "admin@example.com"})).await;
+
+  assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn invalid_emails_are_rejected() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+
+  let response = app.post_change_email(&json!({"email": "not-an-email"})).await;
+
+  assert_is_redirect_to(&response, "/admin/email");
+  let html_page = app.get_change_email_html().await;
+  assert!(html_page.contains("Please enter a valid email address."));
+  assert!(html_page.contains(&format!(r#"value="{}""#, app.test_user.email)));
+}
+
+#[tokio::test]
+async fn admins_without_an_email_are_pointed_to_the_account_page() {
+  let app = spawn_app().await;
+  sqlx::query!("UPDATE users SET email = NULL WHERE user_id = $1", app.test_user.user_id)
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+  app.test_user.login(&app).await;
+  let issue = json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+  });
+
+  let html_page = app.post_send_test_newsletter(&issue).await.text().await.unwrap();
+  assert!(html_page.contains(r#"<a href="/admin/email">account email</a>"#));
+
+  let response = app.post_change_email(&json!({"email": "admin@example.com"})).await;
+  assert_is_redirect_to(&response, "/admin/email");
+  let html_page = app.get_change_email_html().await;
+  assert!(html_page.contains("Your email address has been saved."));
+  assert!(html_page.contains(r#"value="admin@example.com""#));
+
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  let html_page = app.post_send_test_newsletter(&issue).await.text().await.unwrap();
+  assert!(html_page.contains("A test email is on its way to admin@example.com"));
+  app.dispatch_transactional_emails().await;
+}
\ No newline at end of file
@@ -0,0 +1,263 @@
+use serde_json::json;
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{spawn_app, assert_is_redirect_to, TestApp};
+use crate::newsletter::{create_comfirmed_subscriber, when_sending_an_email};
+
+async fn publish_issue(app: &TestApp) -> Uuid {
+  let response = app.post_submit_newsletter(json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": Uuid::new_v4().to_string(),
+  })).await;
+  assert_is_redirect_to(&response, "/admin/newsletters");
+
+  sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id
+}
+
+#[tokio::test]
+async fn must_be_logged_in_to_see_the_issue_history() {
+  let app = spawn_app().await;
+
+  let response = app.get_issue_history().await;
+
+  assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn issue_history_lists_published_issues_with_their_author() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let issue_id = publish_issue(&app).await;
+
+  let html_page = app.get_issue_history().await.text().await.unwrap();
+
+  assert!(html_page.contains(&format!("/admin/issues/{}", issue_id)));
+  assert!(html_page.contains("Newsletter title"));
+  assert!(html_page.contains(&app.test_user.username));
+}
+
+#[tokio::test]
+async fn issue_progress_tracks_delivery() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  let issue_id = publish_issue(&app).await;
+
+  let html_page = app.get_issue_progress(&issue_id.to_string()).await.text().await.unwrap();
+  assert!(html_page.contains("Status:</strong> Sending"));
+  assert!(html_page.contains("Enqueued: 1"));
+  assert!(html_page.contains("Remaining: 1"));
+  assert!(html_page.contains(r#"http-equiv="refresh""#));
+
+  app.displatch_all_pending_emails().await;
+
+  let html_page = app.get_issue_progress(&issue_id.to_string()).await.text().await.unwrap();
+  assert!(html_page.contains("Status:</strong> Finished"));
+  assert!(html_page.contains("Enqueued: 1"));
+  assert!(html_page.contains("Delivered: 1"));
+  assert!(html_page.contains("Remaining: 0"));
+  assert!(!html_page.contains(r#"http-equiv="refresh""#));
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_reported() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  // Postmark turns the email down, which is not worth trying again.
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(422))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  let issue_id = publish_issue(&app).await;
+
+  app.displatch_all_pending_emails().await;
+
+  let html_page = app.get_issue_progress(&issue_id.to_string()).await.text().await.unwrap();
+  assert!(html_page.contains("Delivered: 0"));
+  assert!(html_page.contains("Failed: 1"));
+  assert!(html_page.contains("Remaining: 0"));
+}
+
+#[tokio::test]
+async fn progress_of_an_unknown_issue_returns_a_404() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+
+  let response = app.get_issue_progress(&Uuid::new_v4().to_string()).await;
+
+  assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn must_be_logged_in_to_pause_an_issue() {
+  let app = spawn_app().await;
+
+  let response = app.post_issue_action(&Uuid::new_v4().to_string(), "pause").await;
+
+  assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn paused_issues_are_not_delivered_until_resumed() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  let issue_id = publish_issue(&app).await.to_string();
+  let progress_page = format!("/admin/issues/{}", issue_id);
+
+  let response = app.post_issue_action(&issue_id, "pause").await;
+  assert_is_redirect_to(&response, &progress_page);
+  {
+    let _mock_guard = when_sending_an_email()
+      .respond_with(ResponseTemplate::new(200))
+      .expect(0)
+      .mount_as_scoped(&app.email_server)
+      .await;
+    app.displatch_all_pending_emails().await;
+  }
+  let html_page = app.get_issue_progress(&issue_id).await.text().await.unwrap();
+  assert!(html_page.contains("The delivery has been paused."));
+  assert!(html_page.contains("Status:</strong> Paused"));
+  assert!(html_page.contains("Remaining: 1"));
+
+  let response = app.post_issue_action(&issue_id, "resume").await;
+  assert_is_redirect_to(&response, &progress_page);
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  app.displatch_all_pending_emails().await;
+
+  let html_page = app.get_issue_progress(&issue_id).await.text().await.unwrap();
+  assert!(html_page.contains("Delivered: 1"));
+  assert!(html_page.contains("Remaining: 0"));
+}
+
+#[tokio::test]
+async fn cancelled_deliveries_are_recorded_rather_than_sent() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+  let issue_id = publish_issue(&app).await;
+
+  let response = app.post_issue_action(&issue_id.to_string(), "cancel").await;
+  assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+  app.displatch_all_pending_emails().await;
+
+  let html_page = app.get_issue_progress(&issue_id.to_string()).await.text().await.unwrap();
+  assert!(html_page.contains("1 pending emails will not be sent"));
+  assert!(html_page.contains("Status:</strong> Cancelled"));
+  assert!(html_page.contains("Enqueued: 1"));
+  assert!(html_page.contains("Cancelled: 1"));
+  assert!(html_page.contains("Remaining: 0"));
+
+  let logged = sqlx::query!(
+    "SELECT outcome FROM newsletter_delivery_log WHERE newsletter_issue_id = $1",
+    issue_id
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap();
+  assert_eq!(logged.outcome, "cancelled");
+}
+
+#[tokio::test]
+async fn cancelled_issues_cannot_be_resumed() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let issue_id = publish_issue(&app).await.to_string();
+  app.post_issue_action(&issue_id, "cancel").await;
+
+  let response = app.post_issue_action(&issue_id, "resume").await;
+  assert_is_redirect_to(&response, &format!("/admin/issues/{}", issue_id));
+
+  let html_page = app.get_issue_progress(&issue_id).await.text().await.unwrap();
+  assert!(html_page.contains("Only a paused issue can be resumed."));
+  assert!(html_page.contains("Status:</strong> Cancelled"));
+}
+
+#[tokio::test]
+async fn issues_record_who_published_them_and_their_idempotency_key() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let idempotency_key = Uuid::new_v4().to_string();
+  app.post_submit_newsletter(json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": idempotency_key,
+  })).await;
+
+  let issue = sqlx::query!(
+    "SELECT newsletter_issue_id, author_id, published_by, idempotency_key FROM newsletter_issues"
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap();
+  assert_eq!(issue.author_id, Some(app.test_user.user_id));
+  assert_eq!(issue.published_by, Some(app.test_user.user_id));
+  assert_eq!(issue.idempotency_key.as_deref(), Some(idempotency_key.as_str()));
+
+  let html_page = app.get_issue_progress(&issue.newsletter_issue_id.to_string())
+    .await
+    .text()
+    .await
+    .unwrap();
+  assert!(html_page.contains(&format!("published by {}", app.test_user.username)));
+  assert!(html_page.contains(&format!("Idempotency key: {}", idempotency_key)));
+}
+
+#[tokio::test]
+async fn admin_actions_are_recorded_in_the_audit_trail() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  let issue_id = publish_issue(&app).await;
+
+  for action in ["pause", "resume", "cancel"] {
+    app.post_issue_action(&issue_id.to_string(), action).await;
+  }
+  // Rejected actions are not recorded.
+  app.post_issue_action(&issue_id.to_string(), "resume").await;
+
+  let events = sqlx::query!(
+    r#"
+    SELECT action, user_id, details
+    FROM newsletter_issue_events
+    WHERE newsletter_issue_id = $1
+    ORDER BY occurred_at
+    "#,
+    issue_id,
+  )
+  .fetch_all(&app.db_pool)
+  .await
+  .unwrap();
+  let actions: Vec<_> = events.iter().map(|e| e.action.as_str()).collect();
+  assert_eq!(actions, ["published", "paused", "resumed", "cancelled"]);
+  assert!(events.iter().all(|e| e.user_id == Some(app.test_user.user_id)));
+  assert_eq!(events[3].details, "1 pending emails cancelled");
+
+  let html_page = app.get_issue_progress(&issue_id.to_string()).await.text().await.unwrap();
+  assert!(html_page.contains("Audit Trail"));
+  assert!(html_page.contains("<td>paused</td>"));
+}
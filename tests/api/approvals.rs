@@ -0,0 +1,193 @@
+use serde_json::json;
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::drafts::{create_draft, draft_body};
+use crate::helpers::{spawn_app_requiring_approval, assert_is_redirect_to, TestApp, TestUser};
+use crate::newsletter::{create_comfirmed_subscriber, when_sending_an_email};
+
+async fn review_status(app: &TestApp, draft_id: &str) -> String {
+  sqlx::query!(
+    "SELECT review_status FROM newsletter_drafts WHERE draft_id = $1",
+    Uuid::parse_str(draft_id).unwrap(),
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap()
+  .review_status
+}
+
+/// Logs the test user out and a second, newly created admin in.
+async fn login_as_reviewer(app: &TestApp) -> TestUser {
+  let reviewer = TestUser::generate();
+  reviewer.store(&app.db_pool).await;
+  app.post_logout().await;
+  reviewer.login(app).await;
+  reviewer
+}
+
+fn publish_body(draft_id: &str, html_content: &str) -> serde_json::Value {
+  let mut body = draft_body("Draft title", html_content);
+  body["draft_id"] = json!(draft_id);
+  body
+}
+
+#[tokio::test]
+async fn issues_must_be_published_from_an_approved_draft() {
+  let app = spawn_app_requiring_approval().await;
+  app.test_user.login(&app).await;
+
+  let response = app.post_submit_newsletter(&draft_body("Draft title", "<p>Body</p>")).await;
+  assert_eq!(response.status().as_u16(), 400);
+
+  let draft_id = create_draft(&app, "Draft title", "<p>Body</p>").await;
+  let response = app.post_submit_newsletter(&publish_body(&draft_id, "<p>Body</p>")).await;
+  assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn authors_cannot_approve_their_own_drafts() {
+  let app = spawn_app_requiring_approval().await;
+  app.test_user.login(&app).await;
+  let draft_id = create_draft(&app, "Draft title", "<p>Body</p>").await;
+
+  let response = app.post_draft_action(&draft_id, "submit").await;
+  assert_is_redirect_to(&response, &format!("/admin/newsletters?draft_id={}", draft_id));
+  app.post_draft_action(&draft_id, "approve").await;
+
+  let html_page = app.get_draft_editor_html(&draft_id).await;
+  assert!(html_page.contains("Drafts must be approved by an admin who did not edit them."));
+  assert_eq!(review_status(&app, &draft_id).await, "submitted");
+}
+
+#[tokio::test]
+async fn drafts_must_be_submitted_before_they_are_approved() {
+  let app = spawn_app_requiring_approval().await;
+  app.test_user.login(&app).await;
+  let draft_id = create_draft(&app, "Draft title", "<p>Body</p>").await;
+  login_as_reviewer(&app).await;
+
+  app.post_draft_action(&draft_id, "approve").await;
+
+  let html_page = app.get_draft_editor_html(&draft_id).await;
+  assert!(html_page.contains("Only drafts submitted for review can be approved."));
+  assert_eq!(review_status(&app, &draft_id).await, "draft");
+}
+
+#[tokio::test]
+async fn approved_drafts_are_sent_with_their_approval_recorded() {
+  let app = spawn_app_requiring_approval().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  let draft_id = create_draft(&app, "Draft title", "<p>Body</p>").await;
+  app.post_draft_action(&draft_id, "submit").await;
+
+  let reviewer = login_as_reviewer(&app).await;
+  app.post_draft_action(&draft_id, "approve").await;
+  let html_page = app.get_draft_editor_html(&draft_id).await;
+  assert!(html_page.contains(&format!(
+    "Submitted by {} and approved by {}, ready to send.",
+    app.test_user.username, reviewer.username
+  )));
+
+  let response = app.post_submit_newsletter(&publish_body(&draft_id, "<p>Body</p>")).await;
+  assert_is_redirect_to(&response, "/admin/newsletters");
+  app.displatch_all_pending_emails().await;
+
+  assert_eq!(review_status(&app, &draft_id).await, "sent");
+  let issue = sqlx::query!(
+    r#"
+    SELECT
+      newsletter_issue_id,
+      author_id,
+      published_by,
+      submitted_by,
+      submitted_at,
+      approved_by,
+      approved_at
+    FROM newsletter_issues
+    "#
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap();
+  // The draft's author wrote the issue, the reviewer published it.
+  assert_eq!(issue.author_id, Some(app.test_user.user_id));
+  assert_eq!(issue.published_by, Some(reviewer.user_id));
+  assert_eq!(issue.submitted_by, Some(app.test_user.user_id));
+  assert_eq!(issue.approved_by, Some(reviewer.user_id));
+  assert!(issue.submitted_at.is_some() && issue.approved_at.is_some());
+
+  let html_page = app.get_issue_progress(&issue.newsletter_issue_id.to_string())
+    .await
+    .text()
+    .await
+    .unwrap();
+  assert!(html_page.contains(&format!("Approved by {}", reviewer.username)));
+  assert!(html_page.contains(&format!(
+    "Written by {}, published by {}",
+    app.test_user.username, reviewer.username
+  )));
+
+  let actions: Vec<_> = sqlx::query!(
+    "SELECT action FROM newsletter_issue_events ORDER BY occurred_at"
+  )
+  .fetch_all(&app.db_pool)
+  .await
+  .unwrap()
+  .into_iter()
+  .map(|e| e.action)
+  .collect();
+  assert_eq!(actions, ["submitted", "approved", "published"]);
+}
+
+#[tokio::test]
+async fn edited_drafts_have_to_be_approved_again() {
+  let app = spawn_app_requiring_approval().await;
+  app.test_user.login(&app).await;
+  let draft_id = create_draft(&app, "Draft title", "<p>Body</p>").await;
+  app.post_draft_action(&draft_id, "submit").await;
+  login_as_reviewer(&app).await;
+  app.post_draft_action(&draft_id, "approve").await;
+  assert_eq!(review_status(&app, &draft_id).await, "approved");
+
+  // Content that differs from the approved draft is not sent.
+  let response = app.post_submit_newsletter(&publish_body(&draft_id, "<p>Changed</p>")).await;
+  assert_eq!(response.status().as_u16(), 400);
+
+  app.post_save_draft(Some(&draft_id), &draft_body("Draft title", "<p>Changed</p>")).await;
+  assert_eq!(review_status(&app, &draft_id).await, "draft");
+  let response = app.post_submit_newsletter(&publish_body(&draft_id, "<p>Changed</p>")).await;
+  assert_eq!(response.status().as_u16(), 400);
+}
+
+
+#[tokio::test]
+async fn publish_settings_are_approved_along_with_the_content() {
+  let app = spawn_app_requiring_approval().await;
+  app.test_user.login(&app).await;
+  let draft_id = create_draft(&app, "Draft title", "<p>Body</p>").await;
+  app.post_draft_action(&draft_id, "submit").await;
+  login_as_reviewer(&app).await;
+  app.post_draft_action(&draft_id, "approve").await;
+
+  // Settings that differ from the approved draft are not sent either.
+  let mut body = publish_body(&draft_id, "<p>Body</p>");
+  body["subject_variants"] = json!("A subject nobody reviewed");
+  let response = app.post_submit_newsletter(&body).await;
+  assert_eq!(response.status().as_u16(), 400);
+  assert_eq!(review_status(&app, &draft_id).await, "approved");
+
+  // Saved on the draft, they are shown in the editor and need a new review.
+  app.post_save_draft(Some(&draft_id), &body).await;
+  assert_eq!(review_status(&app, &draft_id).await, "draft");
+  let html_page = app.get_draft_editor_html(&draft_id).await;
+  assert!(html_page.contains(">A subject nobody reviewed</textarea>"));
+  let response = app.post_submit_newsletter(&body).await;
+  assert_eq!(response.status().as_u16(), 400);
+}
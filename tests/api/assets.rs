@@ -0,0 +1,201 @@
+use serde_json::json;
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{spawn_app, assert_is_redirect_to, TestApp};
+use crate::newsletter::{create_comfirmed_subscriber, when_sending_an_email};
+
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\nnot really an image";
+
+async fn upload_asset(app: &TestApp, file_name: &str, content_type: &str, content: &[u8]) -> Uuid {
+  let response = app.post_upload_asset(file_name, content_type, content).await;
+  assert_is_redirect_to(&response, "/admin/assets");
+
+  sqlx::query!("SELECT asset_id FROM newsletter_assets WHERE file_name = $1", file_name)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .asset_id
+}
+
+/// Publishes an issue with the given HTML and returns the body of the email
+/// delivered to the only confirmed subscriber.
+async fn publish_and_deliver(app: &TestApp, html_content: &str, asset_delivery: &str) -> serde_json::Value {
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  let response = app.post_submit_newsletter(&json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": html_content,
+    "asset_delivery": asset_delivery,
+    "disable_open_tracking": "true",
+    "disable_click_tracking": "true",
+    "idempotency_key": Uuid::new_v4().to_string(),
+  })).await;
+  assert_is_redirect_to(&response, "/admin/newsletters");
+  app.displatch_all_pending_emails().await;
+
+  let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+  serde_json::from_slice(&email_request.body).unwrap()
+}
+
+#[tokio::test]
+async fn must_be_logged_in_to_manage_assets() {
+  let app = spawn_app().await;
+
+  let response = app.post_upload_asset("logo.png", "image/png", PNG).await;
+  assert_is_redirect_to(&response, "/login");
+
+  let response = app.api_client
+    .get(format!("{}/admin/assets", &app.address))
+    .send()
+    .await
+    .unwrap();
+  assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn uploaded_assets_are_listed_and_served() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+
+  let asset_id = upload_asset(&app, "logo.png", "image/png", PNG).await;
+
+  let html_page = app.get_asset_library_html().await;
+  assert!(html_page.contains(&format!("Reference it as asset:{}", asset_id)));
+  assert!(html_page.contains("logo.png"));
+
+  let response = app.get_asset(&asset_id.to_string()).await;
+  assert_eq!(response.status().as_u16(), 200);
+  assert_eq!(response.headers()["Content-Type"], "image/png");
+  assert_eq!(response.headers()["X-Content-Type-Options"], "nosniff");
+  assert!(response.headers()["Content-Disposition"].to_str().unwrap().starts_with("inline"));
+  assert_eq!(response.bytes().await.unwrap().as_ref(), PNG);
+}
+
+#[tokio::test]
+async fn documents_are_served_as_downloads() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+
+  let asset_id = upload_asset(&app, "report.pdf", "application/pdf", b"%PDF").await;
+
+  let response = app.get_asset(&asset_id.to_string()).await;
+  assert_eq!(response.status().as_u16(), 200);
+  assert_eq!(response.headers()["X-Content-Type-Options"], "nosniff");
+  assert!(response.headers()["Content-Disposition"].to_str().unwrap().starts_with("attachment"));
+}
+
+#[tokio::test]
+async fn content_types_that_can_run_scripts_are_rejected() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+
+  for (file_name, content_type) in [("page.html", "text/html"), ("logo.svg", "image/svg+xml")] {
+    let response = app.post_upload_asset(file_name, content_type, b"<script>alert(1)</script>").await;
+    assert_is_redirect_to(&response, "/admin/assets");
+  }
+
+  let html_page = app.get_asset_library_html().await;
+  assert!(html_page.contains("Only PNG, JPEG, GIF and WebP images"));
+  let stored = sqlx::query!("SELECT COUNT(*) as \"n!\" FROM newsletter_assets")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(stored.n, 0);
+}
+
+#[tokio::test]
+async fn unknown_assets_return_a_404() {
+  let app = spawn_app().await;
+
+  let response = app.get_asset(&Uuid::new_v4().to_string()).await;
+
+  assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn issues_referencing_unknown_assets_are_rejected() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+
+  let response = app.post_submit_newsletter(&json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": format!(r#"<img src="asset:{}">"#, Uuid::new_v4()),
+    "idempotency_key": Uuid::new_v4().to_string(),
+  })).await;
+
+  assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn hosted_assets_are_delivered_as_links() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  let asset_id = upload_asset(&app, "logo.png", "image/png", PNG).await;
+
+  let body = publish_and_deliver(&app, &format!(r#"<img src="asset:{}">"#, asset_id), "hosted").await;
+
+  assert_eq!(
+    body["HtmlBody"],
+    format!(r#"<img src="{}/assets/{}">"#, app.base_url, asset_id)
+  );
+  assert!(body.get("Attachments").is_none());
+}
+
+#[tokio::test]
+async fn attached_assets_are_sent_as_postmark_attachments() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  let image_id = upload_asset(&app, "logo.png", "image/png", PNG).await;
+  let document_id = upload_asset(&app, "report.pdf", "application/pdf", b"%PDF").await;
+
+  let html = format!(
+    r#"<img src="asset:{}"><a href="asset:{}">Report</a>"#,
+    image_id, document_id
+  );
+  let body = publish_and_deliver(&app, &html, "attached").await;
+
+  assert_eq!(
+    body["HtmlBody"],
+    format!(
+      r#"<img src="cid:{}"><a href="{}/assets/{}">Report</a>"#,
+      image_id, app.base_url, document_id
+    )
+  );
+  let attachments = body["Attachments"].as_array().unwrap();
+  assert_eq!(attachments.len(), 2);
+  let image = attachments.iter().find(|a| a["Name"] == "logo.png").unwrap();
+  assert_eq!(image["ContentType"], "image/png");
+  assert_eq!(image["ContentID"], format!("cid:{}", image_id));
+  let document = attachments.iter().find(|a| a["Name"] == "report.pdf").unwrap();
+  assert_eq!(document["Content"], "JVBERg==");
+  assert!(document.get("ContentID").is_none());
+}
+
+#[tokio::test]
+async fn issues_attaching_more_than_an_email_can_carry_are_rejected() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let first_id = upload_asset(&app, "first.png", "image/png", &vec![0; 6_000_000]).await;
+  let second_id = upload_asset(&app, "second.png", "image/png", &vec![0; 6_000_000]).await;
+  let html = format!(r#"<img src="asset:{}"><img src="asset:{}">"#, first_id, second_id);
+
+  for (asset_delivery, status) in [("attached", 400), ("hosted", 303)] {
+    let response = app.post_submit_newsletter(&json!({
+      "title": "Newsletter title",
+      "text_content": "Newsletter body as plaintext",
+      "html_content": html,
+      "asset_delivery": asset_delivery,
+      "idempotency_key": Uuid::new_v4().to_string(),
+    })).await;
+
+    assert_eq!(response.status().as_u16(), status, "{} assets", asset_delivery);
+  }
+}
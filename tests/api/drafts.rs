@@ -0,0 +1,170 @@
+use serde_json::json;
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{spawn_app, assert_is_redirect_to, TestApp};
+use crate::newsletter::{create_comfirmed_subscriber, when_sending_an_email};
+
+pub fn draft_body(title: &str, html_content: &str) -> serde_json::Value {
+  json!({
+    "title": title,
+    "preheader": "A short preview",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": html_content,
+    // The editor posts every field to the save action; extra ones are ignored.
+    "idempotency_key": Uuid::new_v4().to_string(),
+  })
+}
+
+/// Saves a new draft and returns its id.
+pub async fn create_draft(app: &TestApp, title: &str, html_content: &str) -> String {
+  let response = app.post_save_draft(None, &draft_body(title, html_content)).await;
+  let location = response.headers()["Location"].to_str().unwrap().to_owned();
+  location
+    .strip_prefix("/admin/newsletters?draft_id=")
+    .expect("Saving a draft did not redirect to the editor.")
+    .to_owned()
+}
+
+async fn revision_count(app: &TestApp, draft_id: &str) -> i64 {
+  sqlx::query!(
+    r#"SELECT COUNT(*) as "count!" FROM newsletter_draft_revisions WHERE draft_id = $1"#,
+    Uuid::parse_str(draft_id).unwrap(),
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap()
+  .count
+}
+
+#[tokio::test]
+async fn must_be_logged_in_to_manage_drafts() {
+  let app = spawn_app().await;
+
+  let response = app.post_save_draft(None, &draft_body("Title", "<p>Body</p>")).await;
+  assert_is_redirect_to(&response, "/login");
+
+  let response = app.get_drafts().await;
+  assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn saved_drafts_are_listed_and_loaded_into_the_editor() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+
+  let draft_id = create_draft(&app, "Draft title", "<p>Draft body</p>").await;
+
+  let html_page = app.get_draft_editor_html(&draft_id).await;
+  assert!(html_page.contains("The draft has been saved."));
+  assert!(html_page.contains(r#"value="Draft title""#));
+  assert!(html_page.contains("&lt;p&gt;Draft body&lt;/p&gt;"));
+  assert!(html_page.contains("Editing draft revision 1"));
+
+  let html_page = app.get_drafts().await.text().await.unwrap();
+  assert!(html_page.contains("Draft title"));
+  assert!(html_page.contains(&app.test_user.username));
+}
+
+#[tokio::test]
+async fn edits_are_stored_as_revisions_with_a_diff() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let draft_id = create_draft(&app, "Draft title", "<p>One</p>\n<p>Two</p>").await;
+
+  app.post_save_draft(Some(&draft_id), &draft_body("New title", "<p>One</p>\n<p>Three</p>")).await;
+  // Saving unchanged content does not add a revision.
+  app.post_save_draft(Some(&draft_id), &draft_body("New title", "<p>One</p>\n<p>Three</p>")).await;
+
+  assert_eq!(revision_count(&app, &draft_id).await, 2);
+  let html_page = app.get_draft_revisions_html(&draft_id).await;
+  assert!(html_page.contains("<del>Draft title</del> &rarr; <ins>New title</ins>"));
+  assert!(html_page.contains(r#"<span class="diff-removed">- &lt;p&gt;Two&lt;/p&gt;</span>"#));
+  assert!(html_page.contains(r#"<span class="diff-added">+ &lt;p&gt;Three&lt;/p&gt;</span>"#));
+  assert!(html_page.contains(&format!("Edited by {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn restoring_a_revision_creates_a_new_one() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let draft_id = create_draft(&app, "Draft title", "<p>First</p>").await;
+  app.post_save_draft(Some(&draft_id), &draft_body("Draft title", "<p>Second</p>")).await;
+
+  let response = app.post_restore_revision(&draft_id, 1).await;
+  assert_is_redirect_to(&response, &format!("/admin/newsletters?draft_id={}", draft_id));
+
+  assert_eq!(revision_count(&app, &draft_id).await, 3);
+  let html_page = app.get_draft_editor_html(&draft_id).await;
+  assert!(html_page.contains("Revision 1 has been restored."));
+  assert!(html_page.contains("&lt;p&gt;First&lt;/p&gt;"));
+  assert!(html_page.contains("Editing draft revision 3"));
+}
+
+#[tokio::test]
+async fn restoring_a_revision_the_draft_already_matches_changes_nothing() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let draft_id = create_draft(&app, "Draft title", "<p>First</p>").await;
+  app.post_save_draft(Some(&draft_id), &draft_body("Draft title", "<p>Second</p>")).await;
+  app.post_save_draft(Some(&draft_id), &draft_body("Draft title", "<p>First</p>")).await;
+
+  let response = app.post_restore_revision(&draft_id, 1).await;
+  assert_is_redirect_to(&response, &format!("/admin/newsletters?draft_id={}", draft_id));
+
+  assert_eq!(revision_count(&app, &draft_id).await, 3);
+  let html_page = app.get_draft_editor_html(&draft_id).await;
+  assert!(html_page.contains("The draft is already the same as revision 1, there was nothing to restore."));
+  assert!(!html_page.contains("Revision 1 has been restored."));
+}
+
+#[tokio::test]
+async fn published_drafts_can_no_longer_be_edited() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .mount(&app.email_server)
+    .await;
+  let draft_id = create_draft(&app, "Draft title", "<p>Draft body</p>").await;
+
+  let mut body = draft_body("Draft title", "<p>Draft body</p>");
+  body["draft_id"] = json!(draft_id);
+  let response = app.post_submit_newsletter(&body).await;
+  assert_is_redirect_to(&response, "/admin/newsletters");
+
+  app.post_save_draft(Some(&draft_id), &draft_body("Changed", "<p>Changed</p>")).await;
+  assert_eq!(revision_count(&app, &draft_id).await, 1);
+  let html_page = app.get_draft_editor_html(&draft_id).await;
+  assert!(html_page.contains("This draft has already been published"));
+
+  // A draft is only published once.
+  body["idempotency_key"] = json!(Uuid::new_v4().to_string());
+  let response = app.post_submit_newsletter(&body).await;
+  assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn sent_issues_can_be_duplicated_as_drafts() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  app.post_submit_newsletter(&draft_body("Sent issue", "<p>Sent body</p>")).await;
+  let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id
+    .to_string();
+
+  let response = app.post_issue_action(&issue_id, "duplicate").await;
+  let location = response.headers()["Location"].to_str().unwrap().to_owned();
+  let html_page = app.get_draft_editor_html(
+    location.strip_prefix("/admin/newsletters?draft_id=").unwrap()
+  ).await;
+
+  assert!(html_page.contains("The issue has been duplicated into a new draft."));
+  assert!(html_page.contains(r#"value="Sent issue""#));
+  assert!(html_page.contains(r#"value="A short preview""#));
+  assert!(html_page.contains("&lt;p&gt;Sent body&lt;/p&gt;"));
+}
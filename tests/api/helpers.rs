@@ -4,11 +4,16 @@ use sqlx::{PgPool, PgConnection, Connection, Executor};
 use uuid::Uuid;
 use wiremock::MockServer;
 use scoop::{
-  configuration::{get_configuration, DatabaseSettings},
+  configuration::{get_configuration, AssetStorageSettings, DatabaseSettings, Settings},
   telemetry::{get_subscriber, init_subscriber},
-  startup::{get_connection_pool, Application}, email_client::EmailClient, issue_delivery_workers::{ExecutionOutcome, try_execute_task},
+  startup::{get_connection_pool, Application}, email_client::EmailClient, issue_delivery_workers::{ExecutionOutcome, Worker, run_worker_till_stopped, try_execute_round, try_execute_task},
+  tracking::TrackingLinks, asset_storage::AssetStorage, rate_limiter::RateLimiter,
+  shutdown::{shutdown_channel, ShutdownSwitch},
+  welcome_sequences::{schedule_welcome_sequence_steps, try_execute_sequence_task},
+  transactional_emails::try_execute_transactional_task, priority_lanes::Lane,
 };
 use once_cell::sync::Lazy;
+use tokio::task::JoinHandle;
 
 static TRACING: Lazy<()> = Lazy::new(|| {
   let default_filter_level = "info".to_string();
@@ -30,7 +35,13 @@ pub struct TestApp {
   pub email_server: MockServer,
   pub test_user: TestUser,
   pub api_client: reqwest::Client,
-  pub email_client: EmailClient
+  pub email_client: EmailClient,
+  pub tracking_links: TrackingLinks,
+  pub asset_storage: AssetStorage,
+  pub base_url: String,
+  pub sends_per_worker: usize,
+  pub rate_limiter: RateLimiter,
+  pub configuration: Settings,
 }
 
 pub struct ConfirmationLinks {
@@ -39,18 +50,69 @@ pub struct ConfirmationLinks {
 }
 
 impl TestApp {
-  pub async fn displatch_all_pending_emails(&self) {
-    loop {
-      if let ExecutionOutcome::EmptyQueue =
-        try_execute_task(&self.db_pool, &self.email_client)
-          .await
-          .unwrap()
-          {
-            break;
-          }
+  fn worker(&self) -> Worker<'_> {
+    Worker {
+      id: "test-worker".into(),
+      batch_size: self.sends_per_worker,
+      rate_limiter: &self.rate_limiter,
+      bulk_min_share: self.configuration.delivery.bulk_min_share,
     }
   }
 
+  /// Runs the background delivery workers, as the application binary does,
+  /// until the returned switch is triggered.
+  pub fn spawn_workers(&self) -> (ShutdownSwitch, JoinHandle<Result<(), anyhow::Error>>) {
+    let (shutdown_switch, shutdown) = shutdown_channel();
+    let workers = tokio::spawn(run_worker_till_stopped(self.configuration.clone(), shutdown));
+    (shutdown_switch, workers)
+  }
+
+  /// Sends queued emails until the queue is empty or the send rate holds
+  /// the rest back.
+  pub async fn displatch_all_pending_emails(&self) {
+    while let ExecutionOutcome::TaskCompleted =
+      try_execute_task(
+        &self.db_pool,
+        &self.email_client,
+        &self.tracking_links,
+        &self.asset_storage,
+        &self.base_url,
+        &self.worker(),
+      )
+        .await
+        .unwrap()
+    {}
+  }
+
+  pub async fn dispatch_welcome_sequence_emails(&self) {
+    schedule_welcome_sequence_steps(&self.db_pool).await.unwrap();
+    while let ExecutionOutcome::TaskCompleted =
+      try_execute_sequence_task(&self.db_pool, &self.email_client, &self.worker()).await.unwrap()
+    {}
+  }
+
+  /// Sends the queued transactional emails that are due.
+  pub async fn dispatch_transactional_emails(&self) {
+    while let ExecutionOutcome::TaskCompleted =
+      try_execute_transactional_task(&self.db_pool, &self.email_client, &self.worker()).await.unwrap()
+    {}
+  }
+
+  /// Runs a single round of a worker, serving `first_lane` first.
+  pub async fn run_worker_round(&self, first_lane: Lane) -> ExecutionOutcome {
+    try_execute_round(
+      &self.db_pool,
+      &self.email_client,
+      &self.tracking_links,
+      &self.asset_storage,
+      &self.base_url,
+      &self.worker(),
+      first_lane,
+    )
+      .await
+      .unwrap()
+  }
+
   pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
     self.api_client
       .post(&format!("{}/subscriptions", &self.address))
@@ -125,6 +187,30 @@ impl TestApp {
       .expect("Failed to execute request.")
   }
 
+  pub async fn post_preview_newsletter<Body>(&self, body: &Body) -> reqwest::Response
+  where
+    Body: serde::Serialize,
+  {
+    self.api_client
+      .post(format!("{}/admin/newsletters/preview", &self.address))
+      .form(body)
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  pub async fn post_send_test_newsletter<Body>(&self, body: &Body) -> reqwest::Response
+  where
+    Body: serde::Serialize,
+  {
+    self.api_client
+      .post(format!("{}/admin/newsletters/test", &self.address))
+      .form(body)
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
   pub async fn get_publish_newsletter(&self) -> reqwest::Response {
     self.api_client
       .get(format!("{}/admin/newsletters", &self.address))
@@ -141,6 +227,213 @@ impl TestApp {
       .unwrap()
   }
 
+  pub async fn get_draft_editor_html(&self, draft_id: &str) -> String {
+    self.api_client
+      .get(format!("{}/admin/newsletters?draft_id={}", &self.address, draft_id))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+      .text()
+      .await
+      .unwrap()
+  }
+
+  pub async fn post_save_draft<Body>(&self, draft_id: Option<&str>, body: &Body) -> reqwest::Response
+  where
+    Body: serde::Serialize,
+  {
+    let url = match draft_id {
+      Some(draft_id) => format!("{}/admin/drafts/{}", &self.address, draft_id),
+      None => format!("{}/admin/drafts", &self.address),
+    };
+    self.api_client
+      .post(url)
+      .form(body)
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  pub async fn get_drafts(&self) -> reqwest::Response {
+    self.api_client
+      .get(format!("{}/admin/drafts", &self.address))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  pub async fn get_draft_revisions_html(&self, draft_id: &str) -> String {
+    self.api_client
+      .get(format!("{}/admin/drafts/{}/revisions", &self.address, draft_id))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+      .text()
+      .await
+      .unwrap()
+  }
+
+  pub async fn post_draft_action(&self, draft_id: &str, action: &str) -> reqwest::Response {
+    self.api_client
+      .post(format!("{}/admin/drafts/{}/{}", &self.address, draft_id, action))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  pub async fn post_restore_revision(&self, draft_id: &str, revision: i32) -> reqwest::Response {
+    self.api_client
+      .post(format!("{}/admin/drafts/{}/revisions/{}/restore", &self.address, draft_id, revision))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  pub async fn get_issue_archive_html(&self) -> String {
+    self.api_client
+      .get(format!("{}/issues", &self.address))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+      .text()
+      .await
+      .unwrap()
+  }
+
+  pub async fn get_issue_archive_page_html(&self, page: i64) -> String {
+    self.api_client
+      .get(format!("{}/issues?page={}", &self.address, page))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+      .text()
+      .await
+      .unwrap()
+  }
+
+  pub async fn get_issue_page(&self, id_or_slug: &str) -> reqwest::Response {
+    self.api_client
+      .get(format!("{}/issues/{}", &self.address, id_or_slug))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  pub async fn get_feed(&self, path: &str) -> reqwest::Response {
+    self.api_client
+      .get(format!("{}/{}", &self.address, path))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  pub async fn get_issue_history(&self) -> reqwest::Response {
+    self.api_client
+      .get(format!("{}/admin/issues", &self.address))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  pub async fn get_issue_progress(&self, issue_id: &str) -> reqwest::Response {
+    self.api_client
+      .get(format!("{}/admin/issues/{}", &self.address, issue_id))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  pub async fn get_issue_analytics(&self, issue_id: &str, format: &str) -> reqwest::Response {
+    self.api_client
+      .get(format!("{}/admin/issues/{}/{}", &self.address, issue_id, format))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  pub async fn get_welcome_sequence_html(&self, sequence_id: &str) -> String {
+    self.api_client
+      .get(format!("{}/admin/sequences/{}", &self.address, sequence_id))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+      .text()
+      .await
+      .unwrap()
+  }
+
+  pub async fn post_create_welcome_sequence<Body>(&self, body: &Body) -> reqwest::Response
+  where
+    Body: serde::Serialize,
+  {
+    self.api_client
+      .post(format!("{}/admin/sequences", &self.address))
+      .form(body)
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  pub async fn post_sequence_step<Body>(&self, sequence_id: &str, body: &Body) -> reqwest::Response
+  where
+    Body: serde::Serialize,
+  {
+    self.api_client
+      .post(format!("{}/admin/sequences/{}/steps", &self.address, sequence_id))
+      .form(body)
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  pub async fn post_sequence_action(&self, sequence_id: &str, action: &str) -> reqwest::Response {
+    self.api_client
+      .post(format!("{}/admin/sequences/{}/{}", &self.address, sequence_id, action))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  pub async fn get_asset_library_html(&self) -> String {
+    self.api_client
+      .get(format!("{}/admin/assets", &self.address))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+      .text()
+      .await
+      .unwrap()
+  }
+
+  pub async fn post_upload_asset(&self, file_name: &str, content_type: &str, content: &[u8]) -> reqwest::Response {
+    let part = reqwest::multipart::Part::bytes(content.to_vec())
+      .file_name(file_name.to_owned())
+      .mime_str(content_type)
+      .unwrap();
+    self.api_client
+      .post(format!("{}/admin/assets", &self.address))
+      .multipart(reqwest::multipart::Form::new().part("file", part))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  pub async fn get_asset(&self, asset_id: &str) -> reqwest::Response {
+    self.api_client
+      .get(format!("{}/assets/{}", &self.address, asset_id))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
+  pub async fn post_issue_action(&self, issue_id: &str, action: &str) -> reqwest::Response {
+    self.api_client
+      .post(format!("{}/admin/issues/{}/{}", &self.address, issue_id, action))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
   pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
   where 
     Body: serde::Serialize,
@@ -208,6 +501,29 @@ impl TestApp {
       .unwrap()
   }
   
+  pub async fn get_change_email_html(&self) -> String {
+    self.api_client
+      .get(format!("{}/admin/email", self.address))
+      .send()
+      .await
+      .expect("Failed to execute request.")
+      .text()
+      .await
+      .unwrap()
+  }
+
+  pub async fn post_change_email<Body>(&self, body: &Body) -> reqwest::Response
+  where
+    Body: serde::Serialize,
+  {
+    self.api_client
+      .post(format!("{}/admin/email", self.address))
+      .form(body)
+      .send()
+      .await
+      .expect("Failed to execute request.")
+  }
+
   pub async fn post_logout(&self) -> reqwest::Response
   {
     self.api_client
@@ -222,6 +538,7 @@ pub struct TestUser {
   pub user_id: Uuid,
   pub username: String,
   pub password: String,
+  pub email: String,
 }
 
 impl TestUser {
@@ -230,10 +547,11 @@ impl TestUser {
       user_id: Uuid::new_v4(),
       username: Uuid::new_v4().to_string(),
       password: Uuid::new_v4().to_string(),
+      email: format!("{}@example.com", Uuid::new_v4()),
     }
   }
 
-  async fn store(&self, pool: &PgPool) {
+  pub async fn store(&self, pool: &PgPool) {
     let salt = SaltString::generate(&mut rand::thread_rng());
     let password_hash = Argon2::default()
       .hash_password(self.password.as_bytes(), &salt)
@@ -241,11 +559,12 @@ impl TestUser {
       .to_string();
 
     sqlx::query!(
-      "INSERT INTO users (user_id, username, password_hash)
-      VALUES ($1, $2, $3)",
+      "INSERT INTO users (user_id, username, password_hash, email)
+      VALUES ($1, $2, $3, $4)",
       self.user_id,
       self.username,
       password_hash,
+      self.email,
     )
     .execute(pool)
     .await
@@ -261,7 +580,17 @@ impl TestUser {
   }
 }
 
+/// Most tests publish issues directly, so approvals are turned off unless
+/// the test opts in with `spawn_app_requiring_approval`.
 pub async fn spawn_app() -> TestApp {
+  spawn_app_with_approval(false).await
+}
+
+pub async fn spawn_app_requiring_approval() -> TestApp {
+  spawn_app_with_approval(true).await
+}
+
+async fn spawn_app_with_approval(require_issue_approval: bool) -> TestApp {
   Lazy::force(&TRACING);
 
   let email_server = MockServer::start().await;
@@ -271,6 +600,13 @@ pub async fn spawn_app() -> TestApp {
     c.database.name = Uuid::new_v4().to_string();
     c.email_client.base_url = email_server.uri();
     c.application.port = 0;
+    c.application.require_issue_approval = require_issue_approval;
+    c.asset_storage = AssetStorageSettings::Local {
+      directory: std::env::temp_dir()
+        .join(Uuid::new_v4().to_string())
+        .to_string_lossy()
+        .into_owned(),
+    };
     c
   };
   
@@ -290,11 +626,21 @@ pub async fn spawn_app() -> TestApp {
   let test_app = TestApp {
     address,
     port: application_port, 
-    db_pool: get_connection_pool(&configuration.database),
+    db_pool: get_connection_pool(&configuration.database, configuration.database.max_connections),
     email_server,
     test_user: TestUser::generate(),
     api_client,
-    email_client: configuration.email_client.client()
+    email_client: configuration.email_client.clone().client(),
+    tracking_links: TrackingLinks::new(
+      configuration.application.base_url.clone(),
+      configuration.application.hmac_secret.clone(),
+    ),
+    asset_storage: configuration.asset_storage.clone().storage(),
+    base_url: configuration.application.base_url.clone(),
+    sends_per_worker: configuration.delivery.sends_per_worker,
+    // Tests send at full speed unless they set limits of their own.
+    rate_limiter: RateLimiter::unlimited(),
+    configuration,
   };
   test_app.test_user.store(&test_app.db_pool).await;
   test_app
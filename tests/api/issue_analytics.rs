@@ -0,0 +1,90 @@
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app, assert_is_redirect_to};
+use crate::newsletter::{create_comfirmed_subscriber, when_sending_a_batch, AcceptEveryMessage};
+use crate::tracking::{attribute_url, publish_and_deliver_issue, LINK_HTML_CONTENT};
+
+#[tokio::test]
+async fn must_be_logged_in_to_see_issue_analytics() {
+  let app = spawn_app().await;
+  let issue_id = Uuid::new_v4().to_string();
+
+  for format in ["analytics", "analytics.json"] {
+    let response = app.get_issue_analytics(&issue_id, format).await;
+    assert_is_redirect_to(&response, "/login");
+  }
+}
+
+#[tokio::test]
+async fn analytics_of_unknown_issues_are_not_found() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let issue_id = Uuid::new_v4().to_string();
+
+  for format in ["analytics", "analytics.json"] {
+    let response = app.get_issue_analytics(&issue_id, format).await;
+    assert_eq!(response.status().as_u16(), 404);
+  }
+}
+
+#[tokio::test]
+async fn analytics_report_engagement_with_an_issue() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  when_sending_a_batch()
+    .respond_with(AcceptEveryMessage)
+    .mount(&app.email_server)
+    .await;
+
+  let html = publish_and_deliver_issue(&app, LINK_HTML_CONTENT, json!({})).await;
+  reqwest::get(attribute_url(&app, &html, "src")).await.unwrap();
+  for _ in 0..2 {
+    app.api_client.get(attribute_url(&app, &html, "href")).send().await.unwrap();
+  }
+  let subscription_token = sqlx::query!(
+    r#"
+    SELECT subscription_token
+    FROM subscription_tokens
+    JOIN subscriptions ON subscriptions.id = subscription_tokens.subscriber_id
+    JOIN newsletter_delivery_log ON newsletter_delivery_log.subscriber_email = subscriptions.email
+    WHERE newsletter_delivery_log.first_opened_at IS NOT NULL
+    "#
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap()
+  .subscription_token;
+  reqwest::get(format!("{}/unsubscribe?subscription_token={}", app.address, subscription_token))
+    .await
+    .unwrap();
+  let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id
+    .to_string();
+
+  let analytics: serde_json::Value = app.get_issue_analytics(&issue_id, "analytics.json")
+    .await
+    .json()
+    .await
+    .unwrap();
+
+  assert_eq!(analytics["delivered"], 2);
+  assert_eq!(analytics["opened"], 1);
+  assert_eq!(analytics["clicked"], 1);
+  assert_eq!(analytics["unsubscribed"], 1);
+  assert_eq!(analytics["open_rate"], 0.5);
+  assert_eq!(analytics["click_rate"], 0.5);
+  assert_eq!(analytics["top_links"][0]["url"], "https://example.com/post?id=1&ref=x");
+  assert_eq!(analytics["top_links"][0]["clicks"], 2);
+  assert_eq!(analytics["top_links"][0]["unique_clicks"], 1);
+  assert_eq!(analytics["opens_over_time"][0]["opens"], 1);
+
+  let html_page = app.get_issue_analytics(&issue_id, "analytics").await.text().await.unwrap();
+  assert!(html_page.contains("<td>Opened</td><td>1</td><td>50.0%</td>"));
+  assert!(html_page.contains("https://example.com/post?id=1&amp;ref=x"));
+}
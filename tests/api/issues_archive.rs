@@ -0,0 +1,169 @@
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app, assert_is_redirect_to, TestApp};
+
+async fn publish_issue(app: &TestApp, title: &str, subscribers_only: bool) -> (Uuid, String) {
+  let mut body = json!({
+    "title": title,
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Hi {{name}}, here is the news</p>",
+    "idempotency_key": Uuid::new_v4().to_string(),
+  });
+  if subscribers_only {
+    body["subscribers_only"] = json!("true");
+  }
+  let response = app.post_submit_newsletter(&body).await;
+  assert_is_redirect_to(&response, "/admin/newsletters");
+
+  let issue = sqlx::query!(
+    r#"SELECT newsletter_issue_id, slug as "slug!" FROM newsletter_issues WHERE title = $1"#,
+    title
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap();
+  (issue.newsletter_issue_id, issue.slug)
+}
+
+#[tokio::test]
+async fn published_issues_are_listed_in_the_archive() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let (_, slug) = publish_issue(&app, "First issue", false).await;
+
+  let html_page = app.get_issue_archive_html().await;
+
+  assert!(html_page.contains("First issue"));
+  assert!(html_page.contains(&format!("/issues/{}", slug)));
+}
+
+#[tokio::test]
+async fn archived_issues_can_be_read_by_id_or_slug() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let (issue_id, slug) = publish_issue(&app, "Shareable issue", false).await;
+  assert!(slug.starts_with("shareable-issue-"));
+
+  for path in [issue_id.to_string(), slug] {
+    let response = app.get_issue_page(&path).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Shareable issue"));
+    assert!(html_page.contains("<p>Hi reader, here is the news</p>"));
+  }
+}
+
+#[tokio::test]
+async fn subscriber_only_issues_are_kept_out_of_the_archive() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let (issue_id, slug) = publish_issue(&app, "Members only", true).await;
+
+  let html_page = app.get_issue_archive_html().await;
+  assert!(!html_page.contains("Members only"));
+
+  for path in [issue_id.to_string(), slug] {
+    let response = app.get_issue_page(&path).await;
+    assert_eq!(response.status().as_u16(), 404);
+  }
+}
+
+#[tokio::test]
+async fn unknown_issues_return_a_404() {
+  let app = spawn_app().await;
+
+  let response = app.get_issue_page("does-not-exist").await;
+
+  assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn rss_feed_lists_public_issues() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let (_, slug) = publish_issue(&app, "Feed issue", false).await;
+  publish_issue(&app, "Hidden issue", true).await;
+
+  let response = app.get_feed("feed.xml").await;
+
+  assert_eq!(response.status().as_u16(), 200);
+  assert_eq!(
+    response.headers().get("Content-Type").unwrap(),
+    "application/rss+xml; charset=utf-8"
+  );
+  let feed = response.text().await.unwrap();
+  assert!(feed.contains("<title>Feed issue</title>"));
+  assert!(feed.contains(&format!("/issues/{}</link>", slug)));
+  assert!(feed.contains("+0000</pubDate>"));
+  assert!(feed.contains("&lt;p&gt;Hi reader, here is the news&lt;/p&gt;"));
+  assert!(!feed.contains("Hidden issue"));
+}
+
+#[tokio::test]
+async fn atom_feed_lists_public_issues() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let (issue_id, _) = publish_issue(&app, "Feed issue", false).await;
+  publish_issue(&app, "Hidden issue", true).await;
+
+  let response = app.get_feed("atom.xml").await;
+
+  assert_eq!(response.status().as_u16(), 200);
+  assert_eq!(
+    response.headers().get("Content-Type").unwrap(),
+    "application/atom+xml; charset=utf-8"
+  );
+  let feed = response.text().await.unwrap();
+  assert!(feed.contains("<title>Feed issue</title>"));
+  assert!(feed.contains(&format!("<id>urn:uuid:{}</id>", issue_id)));
+  assert!(!feed.contains("Hidden issue"));
+}
+
+#[tokio::test]
+async fn paused_and_cancelled_issues_are_kept_out_of_the_archive_and_feeds() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let (paused_id, paused_slug) = publish_issue(&app, "Paused issue", false).await;
+  let (cancelled_id, cancelled_slug) = publish_issue(&app, "Cancelled issue", false).await;
+  app.post_issue_action(&paused_id.to_string(), "pause").await;
+  app.post_issue_action(&cancelled_id.to_string(), "cancel").await;
+
+  let html_page = app.get_issue_archive_html().await;
+  assert!(!html_page.contains("Paused issue"));
+  assert!(!html_page.contains("Cancelled issue"));
+  for feed in ["feed.xml", "atom.xml"] {
+    let feed = app.get_feed(feed).await.text().await.unwrap();
+    assert!(!feed.contains("Paused issue"));
+    assert!(!feed.contains("Cancelled issue"));
+  }
+  for path in [paused_id.to_string(), paused_slug, cancelled_id.to_string(), cancelled_slug] {
+    let response = app.get_issue_page(&path).await;
+    assert_eq!(response.status().as_u16(), 404);
+  }
+
+  app.post_issue_action(&paused_id.to_string(), "resume").await;
+  let html_page = app.get_issue_archive_html().await;
+  assert!(html_page.contains("Paused issue"));
+}
+
+#[tokio::test]
+async fn the_archive_is_split_into_pages() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  for i in 1..=51 {
+    publish_issue(&app, &format!("Issue number {}.", i), false).await;
+  }
+
+  let html_page = app.get_issue_archive_html().await;
+  assert!(html_page.contains("Issue number 51."));
+  assert!(html_page.contains("Issue number 2."));
+  assert!(!html_page.contains("Issue number 1."));
+  assert!(html_page.contains(r#"href="/issues?page=2">Older issues</a>"#));
+
+  let html_page = app.get_issue_archive_page_html(2).await;
+  assert!(html_page.contains("Issue number 1."));
+  assert!(!html_page.contains("Issue number 2."));
+  assert!(html_page.contains(r#"href="/issues?page=1">Newer issues</a>"#));
+  assert!(!html_page.contains("Older issues"));
+}
\ No newline at end of file
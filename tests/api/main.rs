@@ -6,4 +6,17 @@ mod newsletter;
 mod login;
 mod admin_dashboard;
 mod change_password;
-mod unsubscribe;
\ No newline at end of file
+mod account_email;
+mod unsubscribe;
+mod issues_archive;
+mod admin_issues;
+mod tracking;
+mod issue_analytics;
+mod subject_testing;
+mod assets;
+mod drafts;
+mod approvals;
+mod welcome_sequences;
+mod rate_limiting;
+mod maintenance;
+mod priority_lanes;
\ No newline at end of file
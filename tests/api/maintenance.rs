@@ -0,0 +1,56 @@
+use scoop::{authentication::create_user, issue_delivery_workers::queue_status};
+use secrecy::Secret;
+use serde_json::json;
+
+use crate::helpers::{spawn_app, assert_is_redirect_to};
+use crate::rate_limiting::publish_issue_to_three_subscribers;
+
+#[tokio::test]
+async fn admins_created_from_the_command_line_can_log_in() {
+  let app = spawn_app().await;
+  let username = uuid::Uuid::new_v4().to_string();
+  let password = uuid::Uuid::new_v4().to_string();
+
+  create_user(&username, Some("alice@example.com"), Secret::new(password.clone()), &app.db_pool)
+    .await
+    .unwrap();
+
+  let response = app.post_login(&json!({
+    "username": username,
+    "password": password,
+  })).await;
+  assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn queue_status_counts_queued_leased_and_deferred_emails() {
+  let app = spawn_app().await;
+  publish_issue_to_three_subscribers(&app).await;
+  sqlx::query!(
+    r#"
+    UPDATE newsletter_delivery_queue
+    SET locked_until = now() + interval '1 minute', locked_by = 'another-worker'
+    WHERE subscriber_email = (SELECT min(subscriber_email) FROM newsletter_delivery_queue)
+    "#
+  )
+  .execute(&app.db_pool)
+  .await
+  .unwrap();
+  sqlx::query!(
+    r#"
+    UPDATE newsletter_delivery_queue
+    SET next_attempt_at = now() + interval '1 minute'
+    WHERE subscriber_email = (SELECT max(subscriber_email) FROM newsletter_delivery_queue)
+    "#
+  )
+  .execute(&app.db_pool)
+  .await
+  .unwrap();
+
+  let status = queue_status(&app.db_pool).await.unwrap();
+
+  assert_eq!(status[0].queue, "newsletter_delivery_queue");
+  assert_eq!((status[0].queued, status[0].leased, status[0].deferred), (3, 1, 1));
+  assert_eq!(status[1].queue, "welcome_sequence_queue");
+  assert_eq!((status[1].queued, status[1].leased, status[1].deferred), (0, 0, 0));
+}
\ No newline at end of file
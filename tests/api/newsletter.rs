@@ -2,11 +2,11 @@ use std::time::Duration;
 
 use fake::{faker::{name::en::Name, internet::en::SafeEmail}, Fake};
 use serde_json::json;
-use wiremock::{Mock, matchers::{any, method, path}, ResponseTemplate, MockBuilder};
+use wiremock::{Mock, matchers::{any, method, path, query_param}, MockBuilder, Request, Respond, ResponseTemplate};
 
 use crate::helpers::{spawn_app, TestApp, ConfirmationLinks, assert_is_redirect_to};
 
-async fn create_uncomfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+pub async fn create_uncomfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
   let name: String = Name().fake();
   let email: String = SafeEmail().fake();
   let body = serde_urlencoded::to_string(&json!({
@@ -26,6 +26,7 @@ async fn create_uncomfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
     .await
     .error_for_status()
     .unwrap();
+  app.dispatch_transactional_emails().await;
 
   let email_request = &app.email_server
     .received_requests()
@@ -37,7 +38,7 @@ async fn create_uncomfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
   app.get_confirmation_links(email_request)
 }
 
-async fn create_comfirmed_subscriber(app: &TestApp) {
+pub async fn create_comfirmed_subscriber(app: &TestApp) {
   let confirmation_links = create_uncomfirmed_subscriber(app).await;
   reqwest::get(confirmation_links.html)
     .await
@@ -46,10 +47,37 @@ async fn create_comfirmed_subscriber(app: &TestApp) {
     .unwrap();
 }
 
-fn when_sending_an_email() -> MockBuilder {
+pub fn when_sending_an_email() -> MockBuilder {
   Mock::given(path("/email")).and(method("POST"))
 }
 
+pub fn when_sending_a_batch() -> MockBuilder {
+  Mock::given(path("/email/batch")).and(method("POST"))
+}
+
+/// The messages sent by a request to the mock email server, one for
+/// `/email` and several for `/email/batch`.
+pub fn messages_of(request: &Request) -> Vec<serde_json::Value> {
+  match serde_json::from_slice(&request.body).unwrap() {
+    serde_json::Value::Array(messages) => messages,
+    message => vec![message],
+  }
+}
+
+/// Answers a Postmark batch call by accepting every message in it.
+pub struct AcceptEveryMessage;
+
+impl Respond for AcceptEveryMessage {
+  fn respond(&self, request: &Request) -> ResponseTemplate {
+    let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    let results: Vec<_> = messages
+      .iter()
+      .map(|message| json!({"ErrorCode": 0, "Message": "OK", "To": message["To"]}))
+      .collect();
+    ResponseTemplate::new(200).set_body_json(results)
+  }
+}
+
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
   let app = spawn_app().await;
@@ -194,4 +222,614 @@ async fn concurrent_form_submission_is_handled_gracefully() {
   assert_eq!(response1.status(), response2.status());
   assert_eq!(response1.text().await.unwrap(), response2.text().await.unwrap());
   app.displatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn must_be_logged_in_to_preview_newsletters() {
+  let app = spawn_app().await;
+
+  let response = app.post_preview_newsletter(&json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+  })).await;
+
+  assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn preview_renders_merge_tags_with_sample_subscriber_data() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+
+  let response = app.post_preview_newsletter(&json!({
+    "title": "News for {{name}}",
+    "text_content": "Hi {{name}}, this was sent to {{email}}",
+    "html_content": "<p>Hi {{name}}</p>",
+  })).await;
+
+  assert_eq!(response.status().as_u16(), 200);
+  let html_page = response.text().await.unwrap();
+  assert!(html_page.contains("News for Ursula Le Guin"));
+  assert!(html_page.contains("Hi Ursula Le Guin, this was sent to ursula_le_guin@example.com"));
+  assert!(html_page.contains("&lt;p&gt;Hi Ursula Le Guin&lt;/p&gt;"));
+}
+
+#[tokio::test]
+async fn preview_uses_a_confirmed_subscriber_when_available() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  let subscriber = sqlx::query!("SELECT email FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+
+  let html_page = app.post_preview_newsletter(&json!({
+    "title": "Newsletter title",
+    "text_content": "Sent to {{email}}",
+    "html_content": "<p>Sent to {{email}}</p>",
+  }))
+  .await
+  .text()
+  .await
+  .unwrap();
+
+  assert!(html_page.contains(&format!("Sent to {}", subscriber.email)));
+}
+
+#[tokio::test]
+async fn test_issues_are_only_sent_to_the_logged_in_admin() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  let response = app.post_send_test_newsletter(&json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+  })).await;
+
+  assert_eq!(response.status().as_u16(), 200);
+  let html_page = response.text().await.unwrap();
+  assert!(html_page.contains(&format!("A test email is on its way to {}", app.test_user.email)));
+  app.dispatch_transactional_emails().await;
+
+  let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+  let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+  assert_eq!(body["To"], app.test_user.email.as_str());
+  assert_eq!(body["Subject"], "[Test] Newsletter title");
+
+  let queued = sqlx::query!("SELECT COUNT(*) as \"n!\" FROM newsletter_delivery_queue")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(queued.n, 0);
+}
+
+#[tokio::test]
+async fn newsletters_are_personalised_for_each_subscriber() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  let subscriber = sqlx::query!("SELECT name FROM subscriptions")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  let response = app.post_submit_newsletter(json!({
+    "title": "Newsletter title",
+    "text_content": "Hi {{name}}",
+    "html_content": "<p>Hi {{name}}</p>",
+    "idempotency_key": uuid::Uuid::new_v4().to_string(),
+  })).await;
+  assert_is_redirect_to(&response, "/admin/newsletters");
+  app.displatch_all_pending_emails().await;
+
+  let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+  let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+  assert_eq!(body["TextBody"], format!("Hi {}", subscriber.name));
+}
+
+#[tokio::test]
+async fn preheader_is_delivered_as_a_hidden_leading_element() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  let response = app.post_submit_newsletter(json!({
+    "title": "Newsletter title",
+    "preheader": "What's inside this week",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<html><body><p>Newsletter body as HTML</p></body></html>",
+    "idempotency_key": uuid::Uuid::new_v4().to_string(),
+    "disable_open_tracking": "true",
+  })).await;
+  assert_is_redirect_to(&response, "/admin/newsletters");
+  app.displatch_all_pending_emails().await;
+
+  let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+  let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+  let html = body["HtmlBody"].as_str().unwrap();
+  assert!(html.starts_with(r#"<html><body><div style="display:none;"#));
+  assert!(html.ends_with("What&#39;s inside this week</div><p>Newsletter body as HTML</p></body></html>"));
+}
+
+#[tokio::test]
+async fn preview_shows_the_preheader() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+
+  let response = app.post_preview_newsletter(&json!({
+    "title": "Newsletter title",
+    "preheader": "A preheader for {{name}}",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+  })).await;
+
+  let html_page = response.text().await.unwrap();
+  assert!(html_page.contains("<strong>Preheader:</strong> A preheader for Ursula Le Guin"));
+  assert!(html_page.contains(r#"name="preheader" value="A preheader for {{name}}""#));
+}
+
+#[tokio::test]
+async fn leased_deliveries_are_only_reclaimed_once_the_lease_expires() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  app.post_submit_newsletter(json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": uuid::Uuid::new_v4().to_string(),
+  })).await;
+  // A worker claimed the delivery, then crashed before sending it.
+  sqlx::query!(
+    "UPDATE newsletter_delivery_queue SET locked_by = 'crashed-worker', locked_until = now() + interval '1 hour'"
+  )
+  .execute(&app.db_pool)
+  .await
+  .unwrap();
+
+  {
+    let _mock_guard = when_sending_an_email()
+      .respond_with(ResponseTemplate::new(200))
+      .expect(0)
+      .mount_as_scoped(&app.email_server)
+      .await;
+    app.displatch_all_pending_emails().await;
+  }
+
+  sqlx::query!("UPDATE newsletter_delivery_queue SET locked_until = now() - interval '1 second'")
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  app.displatch_all_pending_emails().await;
+
+  let logged = sqlx::query!("SELECT outcome FROM newsletter_delivery_log")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(logged.outcome, "delivered");
+  let queued = sqlx::query!(r#"SELECT COUNT(*) as "n!" FROM newsletter_delivery_queue"#)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(queued.n, 0);
+}
+
+#[tokio::test]
+async fn a_batch_of_deliveries_is_sent_in_one_call() {
+  let app = spawn_app().await;
+  for _ in 0..5 {
+    create_comfirmed_subscriber(&app).await;
+  }
+  app.test_user.login(&app).await;
+  app.post_submit_newsletter(json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": uuid::Uuid::new_v4().to_string(),
+  })).await;
+
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+  when_sending_a_batch()
+    .respond_with(AcceptEveryMessage)
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  app.displatch_all_pending_emails().await;
+
+  let delivered = sqlx::query!(
+    r#"SELECT COUNT(*) as "n!" FROM newsletter_delivery_log WHERE outcome = 'delivered'"#
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap();
+  assert_eq!(delivered.n, 5);
+}
+
+#[tokio::test]
+async fn messages_rejected_within_a_batch_are_logged_as_failed() {
+  let app = spawn_app().await;
+  for _ in 0..2 {
+    create_comfirmed_subscriber(&app).await;
+  }
+  app.test_user.login(&app).await;
+  app.post_submit_newsletter(json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": uuid::Uuid::new_v4().to_string(),
+  })).await;
+
+  when_sending_a_batch()
+    .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+      {"ErrorCode": 0, "Message": "OK"},
+      {"ErrorCode": 406, "Message": "Address is inactive."},
+    ])))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  app.displatch_all_pending_emails().await;
+
+  let mut outcomes: Vec<_> = sqlx::query!("SELECT outcome FROM newsletter_delivery_log")
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.outcome)
+    .collect();
+  outcomes.sort();
+  assert_eq!(outcomes, ["delivered", "failed"]);
+}
+
+#[tokio::test]
+async fn idle_workers_are_woken_up_when_an_issue_is_published() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  let _workers = app.spawn_workers();
+  // Let the workers find the queue empty and go idle.
+  tokio::time::sleep(Duration::from_secs(1)).await;
+
+  app.post_submit_newsletter(json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": uuid::Uuid::new_v4().to_string(),
+  })).await;
+
+  // Well within the polling interval.
+  for _ in 0..30 {
+    let delivered = sqlx::query!(r#"SELECT COUNT(*) as "n!" FROM newsletter_delivery_log"#)
+      .fetch_one(&app.db_pool)
+      .await
+      .unwrap()
+      .n;
+    if delivered == 1 {
+      return;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+  }
+  panic!("The workers did not wake up for the new issue.");
+}
+
+#[tokio::test]
+async fn workers_finish_the_emails_they_are_sending_before_shutting_down() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(2)))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  app.post_submit_newsletter(json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": uuid::Uuid::new_v4().to_string(),
+  })).await;
+  let (shutdown_switch, workers) = app.spawn_workers();
+  // Shut down while the email is being sent.
+  tokio::time::sleep(Duration::from_millis(500)).await;
+
+  shutdown_switch.trigger();
+  tokio::time::timeout(Duration::from_secs(10), workers)
+    .await
+    .expect("The workers did not stop.")
+    .unwrap()
+    .unwrap();
+
+  let logged = sqlx::query!("SELECT outcome FROM newsletter_delivery_log")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(logged.outcome, "delivered");
+}
+
+#[tokio::test]
+async fn deliveries_of_workers_that_overrun_the_shutdown_timeout_are_released() {
+  let mut app = spawn_app().await;
+  app.configuration.application.shutdown_timeout_secs = 0;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+    .mount(&app.email_server)
+    .await;
+  app.post_submit_newsletter(json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": uuid::Uuid::new_v4().to_string(),
+  })).await;
+  let (shutdown_switch, workers) = app.spawn_workers();
+  tokio::time::sleep(Duration::from_millis(500)).await;
+
+  shutdown_switch.trigger();
+  tokio::time::timeout(Duration::from_secs(2), workers)
+    .await
+    .expect("The workers did not stop.")
+    .unwrap()
+    .unwrap();
+
+  let queued = sqlx::query!("SELECT locked_by, status FROM newsletter_delivery_queue")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(queued.locked_by, None);
+  assert_eq!(queued.status, "sending");
+}
+
+/// Publishes an issue to a confirmed subscriber whose delivery a worker was
+/// sending when it crashed.
+async fn interrupted_delivery(app: &TestApp) -> uuid::Uuid {
+  create_comfirmed_subscriber(app).await;
+  app.test_user.login(app).await;
+  app.post_submit_newsletter(json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": uuid::Uuid::new_v4().to_string(),
+  })).await;
+  sqlx::query!(
+    r#"
+    UPDATE newsletter_delivery_queue
+    SET status = 'sending', locked_by = 'crashed-worker', locked_until = now() - interval '1 second'
+    RETURNING delivery_id
+    "#
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap()
+  .delivery_id
+}
+
+fn when_looking_up_a_sent_message(delivery_id: uuid::Uuid) -> MockBuilder {
+  Mock::given(path("/messages/outbound"))
+    .and(method("GET"))
+    .and(query_param("metadata_delivery_reference", delivery_id.to_string()))
+}
+
+#[tokio::test]
+async fn deliveries_are_sent_with_their_delivery_id_as_metadata() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  app.post_submit_newsletter(json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": uuid::Uuid::new_v4().to_string(),
+  })).await;
+
+  app.displatch_all_pending_emails().await;
+
+  let logged = sqlx::query!("SELECT delivery_id FROM newsletter_delivery_log")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  let requests = app.email_server.received_requests().await.unwrap();
+  let message = &messages_of(requests.last().unwrap())[0];
+  assert_eq!(message["Metadata"]["delivery_reference"], logged.delivery_id.unwrap().to_string());
+}
+
+#[tokio::test]
+async fn interrupted_deliveries_that_reached_the_provider_are_not_sent_again() {
+  let app = spawn_app().await;
+  let delivery_id = interrupted_delivery(&app).await;
+  when_looking_up_a_sent_message(delivery_id)
+    .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+      "TotalCount": 1,
+      "Messages": [{"MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817"}],
+    })))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+
+  app.displatch_all_pending_emails().await;
+
+  let logged = sqlx::query!("SELECT outcome, delivery_id FROM newsletter_delivery_log")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(logged.outcome, "delivered");
+  assert_eq!(logged.delivery_id, Some(delivery_id));
+}
+
+#[tokio::test]
+async fn interrupted_deliveries_that_never_reached_the_provider_are_sent() {
+  let app = spawn_app().await;
+  let delivery_id = interrupted_delivery(&app).await;
+  when_looking_up_a_sent_message(delivery_id)
+    .respond_with(ResponseTemplate::new(200).set_body_json(json!({"TotalCount": 0, "Messages": []})))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  app.displatch_all_pending_emails().await;
+
+  let logged = sqlx::query!("SELECT outcome FROM newsletter_delivery_log")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(logged.outcome, "delivered");
+}
+
+#[tokio::test]
+async fn interrupted_deliveries_wait_when_the_provider_cannot_tell_if_they_were_sent() {
+  let app = spawn_app().await;
+  let delivery_id = interrupted_delivery(&app).await;
+  when_looking_up_a_sent_message(delivery_id)
+    .respond_with(ResponseTemplate::new(500))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+
+  app.displatch_all_pending_emails().await;
+
+  let queued = sqlx::query!(
+    r#"SELECT status, locked_by, next_attempt_at > now() as "retry_later!" FROM newsletter_delivery_queue"#
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap();
+  assert_eq!(queued.status, "sending");
+  assert_eq!(queued.locked_by, None);
+  assert!(queued.retry_later);
+}
+
+#[tokio::test]
+async fn deliveries_are_checked_with_the_provider_when_a_batch_call_fails_without_an_answer() {
+  let app = spawn_app().await;
+  for _ in 0..2 {
+    create_comfirmed_subscriber(&app).await;
+  }
+  app.test_user.login(&app).await;
+  app.post_submit_newsletter(json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": uuid::Uuid::new_v4().to_string(),
+  })).await;
+  // Postmark took the batch, but its answer cannot be read.
+  when_sending_a_batch()
+    .respond_with(ResponseTemplate::new(200).set_body_string("<html>Bad gateway</html>"))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  app.displatch_all_pending_emails().await;
+
+  let logged = sqlx::query!(r#"SELECT COUNT(*) as "n!" FROM newsletter_delivery_log"#)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(logged.n, 0);
+  let queued = sqlx::query!(
+    r#"SELECT status, locked_by, next_attempt_at > now() as "retry_later!" FROM newsletter_delivery_queue"#
+  )
+  .fetch_all(&app.db_pool)
+  .await
+  .unwrap();
+  assert_eq!(queued.len(), 2);
+  for row in queued {
+    assert_eq!(row.status, "sending");
+    assert_eq!(row.locked_by, None);
+    assert!(row.retry_later);
+  }
+}
+
+#[tokio::test]
+async fn deliveries_stay_queued_when_postmark_fails_their_batch() {
+  let app = spawn_app().await;
+  for _ in 0..2 {
+    create_comfirmed_subscriber(&app).await;
+  }
+  app.test_user.login(&app).await;
+  app.post_submit_newsletter(json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": uuid::Uuid::new_v4().to_string(),
+  })).await;
+  when_sending_a_batch()
+    .respond_with(ResponseTemplate::new(500))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  app.displatch_all_pending_emails().await;
+
+  let logged = sqlx::query!(r#"SELECT COUNT(*) as "n!" FROM newsletter_delivery_log"#)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(logged.n, 0);
+  let queued = sqlx::query!(
+    r#"SELECT status, locked_by, next_attempt_at > now() as "retry_later!" FROM newsletter_delivery_queue"#
+  )
+  .fetch_all(&app.db_pool)
+  .await
+  .unwrap();
+  assert_eq!(queued.len(), 2);
+  for row in queued {
+    assert_eq!(row.status, "queued");
+    assert_eq!(row.locked_by, None);
+    assert!(row.retry_later);
+  }
 }
\ No newline at end of file
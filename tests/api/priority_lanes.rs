@@ -0,0 +1,80 @@
+use scoop::priority_lanes::Lane;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{spawn_app, TestApp};
+use crate::newsletter::{messages_of, when_sending_a_batch, when_sending_an_email, AcceptEveryMessage};
+use crate::rate_limiting::publish_issue_to_three_subscribers;
+
+async fn queued_issue_deliveries(app: &TestApp) -> i64 {
+  sqlx::query!(r#"SELECT COUNT(*) as "n!" FROM newsletter_delivery_queue"#)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .n
+}
+
+async fn queued_transactional_emails(app: &TestApp) -> i64 {
+  sqlx::query!(r#"SELECT COUNT(*) as "n!" FROM transactional_email_queue"#)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .n
+}
+
+/// Queues an issue for three subscribers, then the confirmation email of a
+/// fourth one.
+async fn queue_bulk_and_transactional_emails(app: &TestApp) {
+  publish_issue_to_three_subscribers(app).await;
+  app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into()).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .mount(&app.email_server)
+    .await;
+  when_sending_a_batch()
+    .respond_with(AcceptEveryMessage)
+    .mount(&app.email_server)
+    .await;
+}
+
+#[tokio::test]
+async fn transactional_emails_go_out_before_bulk_deliveries() {
+  let app = spawn_app().await;
+  queue_bulk_and_transactional_emails(&app).await;
+  let sent_before = app.email_server.received_requests().await.unwrap().len();
+
+  app.run_worker_round(Lane::Transactional).await;
+
+  let requests = app.email_server.received_requests().await.unwrap();
+  assert_eq!(requests.len(), sent_before + 1);
+  let message = &messages_of(requests.last().unwrap())[0];
+  assert_eq!(message["To"], "ursula_le_guin@gmail.com");
+  assert_eq!(message["Subject"], "Welcome!");
+  assert_eq!(queued_transactional_emails(&app).await, 0);
+  assert_eq!(queued_issue_deliveries(&app).await, 3);
+}
+
+#[tokio::test]
+async fn bulk_deliveries_go_first_in_their_share_of_rounds() {
+  let app = spawn_app().await;
+  queue_bulk_and_transactional_emails(&app).await;
+
+  app.run_worker_round(Lane::Bulk).await;
+
+  assert_eq!(queued_issue_deliveries(&app).await, 0);
+  assert_eq!(queued_transactional_emails(&app).await, 1);
+}
+
+#[tokio::test]
+async fn a_lane_with_nothing_to_send_hands_the_round_over() {
+  let app = spawn_app().await;
+  app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into()).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  app.run_worker_round(Lane::Bulk).await;
+
+  assert_eq!(queued_transactional_emails(&app).await, 0);
+}
\ No newline at end of file
@@ -0,0 +1,83 @@
+use scoop::rate_limiter::RateLimiter;
+use serde_json::json;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{spawn_app, TestApp};
+use crate::newsletter::{create_comfirmed_subscriber, when_sending_an_email};
+
+pub async fn publish_issue_to_three_subscribers(app: &TestApp) {
+  for _ in 0..3 {
+    create_comfirmed_subscriber(app).await;
+  }
+  app.test_user.login(app).await;
+  app.post_submit_newsletter(json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": uuid::Uuid::new_v4().to_string(),
+  })).await;
+}
+
+struct QueuedDeliveries {
+  due: i64,
+  deferred: i64,
+  leased: i64,
+}
+
+async fn queued_deliveries(app: &TestApp) -> QueuedDeliveries {
+  sqlx::query_as!(
+    QueuedDeliveries,
+    r#"
+    SELECT
+      COUNT(*) FILTER (WHERE next_attempt_at <= now()) as "due!",
+      COUNT(*) FILTER (WHERE next_attempt_at > now()) as "deferred!",
+      COUNT(*) FILTER (WHERE locked_by IS NOT NULL) as "leased!"
+    FROM newsletter_delivery_queue
+    "#
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap()
+}
+
+#[tokio::test]
+async fn the_global_send_rate_holds_back_the_rest_of_the_queue() {
+  let mut app = spawn_app().await;
+  publish_issue_to_three_subscribers(&app).await;
+  app.rate_limiter = RateLimiter::new(Some(1.0), &[]);
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  app.displatch_all_pending_emails().await;
+
+  // Only as many rows as the rate allows are claimed, the rest stay due.
+  let queued = queued_deliveries(&app).await;
+  assert_eq!(queued.due, 2);
+  assert_eq!(queued.leased, 0);
+}
+
+#[tokio::test]
+async fn emails_to_a_throttled_domain_are_deferred() {
+  let mut app = spawn_app().await;
+  publish_issue_to_three_subscribers(&app).await;
+  app.rate_limiter = RateLimiter::new(None, &[("gmail.com".to_owned(), 1.0)]);
+  sqlx::query!("UPDATE newsletter_delivery_queue SET subscriber_email = replace(subscriber_email, '@', '.') || '@gmail.com'")
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  app.displatch_all_pending_emails().await;
+
+  let queued = queued_deliveries(&app).await;
+  assert_eq!(queued.due, 0);
+  assert_eq!(queued.deferred, 2);
+  assert_eq!(queued.leased, 0);
+}
@@ -0,0 +1,162 @@
+use scoop::issue_delivery_workers::pick_subject_test_winners;
+use serde_json::json;
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{spawn_app, assert_is_redirect_to, TestApp};
+use crate::newsletter::{
+  create_comfirmed_subscriber, messages_of, when_sending_a_batch, when_sending_an_email, AcceptEveryMessage,
+};
+use crate::tracking::attribute_url;
+
+fn newsletter_request_body(extra_fields: serde_json::Value) -> serde_json::Value {
+  let mut body = json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": "<p>Newsletter body as HTML</p>",
+    "idempotency_key": Uuid::new_v4().to_string(),
+  });
+  body.as_object_mut().unwrap().extend(extra_fields.as_object().unwrap().clone());
+  body
+}
+
+/// Returns the subject and HTML body of every email sent after the first
+/// `skip` requests received by the mock email server.
+async fn sent_emails(app: &TestApp, skip: usize) -> Vec<(String, String)> {
+  app.email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .iter()
+    .skip(skip)
+    .flat_map(messages_of)
+    .map(|body| {
+      (
+        body["Subject"].as_str().unwrap().to_owned(),
+        body["HtmlBody"].as_str().unwrap().to_owned(),
+      )
+    })
+    .collect()
+}
+
+#[tokio::test]
+async fn invalid_subject_tests_are_rejected() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+
+  let test_cases = vec![
+    (json!({"subject_variants": "Other subject", "ab_test_percentage": "0"}), "a 0% test audience"),
+    (json!({"subject_variants": "Other subject", "ab_test_window_minutes": "0"}), "an empty test window"),
+    (json!({"subject_variants": "Other subject", "ab_test_metric": "replies"}), "an unknown metric"),
+  ];
+  for (extra_fields, error_message) in test_cases {
+    let response = app.post_submit_newsletter(&newsletter_request_body(extra_fields)).await;
+    assert_eq!(
+      400,
+      response.status().as_u16(),
+      "The API did not fail with 400 Bad Request when the payload had {}.",
+      error_message
+    );
+  }
+}
+
+#[tokio::test]
+async fn the_rest_of_the_audience_receives_the_winning_subject() {
+  let app = spawn_app().await;
+  for _ in 0..4 {
+    create_comfirmed_subscriber(&app).await;
+  }
+  app.test_user.login(&app).await;
+  // The test sample and the rest of the audience each go out as a batch.
+  when_sending_a_batch()
+    .respond_with(AcceptEveryMessage)
+    .expect(2)
+    .mount(&app.email_server)
+    .await;
+  let confirmation_emails = app.email_server.received_requests().await.unwrap().len();
+
+  let response = app.post_submit_newsletter(&newsletter_request_body(json!({
+    "subject_variants": "Other subject",
+    "ab_test_percentage": "50",
+    "ab_test_metric": "opens",
+  }))).await;
+  assert_is_redirect_to(&response, "/admin/newsletters");
+
+  // Only the test sample is sent until the window closes.
+  app.displatch_all_pending_emails().await;
+  pick_subject_test_winners(&app.db_pool).await.unwrap();
+  app.displatch_all_pending_emails().await;
+  let mut subjects: Vec<_> = sent_emails(&app, confirmation_emails)
+    .await
+    .into_iter()
+    .map(|(subject, _)| subject)
+    .collect();
+  subjects.sort();
+  assert_eq!(subjects, ["Newsletter title", "Other subject"]);
+
+  let (_, html) = sent_emails(&app, confirmation_emails)
+    .await
+    .into_iter()
+    .find(|(subject, _)| subject == "Other subject")
+    .unwrap();
+  reqwest::get(attribute_url(&app, &html, "src")).await.unwrap();
+  sqlx::query!("UPDATE newsletter_issues SET ab_test_ends_at = now()")
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+  pick_subject_test_winners(&app.db_pool).await.unwrap();
+  app.displatch_all_pending_emails().await;
+
+  let subjects: Vec<_> = sent_emails(&app, confirmation_emails + 1)
+    .await
+    .into_iter()
+    .map(|(subject, _)| subject)
+    .collect();
+  assert_eq!(subjects, ["Other subject", "Other subject"]);
+
+  let issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .newsletter_issue_id
+    .to_string();
+  let analytics: serde_json::Value = app.get_issue_analytics(&issue_id, "analytics.json")
+    .await
+    .json()
+    .await
+    .unwrap();
+  assert_eq!(analytics["subject_variants"][1]["subject"], "Other subject");
+  assert_eq!(analytics["subject_variants"][1]["delivered"], 1);
+  assert_eq!(analytics["subject_variants"][1]["opened"], 1);
+  assert_eq!(analytics["subject_variants"][1]["winner"], true);
+  assert_eq!(analytics["subject_variants"][0]["winner"], false);
+}
+
+#[tokio::test]
+async fn ties_are_won_by_the_issue_title() {
+  let app = spawn_app().await;
+  for _ in 0..2 {
+    create_comfirmed_subscriber(&app).await;
+  }
+  app.test_user.login(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .mount(&app.email_server)
+    .await;
+  let confirmation_emails = app.email_server.received_requests().await.unwrap().len();
+
+  app.post_submit_newsletter(&newsletter_request_body(json!({
+    "subject_variants": "Other subject",
+    "ab_test_percentage": "1",
+  }))).await;
+  app.displatch_all_pending_emails().await;
+  sqlx::query!("UPDATE newsletter_issues SET ab_test_ends_at = now()")
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+  pick_subject_test_winners(&app.db_pool).await.unwrap();
+  app.displatch_all_pending_emails().await;
+
+  let (subject, _) = sent_emails(&app, confirmation_emails).await.pop().unwrap();
+  assert_eq!(subject, "Newsletter title");
+}
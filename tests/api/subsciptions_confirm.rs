@@ -23,6 +23,7 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
     .await;
 
   app.post_subscriptions(body.into()).await;
+  app.dispatch_transactional_emails().await;
 
   let email_request = &app.email_server.received_requests().await.unwrap()[0];
   let confirmation_links = app.get_confirmation_links(&email_request);
@@ -46,6 +47,7 @@ async fn confirmation_link_confirms_subscriber() {
     .await;
 
   app.post_subscriptions(body.into()).await;
+  app.dispatch_transactional_emails().await;
 
   let email_request = &app.email_server.received_requests().await.unwrap()[0];
   let confirmation_links = app.get_confirmation_links(&email_request);
@@ -6,14 +6,7 @@ use crate::helpers::{spawn_app, assert_is_redirect_to};
 async fn subscribe_redirects_for_valid_form_data() {
   let app = spawn_app().await;
   let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-  
-  Mock::given(path("/email"))
-      .and(method("POST"))
-      .respond_with(ResponseTemplate::new(200))
-      .expect(1)
-      .mount(&app.email_server)
-      .await;
-  
+
   let response = app.post_subscriptions(body.into()).await;
   assert_is_redirect_to(&response, "/subscriptions");
   let html_body = app.get_subscription_html().await;
@@ -24,14 +17,7 @@ async fn subscribe_redirects_for_valid_form_data() {
 async fn subscribe_persists_new_subscriber() {
   let app = spawn_app().await;
   let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-  
-  Mock::given(path("/email"))
-      .and(method("POST"))
-      .respond_with(ResponseTemplate::new(200))
-      .expect(1)
-      .mount(&app.email_server)
-      .await;
-  
+
   let response = app.post_subscriptions(body.into()).await;
   assert_is_redirect_to(&response, "/subscriptions");
   let html_body = app.get_subscription_html().await;
@@ -99,6 +85,7 @@ async fn subscribe_sends_confirmation_email_for_valid_data() {
       .await;
   
   app.post_subscriptions(body.into()).await;
+  app.dispatch_transactional_emails().await;
 }
 
 #[tokio::test]
@@ -114,9 +101,73 @@ async fn subscribe_sends_confirmation_email_with_link() {
       .await;
   
   app.post_subscriptions(body.into()).await;
+  app.dispatch_transactional_emails().await;
 
   let email_request = &app.email_server.received_requests().await.unwrap()[0];
   let confirmation_links = app.get_confirmation_links(&email_request);
 
   assert_eq!(confirmation_links.html, confirmation_links.plain_text);
+}
+
+#[tokio::test]
+async fn subscribe_does_not_wait_on_the_email_provider() {
+  let app = spawn_app().await;
+  let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+  Mock::given(path("/email"))
+      .and(method("POST"))
+      .respond_with(ResponseTemplate::new(500))
+      .expect(1)
+      .mount(&app.email_server)
+      .await;
+
+  let response = app.post_subscriptions(body.into()).await;
+  assert_is_redirect_to(&response, "/subscriptions");
+  assert!(app.email_server.received_requests().await.unwrap().is_empty());
+
+  app.dispatch_transactional_emails().await;
+  let queued = sqlx::query!(
+    "SELECT recipient, n_attempts, last_error, next_attempt_at > now() AS retry_later FROM transactional_email_queue"
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap();
+  assert_eq!(queued.recipient, "ursula_le_guin@gmail.com");
+  assert_eq!(queued.n_attempts, 1);
+  assert!(queued.last_error.is_some());
+  assert_eq!(queued.retry_later, Some(true));
+}
+
+#[tokio::test]
+async fn failed_confirmation_emails_are_retried() {
+  let app = spawn_app().await;
+  let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+  Mock::given(path("/email"))
+      .and(method("POST"))
+      .respond_with(ResponseTemplate::new(500))
+      .up_to_n_times(1)
+      .expect(1)
+      .mount(&app.email_server)
+      .await;
+  Mock::given(path("/email"))
+      .and(method("POST"))
+      .respond_with(ResponseTemplate::new(200))
+      .expect(1)
+      .mount(&app.email_server)
+      .await;
+
+  app.post_subscriptions(body.into()).await;
+  app.dispatch_transactional_emails().await;
+  sqlx::query!("UPDATE transactional_email_queue SET next_attempt_at = now()")
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+  app.dispatch_transactional_emails().await;
+
+  let queued = sqlx::query!("SELECT email_id FROM transactional_email_queue")
+    .fetch_optional(&app.db_pool)
+    .await
+    .unwrap();
+  assert!(queued.is_none());
 }
\ No newline at end of file
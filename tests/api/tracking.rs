@@ -0,0 +1,186 @@
+use serde_json::json;
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{spawn_app, assert_is_redirect_to, TestApp};
+use crate::newsletter::{create_comfirmed_subscriber, messages_of, when_sending_an_email};
+
+pub async fn publish_and_deliver_issue(
+  app: &TestApp,
+  html_content: &str,
+  extra_fields: serde_json::Value,
+) -> String {
+  let mut body = json!({
+    "title": "Newsletter title",
+    "text_content": "Newsletter body as plaintext",
+    "html_content": html_content,
+    "idempotency_key": Uuid::new_v4().to_string(),
+  });
+  body.as_object_mut().unwrap().extend(extra_fields.as_object().unwrap().clone());
+  let response = app.post_submit_newsletter(&body).await;
+  assert_is_redirect_to(&response, "/admin/newsletters");
+  app.displatch_all_pending_emails().await;
+
+  let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+  let body = messages_of(&email_request).pop().unwrap();
+  body["HtmlBody"].as_str().unwrap().to_owned()
+}
+
+pub const HTML_CONTENT: &str = "<html><body><p>Newsletter body as HTML</p></body></html>";
+pub const LINK_HTML_CONTENT: &str = r#"<p>Read <a href="https://example.com/post?id=1&amp;ref=x">this</a></p>"#;
+
+pub fn attribute_url(app: &TestApp, html: &str, attribute: &str) -> reqwest::Url {
+  let prefix = format!("{}=\"", attribute);
+  let start = html.find(&prefix).unwrap() + prefix.len();
+  let end = start + html[start..].find('"').unwrap();
+  let link = html[start..end].replace("&amp;", "&");
+  let mut url = reqwest::Url::parse(&link).unwrap();
+  assert_eq!(url.host_str().unwrap(), "127.0.0.1");
+  url.set_port(Some(app.port)).unwrap();
+  url
+}
+
+#[tokio::test]
+async fn delivered_issues_contain_a_tracking_pixel_that_records_opens() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .mount(&app.email_server)
+    .await;
+
+  let html = publish_and_deliver_issue(&app, HTML_CONTENT, json!({})).await;
+  assert!(html.ends_with("</body></html>"));
+  let pixel_url = attribute_url(&app, &html, "src");
+  assert!(pixel_url.path().starts_with("/t/o/"));
+
+  for _ in 0..2 {
+    let response = reqwest::get(pixel_url.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers().get("Content-Type").unwrap(), "image/gif");
+  }
+
+  let log = sqlx::query!(
+    "SELECT first_opened_at, open_count FROM newsletter_delivery_log"
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap();
+  assert!(log.first_opened_at.is_some());
+  assert_eq!(log.open_count, 2);
+}
+
+#[tokio::test]
+async fn open_tracking_can_be_disabled_per_issue() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .mount(&app.email_server)
+    .await;
+
+  let html = publish_and_deliver_issue(&app, HTML_CONTENT, json!({"disable_open_tracking": "true"})).await;
+
+  assert_eq!(html, HTML_CONTENT);
+}
+
+#[tokio::test]
+async fn unknown_tracking_tokens_still_get_a_pixel() {
+  let app = spawn_app().await;
+
+  let response = reqwest::get(format!("{}/t/o/unknown-token.gif", app.address))
+    .await
+    .unwrap();
+
+  assert_eq!(response.status().as_u16(), 200);
+  assert_eq!(response.headers().get("Content-Type").unwrap(), "image/gif");
+}
+
+#[tokio::test]
+async fn clicks_on_rewritten_links_are_recorded_and_redirected() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .mount(&app.email_server)
+    .await;
+
+  let html = publish_and_deliver_issue(&app, LINK_HTML_CONTENT, json!({})).await;
+  let click_url = attribute_url(&app, &html, "href");
+  assert!(click_url.path().starts_with("/t/c/"));
+
+  let response = app.api_client.get(click_url).send().await.unwrap();
+
+  assert_eq!(response.status().as_u16(), 302);
+  assert_eq!(
+    response.headers().get("Location").unwrap(),
+    "https://example.com/post?id=1&ref=x"
+  );
+  let click = sqlx::query!(
+    r#"
+    SELECT newsletter_link_clicks.url
+    FROM newsletter_link_clicks
+    JOIN subscriptions ON subscriptions.email = newsletter_link_clicks.subscriber_email
+    JOIN newsletter_issues USING (newsletter_issue_id)
+    "#
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap();
+  assert_eq!(click.url, "https://example.com/post?id=1&ref=x");
+}
+
+#[tokio::test]
+async fn click_urls_with_a_tampered_destination_are_rejected() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .mount(&app.email_server)
+    .await;
+
+  let html = publish_and_deliver_issue(&app, LINK_HTML_CONTENT, json!({})).await;
+  let mut click_url = attribute_url(&app, &html, "href");
+  let sig = click_url.query_pairs().find(|(k, _)| k == "sig").unwrap().1.into_owned();
+  click_url.query_pairs_mut()
+    .clear()
+    .append_pair("url", "https://evil.example.com")
+    .append_pair("sig", &sig);
+
+  let response = app.api_client.get(click_url).send().await.unwrap();
+
+  assert_eq!(response.status().as_u16(), 400);
+  let clicks = sqlx::query!(r#"SELECT COUNT(*) as "n!" FROM newsletter_link_clicks"#)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(clicks.n, 0);
+}
+
+#[tokio::test]
+async fn click_tracking_can_be_disabled_per_issue() {
+  let app = spawn_app().await;
+  create_comfirmed_subscriber(&app).await;
+  app.test_user.login(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .mount(&app.email_server)
+    .await;
+
+  let html = publish_and_deliver_issue(
+    &app,
+    LINK_HTML_CONTENT,
+    json!({"disable_click_tracking": "true", "disable_open_tracking": "true"})
+  ).await;
+
+  assert_eq!(html, LINK_HTML_CONTENT);
+  let log = sqlx::query!("SELECT tracking_token FROM newsletter_delivery_log")
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert!(log.tracking_token.is_none());
+}
@@ -23,6 +23,7 @@ async fn the_unsub_link_returned_by_subscribe_returns_a_200_if_called() {
     .await;
 
   app.post_subscriptions(body.into()).await;
+  app.dispatch_transactional_emails().await;
 
   let email_request = &app.email_server.received_requests().await.unwrap()[0];
   let unsubscription_link = app.get_unsubscription_link(&email_request);
@@ -47,6 +48,7 @@ async fn unsub_link_removes_subscriber() {
     .await;
 
   app.post_subscriptions(body.into()).await;
+  app.dispatch_transactional_emails().await;
 
   let email_request = &app.email_server.received_requests().await.unwrap()[0];
   let unsubscription_link = app.get_unsubscription_link(&email_request);
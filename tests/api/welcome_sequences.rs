@@ -0,0 +1,228 @@
+use serde_json::json;
+use uuid::Uuid;
+use wiremock::ResponseTemplate;
+
+use crate::helpers::{spawn_app, assert_is_redirect_to, TestApp};
+use crate::newsletter::{create_comfirmed_subscriber, create_uncomfirmed_subscriber, when_sending_an_email};
+
+/// Creates a sequence with one email per delay and returns its id.
+async fn create_sequence(app: &TestApp, delays: &[i32]) -> String {
+  let response = app.post_create_welcome_sequence(&json!({"name": "Onboarding"})).await;
+  let sequence_id = response.headers()["Location"]
+    .to_str()
+    .unwrap()
+    .strip_prefix("/admin/sequences/")
+    .expect("Creating a sequence did not redirect to it.")
+    .to_owned();
+
+  for delay in delays {
+    let response = app.post_sequence_step(&sequence_id, &json!({
+      "delay_days": delay,
+      "subject": format!("Day {}", delay),
+      "text_content": "Welcome, {{name}}!",
+      "html_content": "<p>Welcome, {{name}}!</p>",
+    })).await;
+    assert_is_redirect_to(&response, &format!("/admin/sequences/{}", sequence_id));
+  }
+  sequence_id
+}
+
+/// Returns the subjects of the emails received after the first `skip`.
+async fn sent_subjects(app: &TestApp, skip: usize) -> Vec<String> {
+  app.email_server
+    .received_requests()
+    .await
+    .unwrap()
+    .iter()
+    .skip(skip)
+    .map(|r| {
+      let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+      body["Subject"].as_str().unwrap().to_owned()
+    })
+    .collect()
+}
+
+/// Moves every enrollment `days` into the past.
+async fn travel_forward(app: &TestApp, days: i32) {
+  sqlx::query!(
+    "UPDATE welcome_sequence_enrollments SET enrolled_at = enrolled_at - make_interval(days => $1)",
+    days,
+  )
+  .execute(&app.db_pool)
+  .await
+  .unwrap();
+}
+
+#[tokio::test]
+async fn must_be_logged_in_to_manage_welcome_sequences() {
+  let app = spawn_app().await;
+
+  let response = app.post_create_welcome_sequence(&json!({"name": "Onboarding"})).await;
+
+  assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn invalid_steps_are_rejected() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let sequence_id = create_sequence(&app, &[]).await;
+
+  app.post_sequence_step(&sequence_id, &json!({
+    "delay_days": -1,
+    "subject": "Too early",
+    "text_content": "",
+    "html_content": "",
+  })).await;
+
+  let html_page = app.get_welcome_sequence_html(&sequence_id).await;
+  assert!(html_page.contains("Emails cannot be sent before the subscriber confirms."));
+  assert!(html_page.contains("This sequence has no emails yet."));
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_receive_each_step_when_it_falls_due() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let sequence_id = create_sequence(&app, &[0, 3, 7]).await;
+  create_comfirmed_subscriber(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(3)
+    .mount(&app.email_server)
+    .await;
+  let confirmation_emails = app.email_server.received_requests().await.unwrap().len();
+
+  app.dispatch_welcome_sequence_emails().await;
+  assert_eq!(sent_subjects(&app, confirmation_emails).await, ["Day 0"]);
+
+  travel_forward(&app, 3).await;
+  app.dispatch_welcome_sequence_emails().await;
+  // Nothing is sent twice.
+  app.dispatch_welcome_sequence_emails().await;
+  assert_eq!(sent_subjects(&app, confirmation_emails).await, ["Day 0", "Day 3"]);
+
+  travel_forward(&app, 4).await;
+  app.dispatch_welcome_sequence_emails().await;
+  assert_eq!(sent_subjects(&app, confirmation_emails).await, ["Day 0", "Day 3", "Day 7"]);
+
+  let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+  let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+  assert!(!body["HtmlBody"].as_str().unwrap().contains("{{name}}"));
+
+  let html_page = app.get_welcome_sequence_html(&sequence_id).await;
+  assert!(html_page.contains("1 subscribers enrolled"));
+}
+
+#[tokio::test]
+async fn unconfirmed_subscribers_are_not_enrolled() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  create_sequence(&app, &[0]).await;
+  create_uncomfirmed_subscriber(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(0)
+    .mount(&app.email_server)
+    .await;
+
+  app.dispatch_welcome_sequence_emails().await;
+}
+
+#[tokio::test]
+async fn unsubscribing_stops_the_sequence() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  create_sequence(&app, &[0, 3]).await;
+  let confirmation_links = create_uncomfirmed_subscriber(&app).await;
+  reqwest::get(confirmation_links.html.clone()).await.unwrap().error_for_status().unwrap();
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  app.dispatch_welcome_sequence_emails().await;
+
+  let mut unsubscription_link = confirmation_links.html;
+  unsubscription_link.set_path("/unsubscribe");
+  reqwest::get(unsubscription_link).await.unwrap().error_for_status().unwrap();
+  travel_forward(&app, 3).await;
+  app.dispatch_welcome_sequence_emails().await;
+
+  let enrollments = sqlx::query!(r#"SELECT COUNT(*) as "count!" FROM welcome_sequence_enrollments"#)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .count;
+  assert_eq!(enrollments, 0);
+}
+
+#[tokio::test]
+async fn paused_sequences_neither_enroll_nor_send() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  let sequence_id = create_sequence(&app, &[0]).await;
+  create_comfirmed_subscriber(&app).await;
+  let response = app.post_sequence_action(&sequence_id, "pause").await;
+  assert_is_redirect_to(&response, &format!("/admin/sequences/{}", sequence_id));
+  create_comfirmed_subscriber(&app).await;
+  {
+    let _mock_guard = when_sending_an_email()
+      .respond_with(ResponseTemplate::new(200))
+      .expect(0)
+      .mount_as_scoped(&app.email_server)
+      .await;
+    app.dispatch_welcome_sequence_emails().await;
+  }
+
+  // Only the subscriber who confirmed before the pause is enrolled.
+  app.post_sequence_action(&sequence_id, "resume").await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(200))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+  app.dispatch_welcome_sequence_emails().await;
+
+  let html_page = app.get_welcome_sequence_html(&sequence_id).await;
+  assert!(html_page.contains("Status:</strong> Active"));
+}
+
+#[tokio::test]
+async fn unknown_sequences_return_a_404() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+
+  let response = app.post_sequence_action(&Uuid::new_v4().to_string(), "pause").await;
+
+  assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn welcome_emails_postmark_fails_to_take_are_retried_later() {
+  let app = spawn_app().await;
+  app.test_user.login(&app).await;
+  create_sequence(&app, &[0]).await;
+  create_comfirmed_subscriber(&app).await;
+  when_sending_an_email()
+    .respond_with(ResponseTemplate::new(503))
+    .expect(1)
+    .mount(&app.email_server)
+    .await;
+
+  app.dispatch_welcome_sequence_emails().await;
+
+  let logged = sqlx::query!(r#"SELECT COUNT(*) as "n!" FROM welcome_sequence_log"#)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+  assert_eq!(logged.n, 0);
+  let queued = sqlx::query!(
+    r#"SELECT locked_by, next_attempt_at > now() as "retry_later!" FROM welcome_sequence_queue"#
+  )
+  .fetch_one(&app.db_pool)
+  .await
+  .unwrap();
+  assert_eq!(queued.locked_by, None);
+  assert!(queued.retry_later);
+}